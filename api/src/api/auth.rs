@@ -0,0 +1,128 @@
+use std::marker::PhantomData;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+};
+use axum_extra::extract::CookieJar;
+
+use crate::{
+    session::SessionId,
+    token::Scope,
+    user::{self, Session},
+};
+
+use super::state::AppState;
+
+/// Scope required by a privileged endpoint.
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+/// Marker of the `session:read` scope.
+pub struct SessionRead;
+
+impl RequiredScope for SessionRead {
+    const SCOPE: Scope = Scope::SessionRead;
+}
+
+/// Marker of the `admin:invite` scope.
+pub struct AdminInvite;
+
+impl RequiredScope for AdminInvite {
+    const SCOPE: Scope = Scope::AdminInvite;
+}
+
+/// Request authenticated by a service token with the required scope.
+pub struct Authorized<S> {
+    /// Name of the service token.
+    pub token: String,
+    scope: PhantomData<S>,
+}
+
+#[async_trait]
+impl<S: RequiredScope> FromRequestParts<AppState> for Authorized<S> {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let path = parts.uri.path();
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| {
+                tracing::warn!("missing service token for {path}");
+                StatusCode::UNAUTHORIZED
+            })?;
+
+        let service_token = state.service_tokens().authenticate(token).ok_or_else(|| {
+            tracing::warn!("invalid service token for {path}");
+            StatusCode::UNAUTHORIZED
+        })?;
+        let name = service_token.name();
+
+        if !service_token.has_scope(S::SCOPE) {
+            tracing::warn!(
+                "service token '{name}' without scope {} for {path}",
+                S::SCOPE
+            );
+            return Err(StatusCode::FORBIDDEN);
+        }
+        tracing::info!(
+            "service token '{name}' authorized with scope {} for {path}",
+            S::SCOPE
+        );
+
+        Ok(Self {
+            token: name.to_string(),
+            scope: PhantomData,
+        })
+    }
+}
+
+/// Request of a user who has just signed in, required by the sensitive
+/// operations: the user has to sign in again when the session is older.
+pub struct Reauthenticated {
+    /// Username of the signed in user.
+    pub username: String,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Reauthenticated {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let session = session_from_cookie(parts, state)?;
+        if !session.is_recent() {
+            tracing::warn!("user {} has to sign in again", session.username);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(Self {
+            username: session.username,
+        })
+    }
+}
+
+/// Retrieve the session of the cookie.
+fn session_from_cookie(parts: &Parts, state: &AppState) -> Result<Session, StatusCode> {
+    let jar = CookieJar::from_headers(&parts.headers);
+    let session_id: SessionId = jar
+        .get(Session::COOKIE)
+        .and_then(|cookie| cookie.value().parse().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    user::get_session(state.storage(), session_id)
+        .map_err(|err| {
+            tracing::error!("failed to search session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
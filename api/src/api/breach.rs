@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use fresh_auth_suite::breach::BreachFilter;
+use sha2::{Digest, Sha256};
+
+use super::state::AppState;
+
+/// Breached password filter, as served to the clients.
+pub struct FilterFile {
+    bytes: Bytes,
+    etag: HeaderValue,
+}
+
+impl FilterFile {
+    /// Load and validate the filter.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read filter {}", path.display()))?;
+        BreachFilter::from_bytes(&bytes)?;
+
+        let digest = Sha256::digest(&bytes);
+        let etag = format!("\"{}\"", Base64UrlUnpadded::encode_string(&digest));
+        Ok(Self {
+            bytes: Bytes::from(bytes),
+            etag: HeaderValue::from_str(&etag)?,
+        })
+    }
+}
+
+/// Returns the breached password filter, if configured.
+pub async fn filter(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(filter) = state.breach_filter() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let headers_res = [
+        (
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        ),
+        (
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=86400"),
+        ),
+        (ETAG, filter.etag.clone()),
+    ];
+    if headers.get(IF_NONE_MATCH) == Some(&filter.etag) {
+        return (StatusCode::NOT_MODIFIED, headers_res).into_response();
+    }
+    (headers_res, filter.bytes.clone()).into_response()
+}
@@ -0,0 +1,270 @@
+use std::net::IpAddr;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+
+use crate::{
+    invitation::Invitation,
+    mailer::{self, Mail},
+    session::SessionId,
+    throttle::Throttle,
+    time::Duration,
+    user::{self, UserTable},
+};
+
+use super::{auth::Reauthenticated, extract::ClientAddress, state::AppState};
+
+/// Throttle of the password reset requests, each username and each address
+/// can request 3 and 10 links every hour.
+pub fn reset_throttle() -> Throttle {
+    Throttle::new(3, 10, Duration::hours(1))
+}
+
+#[derive(Deserialize)]
+pub struct EmailReq {
+    email: String,
+}
+
+/// Change the email address of the user who has just signed in.
+///
+/// The address is saved only when confirmed with the link sent to it, so the
+/// reset links cannot be diverted to an address not owned by the user.
+pub async fn set_email(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+    Json(req): Json<EmailReq>,
+) -> Result<Json<()>, StatusCode> {
+    let email = req.email.trim();
+    if !mailer::is_valid_address(email) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if state.mailer().is_none() {
+        tracing::warn!("user {username} cannot confirm email address, mailer not configured");
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    let confirmation = user::EmailConfirmation::new(username.clone(), email.to_string());
+    let token = user::push_email_confirmation(state.storage(), confirmation).map_err(|err| {
+        tracing::error!("failed to push email confirmation: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mail = Mail::ConfirmEmail {
+        username: &username,
+        token: &token,
+    };
+    if send_to(&state, email, mail) {
+        tracing::info!("confirmation link sent to the new email address of user {username}");
+    }
+
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailReq {
+    token: SessionId,
+}
+
+/// Save the email address confirmed with the link, the link can be used only
+/// once.
+pub async fn confirm_email(
+    State(state): State<AppState>,
+    Json(req): Json<ConfirmEmailReq>,
+) -> Result<Json<()>, StatusCode> {
+    let user::EmailConfirmation {
+        username, email, ..
+    } = user::pull_email_confirmation(state.storage(), req.token)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve email confirmation: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    user::set_email(state.storage(), &username, &email).map_err(|err| {
+        tracing::error!("failed to save email address: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    tracing::info!("user {username} changed email address");
+
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct ResetReq {
+    username: String,
+}
+
+/// Send the password reset link to the email address of the user.
+///
+/// The response is always the same and the link is sent in the background,
+/// so the registered users cannot be enumerated. The requests are throttled
+/// by username and by client address.
+pub async fn reset(
+    ClientAddress(address): ClientAddress,
+    State(state): State<AppState>,
+    Json(req): Json<ResetReq>,
+) -> Result<Json<()>, StatusCode> {
+    let result = request_reset(address, state.clone(), req);
+    state.metrics().password_reset(&result);
+    result.map(Json)
+}
+
+fn request_reset(address: IpAddr, state: AppState, req: ResetReq) -> Result<(), StatusCode> {
+    let ResetReq { username } = req;
+
+    if !state.reset_throttle().check(&username, address) {
+        tracing::warn!("password reset of user {username} from {address} throttled");
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    tokio::spawn(async move {
+        let is_registered = match state.storage().user_is_registered(&username) {
+            Ok(is_registered) => is_registered,
+            Err(err) => {
+                tracing::error!("failed to check user {username}: {err}");
+                return;
+            }
+        };
+        if !is_registered {
+            tracing::warn!("password reset requested for unknown user {username}");
+            return;
+        }
+
+        let invitation = Invitation::reset(&username);
+        let code = state.invitation_keys().sign(&invitation);
+        let mail = Mail::Reset {
+            username: &username,
+            code: &code,
+        };
+        if send(&state, &username, mail) {
+            tracing::info!("password reset link sent to user {username}");
+        }
+    });
+
+    Ok(())
+}
+
+/// Send the email to the user in the background, returns `true` if queued.
+///
+/// Failures are only logged: the emails are a courtesy, the requests must
+/// not fail or wait because of them.
+pub fn send(state: &AppState, username: &str, mail: Mail<'_>) -> bool {
+    if state.mailer().is_none() {
+        return false;
+    }
+    let email = match user::get_email(state.storage(), username) {
+        Ok(Some(email)) => email,
+        Ok(None) => {
+            tracing::warn!("user {username} has no email address");
+            return false;
+        }
+        Err(err) => {
+            tracing::error!("failed to retrieve email address: {err}");
+            return false;
+        }
+    };
+    send_to(state, &email, mail)
+}
+
+/// Send the email to the address in the background, returns `true` if
+/// queued.
+fn send_to(state: &AppState, address: &str, mail: Mail<'_>) -> bool {
+    let Some(mailer) = state.mailer() else {
+        return false;
+    };
+    let message = match mailer.message(address, mail) {
+        Ok(message) => message,
+        Err(err) => {
+            tracing::error!("failed to compose email: {err}");
+            return false;
+        }
+    };
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let Some(mailer) = state.mailer() else {
+            return;
+        };
+        if let Err(err) = mailer.send(message).await {
+            tracing::error!("failed to send email: {err}");
+        }
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use serde_json::json;
+
+    use super::*;
+
+    use claym::*;
+
+    use crate::api::testing;
+
+    fn email_req(email: &str) -> Json<EmailReq> {
+        Json(testing::request(json!({ "email": email })))
+    }
+
+    #[tokio::test]
+    async fn keep_email_until_confirmed() {
+        let state = testing::state_with_mailer();
+        let signed_in = Reauthenticated {
+            username: "user".to_string(),
+        };
+        assert_ok!(
+            set_email(
+                signed_in,
+                State(state.clone()),
+                email_req("user@example.com")
+            )
+            .await
+        );
+        assert_none!(assert_ok!(user::get_email(state.storage(), "user")));
+    }
+
+    #[tokio::test]
+    async fn refuse_email_without_mailer() {
+        let state = testing::state();
+        let signed_in = Reauthenticated {
+            username: "user".to_string(),
+        };
+        let status =
+            assert_err!(set_email(signed_in, State(state), email_req("user@example.com")).await);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn save_confirmed_email_once() {
+        let state = testing::state_with_mailer();
+        let confirmation =
+            user::EmailConfirmation::new("user".to_string(), "user@example.com".to_string());
+        let token = assert_ok!(user::push_email_confirmation(state.storage(), confirmation));
+        let req = json!({ "token": token.display().to_string() });
+
+        let confirm = Json(testing::request(req.clone()));
+        assert_ok!(confirm_email(State(state.clone()), confirm).await);
+        let email = assert_ok!(user::get_email(state.storage(), "user"));
+        assert_eq!(assert_some!(email), "user@example.com");
+
+        let confirm = Json(testing::request(req));
+        let status = assert_err!(confirm_email(State(state), confirm).await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn throttle_password_resets() {
+        let state = testing::state();
+        let address = ClientAddress(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        for _ in 0..3 {
+            let req = Json(testing::request(json!({ "username": "user" })));
+            let address = ClientAddress(address.0);
+            assert_ok!(reset(address, State(state.clone()), req).await);
+        }
+
+        let req = Json(testing::request(json!({ "username": "user" })));
+        let status = assert_err!(reset(address, State(state), req).await);
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
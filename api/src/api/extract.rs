@@ -0,0 +1,79 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, ConnectInfo, FromRequest, FromRequestParts, Request},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+/// Header with the address of the client, set by the reverse proxy.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// JSON body carrying OPAQUE messages.
+///
+/// Unlike [`Json`], a body that cannot be deserialized (for example a message
+/// with a different protocol version or cipher suite) is rejected with
+/// `400 Bad Request` and the description of the error, so that outdated
+/// clients fail with a clear reason.
+pub struct OpaqueJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for OpaqueJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(JsonRejection::JsonDataError(err)) => {
+                let body = err.body_text();
+                tracing::warn!("rejected OPAQUE message: {body}");
+                Err((StatusCode::BAD_REQUEST, body).into_response())
+            }
+            Err(rejection) => Err(rejection.into_response()),
+        }
+    }
+}
+
+/// Address of the client.
+///
+/// Behind a reverse proxy on the same host the address is the last one of
+/// the `X-Forwarded-For` header, the one added by the proxy; otherwise the
+/// header is ignored, since the client can set it, and the address of the
+/// connection is used.
+pub struct ClientAddress(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientAddress
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            tracing::error!("missing address of the connection");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        let peer = peer.ip();
+        if !peer.is_loopback() {
+            return Ok(Self(peer));
+        }
+
+        let forwarded = parts
+            .headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .last()
+            .and_then(|address| address.trim().parse().ok());
+        Ok(Self(forwarded.unwrap_or(peer)))
+    }
+}
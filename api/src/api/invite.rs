@@ -0,0 +1,75 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    invitation::{Invitation, InvitationCode},
+    mailer::{self, Mail},
+    user::{self, UserTable},
+};
+
+use super::{
+    auth::{AdminInvite, Authorized},
+    email,
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct InviteReq {
+    username: String,
+    /// Email address of the user, the invitation is sent to it.
+    #[serde(default)]
+    email: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct InviteRes {
+    code: InvitationCode,
+    /// The invitation has been queued to be sent by email.
+    sent: bool,
+}
+
+/// Generate the invitation code for a new user.
+pub async fn invite(
+    auth: Authorized<AdminInvite>,
+    State(state): State<AppState>,
+    Json(req): Json<InviteReq>,
+) -> Result<Json<InviteRes>, StatusCode> {
+    let InviteReq {
+        username,
+        email: address,
+    } = req;
+    let address = address.as_deref().map(str::trim);
+    if address.is_some_and(|address| !mailer::is_valid_address(address)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let is_registered = state
+        .storage()
+        .user_is_registered(&username)
+        .map_err(|err| {
+            tracing::error!("failed to check user {username}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if is_registered {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let invitation = Invitation::new(&username);
+    let code = state.invitation_keys().sign(&invitation);
+    tracing::info!("user {username} invited by service token '{}'", auth.token);
+
+    let mut sent = false;
+    if let Some(address) = address {
+        user::set_email(state.storage(), &username, address).map_err(|err| {
+            tracing::error!("failed to save email address: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let mail = Mail::Invitation {
+            username: &username,
+            code: &code,
+        };
+        sent = email::send(&state, &username, mail);
+    }
+
+    Ok(Json(InviteRes { code, sent }))
+}
@@ -0,0 +1,194 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{self, AuditEvent},
+    config::Secret,
+    legacy::LegacyHash,
+    metrics::Step,
+    opaque,
+    session::SessionId,
+    throttle::Throttle,
+    time::Duration,
+    user,
+};
+
+use super::{
+    extract::{ClientAddress, OpaqueJson},
+    state::AppState,
+};
+
+/// Throttle of the legacy sign in attempts, each username and each address
+/// can try 5 and 20 passwords every 15 minutes.
+pub fn throttle() -> Throttle {
+    Throttle::new(5, 20, Duration::minutes(15))
+}
+
+#[derive(Deserialize)]
+pub struct SigninReq {
+    username: String,
+    /// Password as typed by the user, before the normalization.
+    password: Secret,
+    message: opaque::RegistrationRequest,
+}
+
+#[derive(Serialize)]
+pub struct SigninRes {
+    #[serde(serialize_with = "SessionId::serialize")]
+    session: SessionId,
+    message: opaque::RegistrationResponse,
+}
+
+/// Sign in with the legacy password hash and start the registration with
+/// OPAQUE, the client completes it as a re-registration and the hash is
+/// removed.
+///
+/// The users without a legacy hash are verified against a decoy with the most
+/// common parameters of the imported hashes, so they get the same response
+/// after the same time as most users with a hash. The attempts are throttled
+/// by username and by client address.
+pub async fn signin(
+    ClientAddress(address): ClientAddress,
+    State(state): State<AppState>,
+    OpaqueJson(req): OpaqueJson<SigninReq>,
+) -> Result<Json<SigninRes>, StatusCode> {
+    let Some(decoy) = state.legacy() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if !state.legacy_throttle().check(&req.username, address) {
+        tracing::warn!(
+            "legacy sign in of user {} from {address} throttled",
+            req.username
+        );
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    let result = migrate(&state, req, decoy).await;
+    state.metrics().signin(Step::Legacy, &result);
+    result.map(Json)
+}
+
+async fn migrate(
+    state: &AppState,
+    req: SigninReq,
+    decoy: &LegacyHash,
+) -> Result<SigninRes, StatusCode> {
+    let SigninReq {
+        username,
+        password,
+        message: registration_request,
+    } = req;
+
+    let legacy_hash = user::get_legacy_hash(state.storage(), &username).map_err(|err| {
+        tracing::error!("failed to retrieve legacy hash: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let found = legacy_hash.is_some();
+    let legacy_hash = legacy_hash.unwrap_or_else(|| decoy.clone());
+    // the hash parameters are chosen by the other system, keep the runtime free
+    let verified = tokio::task::spawn_blocking(move || legacy_hash.verify(&password))
+        .await
+        .map_err(|err| {
+            tracing::error!("failed to verify legacy hash: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !found {
+        tracing::warn!("legacy sign in of unknown user {username}");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    if !verified {
+        tracing::warn!("invalid legacy password for user {username}");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    audit::record(
+        state.storage(),
+        &username,
+        AuditEvent::LegacyPasswordVerified,
+    )
+    .map_err(|err| {
+        tracing::error!("failed to record audit event: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let registration_response = opaque::registration_start(
+        state.signatures().current(),
+        &username,
+        registration_request,
+    )
+    .map_err(|err| {
+        tracing::error!("failed to start registration of legacy user {username}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let session = user::SignupSession::new(username);
+    let session_id = user::push_reregister_upload(state.storage(), session).map_err(|err| {
+        tracing::error!("failed to push reregister upload: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(SigninRes {
+        session: session_id,
+        message: registration_response,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::{api::testing, user::UserTable};
+
+    #[tokio::test]
+    async fn migrate_legacy_user() {
+        let state = testing::state();
+        let hash = assert_ok!(LegacyHash::new(&testing::legacy_hash("password")));
+        assert_ok!(user::set_legacy_hash(state.storage(), "user", &hash));
+
+        let response = assert_ok!(testing::legacy_signin(&state, "user", "password").await);
+        assert_some!(testing::session_cookie(&response));
+        assert!(assert_ok!(state.storage().user_is_registered("user")));
+        assert!(assert_ok!(user::get_legacy_hash(state.storage(), "user")).is_none());
+
+        let response = assert_ok!(testing::signin(&state, "user", "password").await);
+        assert_some!(testing::session_cookie(&response));
+    }
+
+    #[tokio::test]
+    async fn reject_wrong_legacy_password() {
+        let state = testing::state();
+        let hash = assert_ok!(LegacyHash::new(&testing::legacy_hash("password")));
+        assert_ok!(user::set_legacy_hash(state.storage(), "user", &hash));
+
+        let status = assert_err!(testing::legacy_signin(&state, "user", "wrong password").await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(!assert_ok!(state.storage().user_is_registered("user")));
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_legacy_user() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        let status = assert_err!(testing::legacy_signin(&state, "user", "password").await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let status = assert_err!(testing::legacy_signin(&state, "unknown", "password").await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn throttle_legacy_signins() {
+        let state = testing::state();
+        let hash = assert_ok!(LegacyHash::new(&testing::legacy_hash("password")));
+        assert_ok!(user::set_legacy_hash(state.storage(), "user", &hash));
+        for _ in 0..5 {
+            let status =
+                assert_err!(testing::legacy_signin(&state, "user", "wrong password").await);
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let status = assert_err!(testing::legacy_signin(&state, "user", "password").await);
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -0,0 +1,59 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::state::AppState;
+
+/// Content type of the OpenMetrics text format.
+const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Metrics of the service, in the OpenMetrics text format.
+pub async fn metrics(State(state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    state.metrics().expire_sessions();
+
+    let body = state.metrics().encode().map_err(|err| {
+        tracing::error!("failed to encode metrics: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(CONTENT_TYPE, OPENMETRICS)], body))
+}
+
+/// Count the requests to the frontend failed by the reverse proxy.
+pub async fn track_upstream(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    if matches!(
+        response.status(),
+        StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT
+    ) {
+        state.metrics().upstream_error();
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::api::testing;
+
+    #[tokio::test]
+    async fn expose_signin_outcomes() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+        assert_ok!(testing::signin(&state, "user", "password").await);
+
+        let response = assert_ok!(metrics(State(state)).await).into_response();
+        let body = assert_ok!(axum::body::to_bytes(response.into_body(), usize::MAX).await);
+        let body = assert_ok!(String::from_utf8(body.to_vec()));
+        assert!(body.contains(r#"fresh_auth_signup_total{step="finish",outcome="success"} 1"#));
+        assert!(body.contains(r#"fresh_auth_signin_total{step="finish",outcome="success"} 1"#));
+        assert!(body.contains(r#"fresh_auth_invitations_total{outcome="success"} 2"#));
+        assert!(body.contains("fresh_auth_active_sessions 1"));
+    }
+}
@@ -0,0 +1,314 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{self, AuditEvent},
+    mailer::Mail,
+    metrics::{Outcome, Step},
+    rng,
+    session::SessionId,
+    time::DateTime,
+    user,
+    webauthn::{AssertionResponse, Challenge, RequestOptions},
+};
+
+use super::{email, state::AppState};
+
+#[derive(Serialize)]
+pub struct MfaRes {
+    #[serde(serialize_with = "SessionId::serialize")]
+    mfa: SessionId,
+    /// Second factors available to the user.
+    methods: Vec<&'static str>,
+}
+
+/// Complete the sign in of a user whose password has been verified.
+///
+/// If the user has a second factor the session is not started, the client
+/// receives a short-lived token to be exchanged with a valid code or a
+/// security key assertion. A user with security keys cannot sign in while
+/// WebAuthn is not configured.
+pub async fn complete_signin(
+    jar: CookieJar,
+    state: &AppState,
+    username: String,
+) -> Result<(Outcome, Response), StatusCode> {
+    let totp = user::get_totp(state.storage(), &username).map_err(|err| {
+        tracing::error!("failed to retrieve TOTP authenticator: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut methods = Vec::new();
+    if totp.is_some_and(|totp| totp.confirmed) {
+        methods.push("totp");
+    }
+    let credentials =
+        user::get_webauthn_credentials(state.storage(), &username).map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn credentials: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !credentials.is_empty() {
+        // the security keys cannot be verified without the relying party, the
+        // sign in is refused instead of skipping the second factor
+        if state.relying_party().is_none() {
+            tracing::error!("user {username} has security keys but WebAuthn is not configured");
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        methods.push("webauthn");
+    }
+
+    if !methods.is_empty() {
+        let session = user::MfaSession::new(username);
+        let session_id = user::push_mfa_session(state.storage(), session).map_err(|err| {
+            tracing::error!("failed to push mfa session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let body = Json(MfaRes {
+            mfa: session_id,
+            methods,
+        });
+        return Ok((Outcome::SecondFactor, body.into_response()));
+    }
+
+    let jar = start_session(jar, state, username)?;
+    let body = Json(());
+    Ok((Outcome::Success, (jar, body).into_response()))
+}
+
+/// Start the session of a fully authenticated user, the user is notified
+/// when the sign in comes from a new device.
+fn start_session(
+    jar: CookieJar,
+    state: &AppState,
+    username: String,
+) -> Result<CookieJar, StatusCode> {
+    let device_id = jar
+        .get(user::Device::COOKIE)
+        .and_then(|cookie| cookie.value().parse().ok());
+    let device = user::register_device(state.storage(), &username, device_id).map_err(|err| {
+        tracing::error!("failed to register device: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let jar = match device {
+        Some(device) => {
+            if !device.is_first {
+                let mail = Mail::Signin {
+                    username: &username,
+                    at: DateTime::now(),
+                };
+                email::send(state, &username, mail);
+            }
+            jar.add(device.cookie)
+        }
+        None => jar,
+    };
+
+    let cookie = user::start_new_session(state.storage(), username).map_err(|err| {
+        tracing::error!("failed to create a new session: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(jar.add(cookie))
+}
+
+#[derive(Deserialize)]
+pub struct TotpReq {
+    token: SessionId,
+    code: String,
+}
+
+/// Exchange the second factor token with a TOTP code, the token can be used
+/// only once: after a wrong code the user has to sign in again.
+pub async fn totp(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Json(req): Json<TotpReq>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let result = verify_totp(jar, &state, req);
+    state.metrics().signin(Step::Totp, &result);
+    Ok((result?, Json(())))
+}
+
+fn verify_totp(jar: CookieJar, state: &AppState, req: TotpReq) -> Result<CookieJar, StatusCode> {
+    let TotpReq { token, code } = req;
+
+    let user::MfaSession { username, .. } = user::pull_mfa_session(state.storage(), token)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve mfa session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let now = DateTime::now().unix_timestamp();
+    let verified = user::verify_totp(state.storage(), &username, &code, now).map_err(|err| {
+        tracing::error!("failed to verify TOTP code: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !verified {
+        tracing::warn!("invalid TOTP code for user {username}");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    start_session(jar, state, username)
+}
+
+#[derive(Deserialize)]
+pub struct WebauthnStartReq {
+    token: SessionId,
+}
+
+#[derive(Serialize)]
+pub struct WebauthnStartRes {
+    /// Token to be used to finish the assertion, it replaces the previous one.
+    #[serde(serialize_with = "SessionId::serialize")]
+    token: SessionId,
+    options: RequestOptions,
+}
+
+/// Start the security key assertion of the pending second factor session.
+pub async fn webauthn_start(
+    State(state): State<AppState>,
+    Json(req): Json<WebauthnStartReq>,
+) -> Result<Json<WebauthnStartRes>, StatusCode> {
+    let relying_party = state.relying_party().ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut session = user::pull_mfa_session(state.storage(), req.token)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve mfa session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let credentials =
+        user::get_webauthn_credentials(state.storage(), &session.username).map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn credentials: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if credentials.is_empty() {
+        tracing::error!("user {} has no security key", session.username);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let challenge = rng::with_crypto_rng(Challenge::generate);
+    let options = relying_party.request_options(&challenge, &credentials);
+    session.challenge = Some(challenge);
+    let token = user::push_mfa_session(state.storage(), session).map_err(|err| {
+        tracing::error!("failed to push mfa session: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(WebauthnStartRes { token, options }))
+}
+
+#[derive(Deserialize)]
+pub struct WebauthnFinishReq {
+    token: SessionId,
+    credential: AssertionResponse,
+}
+
+/// Exchange the second factor token with a security key assertion, the token
+/// can be used only once.
+pub async fn webauthn_finish(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Json(req): Json<WebauthnFinishReq>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let result = verify_assertion(jar, &state, req);
+    state.metrics().signin(Step::Webauthn, &result);
+    Ok((result?, Json(())))
+}
+
+fn verify_assertion(
+    jar: CookieJar,
+    state: &AppState,
+    req: WebauthnFinishReq,
+) -> Result<CookieJar, StatusCode> {
+    let WebauthnFinishReq { token, credential } = req;
+    let relying_party = state.relying_party().ok_or(StatusCode::NOT_FOUND)?;
+
+    let user::MfaSession {
+        username,
+        challenge,
+        ..
+    } = user::pull_mfa_session(state.storage(), token)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve mfa session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let challenge = challenge.ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let mut credentials =
+        user::get_webauthn_credentials(state.storage(), &username).map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn credentials: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    relying_party
+        .authenticate(&challenge, &mut credentials, &credential)
+        .map_err(|err| {
+            tracing::warn!("invalid WebAuthn assertion for user {username}: {err}");
+            StatusCode::UNAUTHORIZED
+        })?;
+    user::set_webauthn_credentials(state.storage(), &username, &credentials).map_err(|err| {
+        tracing::error!("failed to save WebAuthn credentials: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    start_session(jar, state, username)
+}
+
+#[derive(Deserialize)]
+pub struct RecoveryReq {
+    token: SessionId,
+    code: String,
+}
+
+/// Exchange the second factor token with a recovery code, both can be used
+/// only once.
+pub async fn recovery(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Json(req): Json<RecoveryReq>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let result = redeem_recovery_code(jar, &state, req);
+    state.metrics().signin(Step::Recovery, &result);
+    Ok((result?, Json(())))
+}
+
+fn redeem_recovery_code(
+    jar: CookieJar,
+    state: &AppState,
+    req: RecoveryReq,
+) -> Result<CookieJar, StatusCode> {
+    let RecoveryReq { token, code } = req;
+
+    let user::MfaSession { username, .. } = user::pull_mfa_session(state.storage(), token)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve mfa session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let remaining = user::redeem_recovery_code(state.storage(), &username, &code)
+        .map_err(|err| {
+            tracing::error!("failed to redeem recovery code: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or_else(|| {
+            tracing::warn!("invalid recovery code for user {username}");
+            StatusCode::UNAUTHORIZED
+        })?;
+    let event = AuditEvent::RecoveryCodeUsed { remaining };
+    audit::record(state.storage(), &username, event).map_err(|err| {
+        tracing::error!("failed to record audit event: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    start_session(jar, state, username)
+}
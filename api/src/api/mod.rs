@@ -1,44 +1,113 @@
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
 
 use anyhow::Result;
 use axum::{
     http::StatusCode,
+    middleware,
     routing::{get, post},
     Router,
 };
-use mello::{kvstorage::KVStorage, reverse_proxy::ReverseProxy};
+use mello::reverse_proxy::ReverseProxy;
 use tokio::net::TcpListener;
-use tower_http::validate_request::ValidateRequestHeaderLayer;
 use tower_otel::trace::HttpLayer;
 use tracing::Level;
 
 use crate::{
     config::Config,
-    invitation::{Invitation, InvitationKey},
-    opaque::OpaqueSignature,
-    user::UserTable,
+    invitation::{Invitation, InvitationKey, InvitationKeys},
+    legacy::LegacyHash,
+    mailer::Mailer,
+    metrics::Metrics,
+    opaque::OpaqueSignatures,
+    storage::{Storage, StorageKeys},
+    token::ServiceTokens,
+    user::{self, UserTable},
+    webauthn::RelyingParty,
 };
 
-use self::state::AppState;
+use self::state::{AppConfig, AppState};
 
+mod auth;
+mod breach;
+mod email;
+mod extract;
+mod invite;
+mod legacy;
+mod metrics;
+mod mfa;
+mod params;
+mod policy;
+mod recovery;
+mod reregister;
 mod session;
 mod signin;
 mod signout;
 mod signup;
 mod state;
+#[cfg(test)]
+mod testing;
+mod totp;
+mod webauthn;
 
 /// Launch the management server listening on the given port
-pub async fn serve(config: &Config) -> Result<()> {
-    let storage = KVStorage::open(&config.storage)?;
-    let invitation_key: InvitationKey = config.key.invitation.parse()?;
-    let signature = OpaqueSignature::new(&config.key.opaque)?;
-    let auth_layer = ValidateRequestHeaderLayer::bearer(&config.key.session);
+pub async fn serve(mut config: Config) -> Result<()> {
+    let metrics = Metrics::new();
+    let storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+    // the sessions started before are counted once, before the latency of
+    // the storage is recorded
+    if config.metrics.is_some() {
+        match user::track_sessions(&storage, &metrics.storage()) {
+            Ok(count) => tracing::info!("{count} active sessions"),
+            Err(err) => tracing::error!("failed to count the active sessions: {err}"),
+        }
+    }
+    // the users without a legacy hash are verified against a decoy as costly
+    // as most imported hashes
+    let legacy = if config.legacy {
+        let decoy = user::legacy_decoy(&storage).unwrap_or_else(|err| {
+            tracing::error!("failed to read the legacy hashes, using the default decoy: {err}");
+            LegacyHash::decoy(&[])
+        });
+        Some(decoy)
+    } else {
+        None
+    };
+    let storage = storage.with_metrics(metrics.storage());
+    let invitation_key: InvitationKey = config.key.invitation()?.parse()?;
+    let retired_invitation_keys = config
+        .key
+        .retired_invitation()?
+        .iter()
+        .map(|key| key.parse())
+        .collect::<Result<Vec<InvitationKey>, _>>()?;
+    let invitation_keys = InvitationKeys::new(invitation_key, retired_invitation_keys);
+    let signatures = OpaqueSignatures::load(&mut config.key)?;
+    let service_tokens = ServiceTokens::new(&config.tokens)?;
+    config.ksf.validate()?;
+    for retired in &config.opaque.retired {
+        retired.ksf.validate()?;
+    }
+    let breach_filter = config
+        .breach
+        .as_deref()
+        .map(breach::FilterFile::load)
+        .transpose()?;
+    config.password.validate(breach_filter.is_some())?;
+    let relying_party = config
+        .webauthn
+        .as_ref()
+        .map(|webauthn| RelyingParty::new(webauthn, &config.issuer));
+    let mailer = config.mail.as_ref().map(Mailer::new).transpose()?;
 
     // generate an invitation code for the administrator
     if !storage.user_is_registered(&config.admin)? {
         let username = &config.admin;
         let invitation = Invitation::admin(&username);
-        let invitation_code = invitation_key.sign(&invitation);
+        let invitation_code = invitation_keys.sign(&invitation);
         tracing::info!("'{username}' invitation code is '{invitation_code}'");
     }
 
@@ -46,27 +115,84 @@ pub async fn serve(config: &Config) -> Result<()> {
     let local_addr = listener.local_addr()?;
     tracing::info!("listening on {}", local_addr);
 
+    let state = AppState::new(AppConfig {
+        storage,
+        signatures,
+        invitation_keys,
+        service_tokens,
+        ksf: config.ksf,
+        opaque: config.opaque.clone(),
+        breach_filter,
+        policy: config.password,
+        issuer: config.issuer.clone(),
+        relying_party,
+        mailer,
+        legacy,
+        metrics,
+    });
+
+    if let Some(metrics_addr) = config.metrics {
+        let listener = TcpListener::bind(metrics_addr).await?;
+        tracing::info!("metrics listening on {}", listener.local_addr()?);
+        let router = Router::new()
+            .route("/metrics", get(metrics::metrics))
+            .with_state(state.clone());
+        tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, router).await {
+                tracing::error!("metrics server failed: {err}");
+            }
+        });
+    }
+
     let fresh_addr = (Ipv4Addr::LOCALHOST, 8000);
-    let reverse_proxy = ReverseProxy::new(fresh_addr);
+    let reverse_proxy = Router::new()
+        .fallback_service(ReverseProxy::new(fresh_addr))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_upstream,
+        ));
 
     let signup = post(signup::signup).get_service(reverse_proxy.clone());
 
-    let state = AppState::new(storage, signature, invitation_key);
     let router = Router::new()
         .route("/api/health", get(health))
-        .route(
-            "/api/session/:id",
-            get(session::get_session).layer(auth_layer.clone()),
-        )
+        .route("/api/opaque/params", get(params::params))
+        .route("/api/opaque/key", get(params::key))
+        .route("/api/breach/filter", get(breach::filter))
+        .route("/api/password/policy", get(policy::policy))
+        .route("/api/session/:id", get(session::get_session))
+        .route("/api/invitation", post(invite::invite))
+        .route("/api/email", post(email::set_email))
+        .route("/api/email/confirm", post(email::confirm_email))
+        .route("/api/reset", post(email::reset))
         .route("/signup", signup)
         .route("/api/signin/start", post(signin::start))
         .route("/api/signin/finish", post(signin::finish))
+        .route("/api/signin/legacy", post(legacy::signin))
+        .route("/api/reregister/start", post(reregister::start))
+        .route("/api/reregister/finish", post(reregister::finish))
+        .route("/api/mfa/totp", post(mfa::totp))
+        .route("/api/mfa/webauthn/start", post(mfa::webauthn_start))
+        .route("/api/mfa/webauthn/finish", post(mfa::webauthn_finish))
+        .route("/api/mfa/recovery", post(mfa::recovery))
+        .route("/api/recovery/regenerate", post(recovery::regenerate))
+        .route("/api/totp/enroll", post(totp::enroll))
+        .route("/api/totp/confirm", post(totp::confirm))
+        .route(
+            "/api/webauthn/register/start",
+            post(webauthn::register_start),
+        )
+        .route(
+            "/api/webauthn/register/finish",
+            post(webauthn::register_finish),
+        )
         .route("/api/signout", get(signout::signout))
         .fallback_service(reverse_proxy)
         .with_state(state)
         .layer(HttpLayer::server(Level::INFO));
 
-    axum::serve(listener, router).await?;
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, service).await?;
     Ok(())
 }
 
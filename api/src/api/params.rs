@@ -0,0 +1,51 @@
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::opaque::KsfParams;
+
+use super::state::AppState;
+
+#[derive(Serialize)]
+pub struct ParamsRes {
+    /// Cipher suite of the server, the client must use the same one.
+    suite: &'static str,
+    /// Key stretching parameters to be used for new registrations.
+    #[serde(flatten)]
+    ksf: KsfParams,
+    /// Server identity to be bound to new registrations.
+    identity: Option<String>,
+    /// Context of the application, bound to each sign in.
+    context: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct KeyRes {
+    /// Public key of the current signature.
+    key: String,
+    /// Public keys of the retired signatures, still used by the users not
+    /// migrated yet.
+    retired: Vec<String>,
+}
+
+/// Static public keys of the server, to be pinned by the clients.
+pub async fn key(State(state): State<AppState>) -> Json<KeyRes> {
+    let signatures = state.signatures();
+    Json(KeyRes {
+        key: signatures.current().public_key().to_string(),
+        retired: signatures
+            .retired()
+            .iter()
+            .map(|signature| signature.public_key().to_string())
+            .collect(),
+    })
+}
+
+/// Protocol parameters to be used for new registrations.
+pub async fn params(State(state): State<AppState>) -> Json<ParamsRes> {
+    Json(ParamsRes {
+        suite: fresh_auth_suite::SUITE,
+        ksf: state.ksf(),
+        identity: state.opaque().identity.clone(),
+        context: state.opaque().context.clone(),
+    })
+}
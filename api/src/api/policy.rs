@@ -0,0 +1,10 @@
+use axum::{extract::State, Json};
+
+use crate::policy::PasswordPolicy;
+
+use super::state::AppState;
+
+/// Password policy enforced by the client.
+pub async fn policy(State(state): State<AppState>) -> Json<PasswordPolicy> {
+    Json(state.policy())
+}
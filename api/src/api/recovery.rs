@@ -0,0 +1,62 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+
+use crate::{
+    audit::{self, AuditEvent},
+    config::Secret,
+    recovery::RecoveryCodes,
+    rng, user,
+};
+
+use super::{auth::Reauthenticated, state::AppState};
+
+#[derive(Serialize)]
+pub struct RecoveryRes {
+    /// New recovery codes, shown only once.
+    recovery: Vec<Secret>,
+}
+
+/// Replace the recovery codes of the user, the previous ones are no longer
+/// valid.
+pub async fn regenerate(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+) -> Result<Json<RecoveryRes>, StatusCode> {
+    let recovery = generate(&state, &username)?;
+    Ok(Json(RecoveryRes { recovery }))
+}
+
+/// Generate the recovery codes when the user enables the first second factor,
+/// returns `None` if the user has already them.
+pub fn generate_if_missing(
+    state: &AppState,
+    username: &str,
+) -> Result<Option<Vec<Secret>>, StatusCode> {
+    let codes = user::get_recovery_codes(state.storage(), username).map_err(|err| {
+        tracing::error!("failed to retrieve recovery codes: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if codes.is_some() {
+        return Ok(None);
+    }
+    generate(state, username).map(Some)
+}
+
+/// Generate and save a new set of recovery codes.
+fn generate(state: &AppState, username: &str) -> Result<Vec<Secret>, StatusCode> {
+    let (recovery, codes) = rng::with_crypto_rng(RecoveryCodes::generate);
+    user::set_recovery_codes(state.storage(), username, &codes).map_err(|err| {
+        tracing::error!("failed to save recovery codes: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    audit::record(
+        state.storage(),
+        username,
+        AuditEvent::RecoveryCodesGenerated,
+    )
+    .map_err(|err| {
+        tracing::error!("failed to record audit event: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(recovery)
+}
@@ -0,0 +1,165 @@
+use axum::{extract::State, http::StatusCode, response::Response, Json};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metrics::{Outcome, Step},
+    opaque,
+    session::SessionId,
+    user::{self, UserTable},
+};
+
+use super::{extract::OpaqueJson, mfa, state::AppState};
+
+#[derive(Deserialize)]
+pub struct StartReq {
+    session: SessionId,
+    message: opaque::RegistrationRequest,
+}
+
+#[derive(Serialize)]
+pub struct StartRes {
+    #[serde(serialize_with = "SessionId::serialize")]
+    session: SessionId,
+    message: opaque::RegistrationResponse,
+}
+
+/// First step of re-registration, the session is obtained from a successful login.
+pub async fn start(
+    State(state): State<AppState>,
+    OpaqueJson(req): OpaqueJson<StartReq>,
+) -> Result<Json<StartRes>, StatusCode> {
+    let result = reregister_start(&state, req);
+    state.metrics().signin(Step::ReregisterStart, &result);
+    result.map(Json)
+}
+
+fn reregister_start(state: &AppState, req: StartReq) -> Result<StartRes, StatusCode> {
+    let StartReq {
+        session: session_id,
+        message: registration_request,
+    } = req;
+
+    let session = user::pull_reregister_session(state.storage(), session_id)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve reregister session: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let registration_response = opaque::registration_start(
+        state.signatures().current(),
+        &session.username,
+        registration_request,
+    )
+    .map_err(|err| {
+        let username = &session.username;
+        tracing::error!("failed to start re-registration of user {username}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let session = user::SignupSession::new(session.username);
+    let session_id = user::push_reregister_upload(state.storage(), session).map_err(|err| {
+        tracing::error!("failed to push reregister upload: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StartRes {
+        session: session_id,
+        message: registration_response,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct FinishReq {
+    session: SessionId,
+    message: opaque::RegistrationUpload,
+}
+
+/// Finish re-registration and complete the sign in.
+pub async fn finish(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    OpaqueJson(req): OpaqueJson<FinishReq>,
+) -> Result<Response, StatusCode> {
+    let result = reregister_finish(jar, &state, req).await;
+    let outcome = result.as_ref().map(|(outcome, _)| *outcome);
+    state
+        .metrics()
+        .signin_outcome(Step::ReregisterFinish, outcome);
+    result.map(|(_, response)| response)
+}
+
+async fn reregister_finish(
+    jar: CookieJar,
+    state: &AppState,
+    req: FinishReq,
+) -> Result<(Outcome, Response), StatusCode> {
+    let FinishReq {
+        session: session_id,
+        message: registration_upload,
+    } = req;
+
+    let user::SignupSession { username, .. } =
+        user::pull_reregister_upload(state.storage(), session_id)
+            .map_err(|err| {
+                tracing::error!("failed to retrieve reregister upload: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let password_file = opaque::registration_finish(
+        state.signatures().current(),
+        state.ksf(),
+        state.opaque().identity.as_deref(),
+        registration_upload,
+    );
+    state
+        .storage()
+        .register_user_password(&username, password_file)
+        .map_err(|err| {
+            tracing::error!("failed to save user's password file: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tracing::info!("user {username} migrated to the current registration");
+
+    mfa::complete_signin(jar, state, username).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+    use serde_json::json;
+
+    use crate::api::testing;
+
+    #[tokio::test]
+    async fn reject_login_token_at_finish() {
+        let state = testing::state();
+        let push = || {
+            let session = user::SignupSession::new("user".to_string());
+            let session_id = assert_ok!(user::push_reregister_session(state.storage(), session));
+            session_id.display().to_string()
+        };
+        let token = push();
+
+        let registration = testing::Registration::start("password");
+        let message = &registration.message;
+        let req = testing::request(json!({ "session": push(), "message": message }));
+        let Json(response) = assert_ok!(start(State(state.clone()), OpaqueJson(req)).await);
+        let response = assert_ok!(serde_json::to_value(response));
+        let message = registration.upload(&response);
+
+        let req = testing::request(json!({ "session": token, "message": message }));
+        let status =
+            assert_err!(finish(CookieJar::new(), State(state.clone()), OpaqueJson(req)).await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let req = testing::request(json!({ "session": response["session"], "message": message }));
+        let response =
+            assert_ok!(finish(CookieJar::new(), State(state.clone()), OpaqueJson(req)).await);
+        assert_some!(testing::session_cookie(&response));
+    }
+}
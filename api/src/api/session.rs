@@ -7,7 +7,10 @@ use serde::Serialize;
 
 use crate::{session::SessionId, user};
 
-use super::state::AppState;
+use super::{
+    auth::{Authorized, SessionRead},
+    state::AppState,
+};
 
 #[derive(Serialize)]
 pub struct Session {
@@ -15,6 +18,7 @@ pub struct Session {
 }
 
 pub async fn get_session(
+    _: Authorized<SessionRead>,
     State(state): State<AppState>,
     Path(session_id): Path<SessionId>,
 ) -> Result<Json<Session>, StatusCode> {
@@ -1,10 +1,20 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use axum_extra::extract::CookieJar;
 use serde::{Deserialize, Serialize};
 
-use crate::{opaque, rng, session::SessionId, user};
+use crate::{
+    metrics::{Outcome, Step},
+    opaque, rng,
+    session::SessionId,
+    user,
+};
 
-use super::state::AppState;
+use super::{extract::OpaqueJson, mfa, state::AppState};
 
 #[derive(Deserialize)]
 pub struct StartReq {
@@ -17,13 +27,31 @@ pub struct StartRes {
     #[serde(serialize_with = "SessionId::serialize")]
     session: SessionId,
     message: opaque::LoginResponse,
+    /// Key stretching parameters used for the registration.
+    ksf: opaque::KsfParams,
+    /// Server identity used for the registration.
+    identity: Option<String>,
+    /// Context of the application.
+    context: Option<String>,
+    /// Cipher suite of the server.
+    suite: &'static str,
+    /// The legacy password hashes are accepted, the client should try the
+    /// legacy endpoint when the sign in fails. It is the same for all the
+    /// users, so the ones not migrated yet cannot be told apart.
+    legacy: bool,
 }
 
 /// First step of login.
 pub async fn start(
     State(state): State<AppState>,
-    Json(req): Json<StartReq>,
+    OpaqueJson(req): OpaqueJson<StartReq>,
 ) -> Result<Json<StartRes>, StatusCode> {
+    let result = signin_start(&state, req);
+    state.metrics().signin(Step::Start, &result);
+    result.map(Json)
+}
+
+fn signin_start(state: &AppState, req: StartReq) -> Result<StartRes, StatusCode> {
     let StartReq {
         username,
         message: login_request,
@@ -33,13 +61,26 @@ pub async fn start(
         tracing::error!("failed to retrieve password file: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    let config = state.opaque();
+    let reregister = password_file.as_ref().is_some_and(|password_file| {
+        state.signatures().needs_migration(password_file)
+            || password_file.ksf() != state.ksf()
+            || password_file.identity() != config.identity.as_deref()
+    });
+    let (ksf, identity) = match &password_file {
+        Some(password_file) => (password_file.ksf(), password_file.identity()),
+        None => decoy_params(state, &username),
+    };
+    let identity = identity.map(str::to_string);
 
     let (login_response, login_state) = rng::with_crypto_rng(|rng| {
         opaque::login_start(
             rng,
-            state.signature(),
+            state.signatures(),
             &username,
             password_file,
+            identity.as_deref(),
+            config.context.as_deref(),
             login_request,
         )
     })
@@ -48,16 +89,35 @@ pub async fn start(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let session = user::SigninSession::new(username, login_state);
+    let session = user::SigninSession::new(username, login_state, reregister);
     let session_id = user::push_signin_session(state.storage(), session).map_err(|err| {
         tracing::error!("failed to push signin session: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    Ok(Json(StartRes {
+    Ok(StartRes {
         session: session_id,
         message: login_response,
-    }))
+        ksf,
+        identity,
+        context: config.context.clone(),
+        suite: fresh_auth_suite::SUITE,
+        legacy: state.legacy().is_some(),
+    })
+}
+
+/// Returns the key stretching parameters and the server identity given to
+/// an unknown user, the current or the retired ones like the registered users.
+fn decoy_params<'a>(state: &'a AppState, username: &str) -> (opaque::KsfParams, Option<&'a str>) {
+    let config = state.opaque();
+    let index = state
+        .signatures()
+        .current()
+        .pick(username, config.retired.len() + 1);
+    match config.retired.get(index) {
+        Some(retired) => (retired.ksf, retired.identity.as_deref()),
+        None => (state.ksf(), config.identity.as_deref()),
+    }
 }
 
 #[derive(Deserialize)]
@@ -66,12 +126,35 @@ pub struct FinishReq {
     message: opaque::LoginFinalization,
 }
 
+#[derive(Serialize)]
+pub struct ReregisterRes {
+    #[serde(serialize_with = "SessionId::serialize")]
+    reregister: SessionId,
+}
+
 /// Finish login.
+///
+/// If the user was registered with a retired server setup, with outdated key
+/// stretching parameters or with a different server identity the session is
+/// not started, the client is forced to re-register the password first. If
+/// the user has a second factor, the session is started only after its
+/// verification.
 pub async fn finish(
     jar: CookieJar,
     State(state): State<AppState>,
-    Json(req): Json<FinishReq>,
-) -> Result<impl IntoResponse, StatusCode> {
+    OpaqueJson(req): OpaqueJson<FinishReq>,
+) -> Result<Response, StatusCode> {
+    let result = signin_finish(jar, &state, req).await;
+    let outcome = result.as_ref().map(|(outcome, _)| *outcome);
+    state.metrics().signin_outcome(Step::Finish, outcome);
+    result.map(|(_, response)| response)
+}
+
+async fn signin_finish(
+    jar: CookieJar,
+    state: &AppState,
+    req: FinishReq,
+) -> Result<(Outcome, Response), StatusCode> {
     let FinishReq {
         session: session_id,
         message: login_finalization,
@@ -80,6 +163,7 @@ pub async fn finish(
     let user::SigninSession {
         username,
         state: login_state,
+        reregister,
         ..
     } = user::pull_signin_session(state.storage(), session_id)
         .map_err(|err| {
@@ -93,12 +177,189 @@ pub async fn finish(
         StatusCode::UNAUTHORIZED
     })?;
 
-    let cookie = user::start_new_session(state.storage(), username).map_err(|err| {
-        tracing::error!("failed to create a new session: {err}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    if reregister {
+        tracing::info!("user {username} should be migrated to the current registration");
+        let session = user::SignupSession::new(username);
+        let session_id =
+            user::push_reregister_session(state.storage(), session).map_err(|err| {
+                tracing::error!("failed to push reregister session: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        let body = Json(ReregisterRes {
+            reregister: session_id,
+        });
+        return Ok((Outcome::Reregister, body.into_response()));
+    }
+
+    // the password file saved without setup identifier has been verified
+    // with the current signature
+    user::bind_password_file(state.storage(), &username, state.signatures().current()).map_err(
+        |err| {
+            tracing::error!("failed to bind password file of user {username}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    )?;
+
+    mfa::complete_signin(jar, state, username).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use super::*;
+
+    use claym::*;
+
+    use crate::{
+        api::testing,
+        opaque::{ConfigOpaque, ConfigRetiredOpaque, OpaqueSignature, PasswordFile},
+        rng,
+        user::UserTable,
+        webauthn::Credential,
+    };
+
+    /// Returns the password file of the user without setup identifier, as
+    /// saved before the server setup rotation.
+    fn unversioned_password_file(state: &AppState, username: &str) -> PasswordFile {
+        let password_file = assert_ok!(user::get_password_file(state.storage(), username));
+        let mut value = assert_ok!(serde_json::to_value(assert_some!(password_file)));
+        assert_some!(assert_some!(value.as_object_mut()).remove("setup"));
+        assert_ok!(serde_json::from_value(value))
+    }
+
+    #[tokio::test]
+    async fn start_session_of_registered_user() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        let response = assert_ok!(testing::signin(&state, "user", "password").await);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_some!(testing::session_cookie(&response));
+    }
+
+    #[tokio::test]
+    async fn bind_unversioned_password_file() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+        let password_file = unversioned_password_file(&state, "user");
+        assert_ok!(state
+            .storage()
+            .register_user_password("user", password_file));
+
+        let response = assert_ok!(testing::signin(&state, "user", "password").await);
+        assert_some!(testing::session_cookie(&response));
+        let password_file = assert_ok!(user::get_password_file(state.storage(), "user"));
+        let value = assert_ok!(serde_json::to_value(assert_some!(password_file)));
+        assert!(value["setup"].is_string());
+    }
+
+    #[tokio::test]
+    async fn migrate_unversioned_password_file_after_rotation() {
+        let oldest = rng::with_crypto_rng(OpaqueSignature::generate);
+        let current = rng::with_crypto_rng(OpaqueSignature::generate);
+
+        let before = testing::state_with_signatures(&oldest, &[]);
+        let code = testing::invite(&before, "user");
+        assert_ok!(testing::register(&before, &code, "password").await);
+        let password_file = unversioned_password_file(&before, "user");
+
+        let after = testing::state_with_signatures(&current, &[&oldest]);
+        assert_ok!(after
+            .storage()
+            .register_user_password("user", password_file));
+        let response = assert_ok!(testing::signin(&after, "user", "password").await);
+        assert_none!(testing::session_cookie(&response));
+        let body = testing::body(response).await;
+        assert!(body["reregister"].is_string());
+    }
+
+    #[tokio::test]
+    async fn reject_wrong_password() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        let login = assert_ok!(testing::Login::start(&state, "user", "wrong password").await);
+        assert_none!(login.finalization("wrong password"));
+    }
+
+    #[tokio::test]
+    async fn hide_unknown_users() {
+        let state = testing::state();
+        let login = assert_ok!(testing::Login::start(&state, "unknown", "password").await);
+        assert_none!(login.finalization("password"));
+    }
+
+    #[tokio::test]
+    async fn give_unknown_users_retired_parameters() {
+        let retired = opaque::KsfParams {
+            memory: 16,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let state = testing::state_with_opaque(ConfigOpaque {
+            retired: vec![ConfigRetiredOpaque {
+                ksf: retired,
+                identity: Some("old.example.com".to_string()),
+            }],
+            ..Default::default()
+        });
+
+        let mut given = HashSet::new();
+        for n in 0..32 {
+            let username = format!("unknown-{n}");
+            let first = assert_ok!(testing::Login::start(&state, &username, "password").await);
+            let again = assert_ok!(testing::Login::start(&state, &username, "password").await);
+            assert_eq!(first.body["ksf"], again.body["ksf"]);
+            assert_eq!(first.body["identity"], again.body["identity"]);
+            let old_identity = first.body["identity"] == "old.example.com";
+            let ksf: opaque::KsfParams =
+                assert_ok!(serde_json::from_value(first.body["ksf"].clone()));
+            assert_eq!(ksf == retired, old_identity);
+            given.insert((ksf.memory, ksf.iterations, ksf.parallelism));
+        }
+        assert_eq!(given.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reject_finalization_of_another_signin() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        let first = assert_ok!(testing::Login::start(&state, "user", "password").await);
+        let second = assert_ok!(testing::Login::start(&state, "user", "password").await);
+        let session = first.session.clone();
+        let message = assert_some!(second.finalization("password"));
+        let req = testing::request(json!({ "session": session, "message": message }));
+
+        let status = assert_err!(finish(CookieJar::new(), State(state), OpaqueJson(req)).await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn refuse_security_keys_without_relying_party() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+        let credential: Credential = assert_ok!(serde_json::from_value(json!({
+            "id": "a2V5",
+            "alg": -7,
+            "key": "",
+            "counter": 0,
+        })));
+        assert_ok!(user::set_webauthn_credentials(
+            state.storage(),
+            "user",
+            &[credential]
+        ));
 
-    let jar = jar.add(cookie);
-    let body = Json(());
-    Ok((jar, body))
+        let status = assert_err!(testing::signin(&state, "user", "password").await);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
 }
@@ -28,3 +28,27 @@ pub async fn signout(
     let jar = jar.add(cookie);
     Ok((jar, Json(())))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::api::testing;
+
+    #[tokio::test]
+    async fn finish_session() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+        let response = assert_ok!(testing::signin(&state, "user", "password").await);
+        let cookie = assert_some!(testing::session_cookie(&response));
+        let session_id = assert_ok!(cookie.value().parse());
+
+        let jar = CookieJar::new().add(cookie);
+        assert_ok!(signout(jar, State(state.clone())).await);
+        let session = assert_ok!(user::get_session(state.storage(), session_id));
+        assert!(session.is_none());
+    }
+}
@@ -4,12 +4,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     invitation::{Invitation, InvitationCode},
+    metrics::Step,
     opaque,
     session::SessionId,
     user::{self, UserTable},
 };
 
-use super::state::AppState;
+use super::{extract::OpaqueJson, state::AppState};
 
 #[derive(Deserialize)]
 #[serde(tag = "step", rename_all = "lowercase")]
@@ -28,11 +29,19 @@ enum Response {
 /// Registration endpoint
 pub async fn signup(
     State(state): State<AppState>,
-    Json(req): Json<Request>,
+    OpaqueJson(req): OpaqueJson<Request>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let res = match req {
-        Request::Start(req) => Response::Start(start(state, req).await?),
-        Request::Finish(req) => Response::Finish(finish(state, req).await?),
+        Request::Start(req) => {
+            let result = start(state.clone(), req).await;
+            state.metrics().signup(Step::Start, &result);
+            Response::Start(result?)
+        }
+        Request::Finish(req) => {
+            let result = finish(state.clone(), req).await;
+            state.metrics().signup(Step::Finish, &result);
+            Response::Finish(result?)
+        }
     };
     Ok(Json(res))
 }
@@ -57,20 +66,23 @@ async fn start(state: AppState, req: StartReq) -> Result<StartRes, StatusCode> {
         message: registration_request,
     } = req;
 
-    let Invitation { username, .. } = state
-        .invitation_key()
-        .verify(&code)
-        .map_err(|_| StatusCode::UNAUTHORIZED)?;
-
-    let registration_response =
-        opaque::registration_start(state.signature(), &username, registration_request).map_err(
-            |err| {
-                tracing::error!("failed to start registration of user {username}: {err}",);
-                StatusCode::INTERNAL_SERVER_ERROR
-            },
-        )?;
+    let Invitation { username, .. } = state.invitation_keys().verify(&code).map_err(|err| {
+        state.metrics().invitation_failure(err);
+        StatusCode::UNAUTHORIZED
+    })?;
+    state.metrics().invitation_verified();
+
+    let registration_response = opaque::registration_start(
+        state.signatures().current(),
+        &username,
+        registration_request,
+    )
+    .map_err(|err| {
+        tracing::error!("failed to start registration of user {username}: {err}",);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
 
-    let session = user::SignupSession::new(username);
+    let session = user::SignupSession::with_invitation(username, code);
     let session_id = user::push_signup_session(state.storage(), session).map_err(|err| {
         tracing::error!("failed to push signup session: {err}");
         StatusCode::INTERNAL_SERVER_ERROR
@@ -98,7 +110,7 @@ async fn finish(state: AppState, req: FinishReq) -> Result<FinishRes, StatusCode
         message: registration_upload,
     } = req;
 
-    let user::SignupSession { username, .. } =
+    let user::SignupSession { username, code, .. } =
         user::pull_signup_session(state.storage(), session_id)
             .map_err(|err| {
                 tracing::error!("failed to retrieve signup session: {err}");
@@ -106,7 +118,41 @@ async fn finish(state: AppState, req: FinishReq) -> Result<FinishRes, StatusCode
             })?
             .ok_or(StatusCode::UNAUTHORIZED)?;
 
-    let password_file = opaque::registration_finish(registration_upload);
+    // each invitation can complete only one registration
+    if let Some(code) = code {
+        let invitation = state.invitation_keys().verify(&code).map_err(|err| {
+            state.metrics().invitation_failure(err);
+            StatusCode::UNAUTHORIZED
+        })?;
+        let redeemed = state
+            .storage()
+            .redeem_invitation(&code.digest(), invitation.expiration())
+            .map_err(|err| {
+                tracing::error!("failed to redeem invitation: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        if !redeemed {
+            tracing::warn!("invitation of user {username} already redeemed");
+            state.metrics().invitation_redeemed();
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        state.metrics().invitation_verified();
+    }
+
+    let is_registered = state
+        .storage()
+        .user_is_registered(&username)
+        .map_err(|err| {
+            tracing::error!("failed to check user {username}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let password_file = opaque::registration_finish(
+        state.signatures().current(),
+        state.ksf(),
+        state.opaque().identity.as_deref(),
+        registration_upload,
+    );
     state
         .storage()
         .register_user_password(&username, password_file)
@@ -115,5 +161,55 @@ async fn finish(state: AppState, req: FinishReq) -> Result<FinishRes, StatusCode
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    // the password has been reset, the sessions started with the old one
+    // are no longer trusted
+    if is_registered {
+        let count = user::finish_user_sessions(state.storage(), &username).map_err(|err| {
+            tracing::error!("failed to finish sessions of user {username}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        tracing::info!("password of user {username} reset, {count} sessions finished");
+    }
+
     Ok(FinishRes {})
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::api::testing;
+
+    #[tokio::test]
+    async fn register_invited_user() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        assert!(assert_ok!(state.storage().user_is_registered("user")));
+    }
+
+    #[tokio::test]
+    async fn reject_redeemed_invitation() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+
+        let status = assert_err!(testing::register(&state, &code, "other password").await);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn finish_sessions_on_password_reset() {
+        let state = testing::state();
+        let code = testing::invite(&state, "user");
+        assert_ok!(testing::register(&state, &code, "password").await);
+        assert_ok!(user::start_new_session(state.storage(), "user".to_string()));
+
+        let code = state.invitation_keys().sign(&Invitation::reset("user"));
+        assert_ok!(testing::register(&state, &code.to_string(), "new password").await);
+        assert!(assert_ok!(state.storage().user_sessions("user")).is_empty());
+    }
+}
@@ -1,8 +1,18 @@
 use std::sync::Arc;
 
-use mello::kvstorage::KVStorage;
-
-use crate::{invitation::InvitationKey, opaque::OpaqueSignature};
+use super::{breach::FilterFile, email, legacy};
+use crate::{
+    invitation::InvitationKeys,
+    legacy::LegacyHash,
+    mailer::Mailer,
+    metrics::Metrics,
+    opaque::{ConfigOpaque, KsfParams, OpaqueSignatures},
+    policy::PasswordPolicy,
+    storage::Storage,
+    throttle::Throttle,
+    token::ServiceTokens,
+    webauthn::RelyingParty,
+};
 
 /// Application state
 #[derive(Clone)]
@@ -10,23 +20,40 @@ pub struct AppState {
     inner: Arc<Inner>,
 }
 
+/// Parts of the application state, loaded from the configuration.
+pub struct AppConfig {
+    pub storage: Storage,
+    pub signatures: OpaqueSignatures,
+    pub invitation_keys: InvitationKeys,
+    pub service_tokens: ServiceTokens,
+    /// Key stretching parameters for new registrations.
+    pub ksf: KsfParams,
+    pub opaque: ConfigOpaque,
+    pub breach_filter: Option<FilterFile>,
+    pub policy: PasswordPolicy,
+    /// Name of the service, shown by the authenticator apps.
+    pub issuer: String,
+    pub relying_party: Option<RelyingParty>,
+    pub mailer: Option<Mailer>,
+    /// Decoy verified for the users without a legacy password hash, the
+    /// hashes are accepted only when set.
+    pub legacy: Option<LegacyHash>,
+    pub metrics: Metrics,
+}
+
 struct Inner {
-    storage: KVStorage,
-    signature: OpaqueSignature,
-    invitation_key: InvitationKey,
+    config: AppConfig,
+    reset_throttle: Throttle,
+    legacy_throttle: Throttle,
 }
 
 impl AppState {
     /// Create a new application state.
-    pub fn new(
-        storage: KVStorage,
-        signature: OpaqueSignature,
-        invitation_key: InvitationKey,
-    ) -> Self {
+    pub fn new(config: AppConfig) -> Self {
         let inner = Inner {
-            storage,
-            signature,
-            invitation_key,
+            config,
+            reset_throttle: email::reset_throttle(),
+            legacy_throttle: legacy::throttle(),
         };
         Self {
             inner: Arc::new(inner),
@@ -34,17 +61,77 @@ impl AppState {
     }
 
     /// Returns a reference to the storage.
-    pub fn storage(&self) -> &KVStorage {
-        &self.inner.storage
+    pub fn storage(&self) -> &Storage {
+        &self.inner.config.storage
+    }
+
+    /// Returns a reference to the server signatures.
+    pub fn signatures(&self) -> &OpaqueSignatures {
+        &self.inner.config.signatures
+    }
+
+    /// Returns a reference to the inviation keys.
+    pub fn invitation_keys(&self) -> &InvitationKeys {
+        &self.inner.config.invitation_keys
+    }
+
+    /// Returns a reference to the service tokens.
+    pub fn service_tokens(&self) -> &ServiceTokens {
+        &self.inner.config.service_tokens
+    }
+
+    /// Returns the key stretching parameters for new registrations.
+    pub fn ksf(&self) -> KsfParams {
+        self.inner.config.ksf
+    }
+
+    /// Returns the server identity and the context bound to the key exchange.
+    pub fn opaque(&self) -> &ConfigOpaque {
+        &self.inner.config.opaque
+    }
+
+    /// Returns a reference to the breached password filter, if any.
+    pub fn breach_filter(&self) -> Option<&FilterFile> {
+        self.inner.config.breach_filter.as_ref()
+    }
+
+    /// Returns the password policy.
+    pub fn policy(&self) -> PasswordPolicy {
+        self.inner.config.policy
+    }
+
+    /// Returns the name of the service, shown by the authenticator apps.
+    pub fn issuer(&self) -> &str {
+        &self.inner.config.issuer
+    }
+
+    /// Returns a reference to the WebAuthn relying party, if any.
+    pub fn relying_party(&self) -> Option<&RelyingParty> {
+        self.inner.config.relying_party.as_ref()
+    }
+
+    /// Returns a reference to the mailer, if any.
+    pub fn mailer(&self) -> Option<&Mailer> {
+        self.inner.config.mailer.as_ref()
+    }
+
+    /// Returns the decoy of the legacy password hashes, if they are accepted.
+    pub fn legacy(&self) -> Option<&LegacyHash> {
+        self.inner.config.legacy.as_ref()
+    }
+
+    /// Returns a reference to the metrics.
+    pub fn metrics(&self) -> &Metrics {
+        &self.inner.config.metrics
     }
 
-    /// Returns a reference to the server signature.
-    pub fn signature(&self) -> &OpaqueSignature {
-        &self.inner.signature
+    /// Returns the throttle of the password reset requests.
+    pub fn reset_throttle(&self) -> &Throttle {
+        &self.inner.reset_throttle
     }
 
-    /// Returns a reference to the inviation key.
-    pub fn invitation_key(&self) -> &InvitationKey {
-        &self.inner.invitation_key
+    /// Returns the throttle of the legacy sign in attempts.
+    pub fn legacy_throttle(&self) -> &Throttle {
+        &self.inner.legacy_throttle
     }
 }
@@ -0,0 +1,321 @@
+//! Helpers of the handler tests, the client side of the protocol is driven
+//! with `opaque-ke` as done by the frontend.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use axum::{
+    extract::State,
+    http::{header::SET_COOKIE, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use claym::*;
+use cookie::Cookie;
+use fresh_auth_suite::{
+    envelope::{self, MessageType},
+    CipherSuite,
+};
+use rand::rngs::OsRng;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::{
+    invitation::{Invitation, InvitationKey, InvitationKeys},
+    legacy::LegacyHash,
+    mailer::{ConfigMail, Mailer, TransportKind},
+    metrics::Metrics,
+    opaque::{ConfigOpaque, KsfParams, OpaqueSignature, OpaqueSignatures},
+    policy::PasswordPolicy,
+    rng, storage,
+    token::ServiceTokens,
+    user,
+};
+
+use super::{
+    extract::{ClientAddress, OpaqueJson},
+    legacy, reregister, signin, signup,
+    state::{AppConfig, AppState},
+};
+
+/// Key stretching parameters of the tests, as fast as possible.
+const KSF: KsfParams = KsfParams {
+    memory: 8,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// Application state with an in-memory storage.
+pub fn state() -> AppState {
+    let signature = rng::with_crypto_rng(OpaqueSignature::generate);
+    state_with_signatures(&signature, &[])
+}
+
+/// Application state with the given current and retired signatures.
+pub fn state_with_signatures(current: &str, retired: &[&str]) -> AppState {
+    build_state(current, retired, ConfigOpaque::default(), None)
+}
+
+/// Application state with the given parameters of the key exchange.
+pub fn state_with_opaque(opaque: ConfigOpaque) -> AppState {
+    let signature = rng::with_crypto_rng(OpaqueSignature::generate);
+    build_state(&signature, &[], opaque, None)
+}
+
+/// Application state with a mailer that only logs the emails.
+pub fn state_with_mailer() -> AppState {
+    let signature = rng::with_crypto_rng(OpaqueSignature::generate);
+    let config = ConfigMail {
+        from: "noreply@example.com".to_string(),
+        url: "https://example.com".to_string(),
+        transport: TransportKind::Log,
+        smtp: None,
+        dir: None,
+    };
+    let mailer = assert_ok!(Mailer::new(&config));
+    build_state(&signature, &[], ConfigOpaque::default(), Some(mailer))
+}
+
+fn build_state(
+    current: &str,
+    retired: &[&str],
+    opaque: ConfigOpaque,
+    mailer: Option<Mailer>,
+) -> AppState {
+    let metrics = Metrics::new();
+    let storage = storage::tests::storage().with_metrics(metrics.storage());
+    let current = assert_ok!(OpaqueSignature::new(current));
+    let retired = retired
+        .iter()
+        .map(|signature| assert_ok!(OpaqueSignature::new(signature)))
+        .collect();
+    let invitation_key = rng::with_crypto_rng(InvitationKey::generate);
+
+    AppState::new(AppConfig {
+        storage,
+        signatures: OpaqueSignatures::new(current, retired),
+        invitation_keys: InvitationKeys::new(invitation_key, Vec::new()),
+        service_tokens: assert_ok!(ServiceTokens::new(&HashMap::new())),
+        ksf: KSF,
+        opaque,
+        breach_filter: None,
+        policy: PasswordPolicy::default(),
+        issuer: "fresh-auth".to_string(),
+        relying_party: None,
+        mailer,
+        legacy: Some(LegacyHash::decoy(&[])),
+        metrics,
+    })
+}
+
+/// Returns a new invitation code for the user.
+pub fn invite(state: &AppState, username: &str) -> String {
+    let invitation = Invitation::new(username);
+    state.invitation_keys().sign(&invitation).to_string()
+}
+
+/// Deserialize the request from its JSON representation.
+pub fn request<T: DeserializeOwned>(value: serde_json::Value) -> T {
+    assert_ok!(serde_json::from_value(value))
+}
+
+/// Returns the JSON body of the response.
+pub async fn body(response: Response) -> serde_json::Value {
+    let body = assert_ok!(axum::body::to_bytes(response.into_body(), usize::MAX).await);
+    assert_ok!(serde_json::from_slice(&body))
+}
+
+/// Returns the session cookie set by the response.
+pub fn session_cookie(response: &Response) -> Option<Cookie<'static>> {
+    response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| Cookie::parse(value.to_str().ok()?.to_string()).ok())
+        .find(|cookie| cookie.name() == user::Session::COOKIE)
+}
+
+/// Pending registration of a password.
+pub struct Registration {
+    state: opaque_ke::ClientRegistration<CipherSuite>,
+    password: String,
+    /// Registration request.
+    pub message: String,
+}
+
+impl Registration {
+    /// Start the registration of the password.
+    pub fn start(password: &str) -> Self {
+        let registration_start = assert_ok!(opaque_ke::ClientRegistration::<CipherSuite>::start(
+            &mut OsRng,
+            password.as_bytes()
+        ));
+        let message = envelope::seal(
+            MessageType::RegistrationRequest,
+            &registration_start.message.serialize(),
+        );
+        Self {
+            state: registration_start.state,
+            password: password.to_string(),
+            message,
+        }
+    }
+
+    /// Returns the registration upload, from the response of the server.
+    pub fn upload(self, response: &serde_json::Value) -> String {
+        let message = assert_some!(response["message"].as_str());
+        let message = assert_ok!(envelope::open(MessageType::RegistrationResponse, message));
+        let message = assert_ok!(opaque_ke::RegistrationResponse::deserialize(&message));
+        let ksf = ksf();
+        let params = opaque_ke::ClientRegistrationFinishParameters::new(identifiers(), Some(&ksf));
+        let registration_finish =
+            assert_ok!(self
+                .state
+                .finish(&mut OsRng, self.password.as_bytes(), message, params,));
+        envelope::seal(
+            MessageType::RegistrationUpload,
+            &registration_finish.message.serialize(),
+        )
+    }
+}
+
+/// Register the password with the invitation code.
+pub async fn register(state: &AppState, code: &str, password: &str) -> Result<(), StatusCode> {
+    let registration = Registration::start(password);
+    let message = &registration.message;
+    let req = request(json!({ "step": "start", "code": code, "message": message }));
+    let response = signup::signup(State(state.clone()), OpaqueJson(req)).await?;
+    let response = body(response.into_response()).await;
+
+    let message = registration.upload(&response);
+    let req = request(json!({
+        "step": "finish",
+        "session": response["session"],
+        "message": message,
+    }));
+    signup::signup(State(state.clone()), OpaqueJson(req)).await?;
+    Ok(())
+}
+
+/// Returns the Argon2 hash of the password, as imported from another system.
+pub fn legacy_hash(password: &str) -> String {
+    let salt = argon2::password_hash::SaltString::generate(&mut OsRng);
+    let hash = assert_ok!(argon2::PasswordHasher::hash_password(
+        &ksf(),
+        password.as_bytes(),
+        &salt
+    ));
+    hash.to_string()
+}
+
+/// Returns the address of the client of the tests.
+pub fn client_address() -> ClientAddress {
+    ClientAddress(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/// Sign in with the legacy password hash, registering the password with OPAQUE.
+pub async fn legacy_signin(
+    state: &AppState,
+    username: &str,
+    password: &str,
+) -> Result<Response, StatusCode> {
+    let registration = Registration::start(password);
+    let message = &registration.message;
+    let req = request(json!({ "username": username, "password": password, "message": message }));
+    let response = legacy::signin(client_address(), State(state.clone()), OpaqueJson(req)).await?;
+    let response = body(response.into_response()).await;
+
+    let message = registration.upload(&response);
+    let req = request(json!({ "session": response["session"], "message": message }));
+    reregister::finish(CookieJar::new(), State(state.clone()), OpaqueJson(req)).await
+}
+
+/// Pending sign in, the password has not been verified yet.
+pub struct Login {
+    state: opaque_ke::ClientLogin<CipherSuite>,
+    /// Sign in session.
+    pub session: serde_json::Value,
+    /// Body of the response of the server.
+    pub body: serde_json::Value,
+    /// Response of the server.
+    message: opaque_ke::CredentialResponse<CipherSuite>,
+}
+
+impl Login {
+    /// Start the sign in of the user.
+    pub async fn start(
+        state: &AppState,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, StatusCode> {
+        let login_start = assert_ok!(opaque_ke::ClientLogin::<CipherSuite>::start(
+            &mut OsRng,
+            password.as_bytes()
+        ));
+        let message = envelope::seal(
+            MessageType::CredentialRequest,
+            &login_start.message.serialize(),
+        );
+        let req = request(json!({ "username": username, "message": message }));
+        let response = signin::start(State(state.clone()), OpaqueJson(req)).await?;
+        let response = body(response.into_response()).await;
+
+        let message = assert_some!(response["message"].as_str());
+        let message = assert_ok!(envelope::open(MessageType::CredentialResponse, message));
+        let message = assert_ok!(opaque_ke::CredentialResponse::deserialize(&message));
+        Ok(Self {
+            state: login_start.state,
+            session: response["session"].clone(),
+            body: response,
+            message,
+        })
+    }
+
+    /// Returns the finalization message, `None` if the password is wrong.
+    pub fn finalization(self, password: &str) -> Option<String> {
+        let ksf = ksf();
+        let params = opaque_ke::ClientLoginFinishParameters::new(None, identifiers(), Some(&ksf));
+        let login_finish = self
+            .state
+            .finish(password.as_bytes(), self.message, params)
+            .ok()?;
+        Some(envelope::seal(
+            MessageType::CredentialFinalization,
+            &login_finish.message.serialize(),
+        ))
+    }
+}
+
+/// Sign in the user, returns the response of the last step.
+pub async fn signin(
+    state: &AppState,
+    username: &str,
+    password: &str,
+) -> Result<Response, StatusCode> {
+    let login = Login::start(state, username, password).await?;
+    let session = login.session.clone();
+    let message = assert_some!(login.finalization(password));
+    let req = request(json!({ "session": session, "message": message }));
+    signin::finish(CookieJar::new(), State(state.clone()), OpaqueJson(req)).await
+}
+
+/// Key stretching function with the parameters of the tests.
+fn ksf() -> argon2::Argon2<'static> {
+    let params = assert_ok!(argon2::Params::new(
+        KSF.memory,
+        KSF.iterations,
+        KSF.parallelism,
+        None
+    ));
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+}
+
+/// Identifiers bound to the key exchange, the tests use no server identity.
+fn identifiers() -> opaque_ke::Identifiers<'static> {
+    opaque_ke::Identifiers {
+        client: None,
+        server: None,
+    }
+}
@@ -0,0 +1,100 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Secret,
+    rng,
+    time::DateTime,
+    totp::TotpSecret,
+    user::{self, Totp},
+};
+
+use super::{auth::Reauthenticated, recovery, state::AppState};
+
+#[derive(Serialize)]
+pub struct EnrollRes {
+    /// Shared secret, base32 encoded.
+    secret: Secret,
+    /// Provisioning URI, to be shown as QR code.
+    uri: Secret,
+}
+
+/// Generate a new TOTP secret for the user who has just signed in, the second
+/// factor is enabled only after the confirmation of the first code.
+pub async fn enroll(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+) -> Result<Json<EnrollRes>, StatusCode> {
+    let totp = user::get_totp(state.storage(), &username).map_err(|err| {
+        tracing::error!("failed to retrieve TOTP authenticator: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if totp.is_some_and(|totp| totp.confirmed) {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let secret = rng::with_crypto_rng(TotpSecret::generate);
+    let totp = Totp {
+        secret: secret.encode(),
+        confirmed: false,
+        last_step: None,
+    };
+    user::set_totp(state.storage(), &username, &totp).map_err(|err| {
+        tracing::error!("failed to save TOTP authenticator: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(EnrollRes {
+        secret: totp.secret,
+        uri: secret.provisioning_uri(state.issuer(), &username),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmReq {
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct ConfirmRes {
+    /// Recovery codes, generated with the first second factor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery: Option<Vec<Secret>>,
+}
+
+/// Confirm the TOTP authenticator with its first code, the user must have
+/// just signed in as the first recovery codes are returned.
+pub async fn confirm(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+    Json(req): Json<ConfirmReq>,
+) -> Result<Json<ConfirmRes>, StatusCode> {
+    let mut totp = user::get_totp(state.storage(), &username)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve TOTP authenticator: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if totp.confirmed {
+        return Err(StatusCode::CONFLICT);
+    }
+    let secret = TotpSecret::decode(&totp.secret).map_err(|err| {
+        tracing::error!("failed to decode TOTP secret of user {username}: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let now = DateTime::now().unix_timestamp();
+    let step = secret
+        .verify(&req.code, now, None)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    totp.confirmed = true;
+    totp.last_step = Some(step);
+    user::set_totp(state.storage(), &username, &totp).map_err(|err| {
+        tracing::error!("failed to save TOTP authenticator: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    tracing::info!("user {username} enabled TOTP authenticator");
+
+    let recovery = recovery::generate_if_missing(&state, &username)?;
+    Ok(Json(ConfirmRes { recovery }))
+}
@@ -0,0 +1,82 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+
+use crate::{
+    config::Secret,
+    rng, user,
+    webauthn::{Challenge, CreationOptions, RegistrationResponse},
+};
+
+use super::{auth::Reauthenticated, recovery, state::AppState};
+
+/// Start the registration of a security key for the user who has just signed
+/// in.
+pub async fn register_start(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+) -> Result<Json<CreationOptions>, StatusCode> {
+    let relying_party = state.relying_party().ok_or(StatusCode::NOT_FOUND)?;
+
+    let credentials =
+        user::get_webauthn_credentials(state.storage(), &username).map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn credentials: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let challenge = rng::with_crypto_rng(Challenge::generate);
+    let options = relying_party.creation_options(&challenge, &username, &credentials);
+    user::push_webauthn_registration(state.storage(), &username, challenge).map_err(|err| {
+        tracing::error!("failed to push WebAuthn registration: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(options))
+}
+
+#[derive(Serialize)]
+pub struct RegisterRes {
+    /// Recovery codes, generated with the first second factor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery: Option<Vec<Secret>>,
+}
+
+/// Finish the registration of a security key, the second factor is required
+/// at sign in from now on. The user must have just signed in as the first
+/// recovery codes are returned.
+pub async fn register_finish(
+    Reauthenticated { username }: Reauthenticated,
+    State(state): State<AppState>,
+    Json(req): Json<RegistrationResponse>,
+) -> Result<Json<RegisterRes>, StatusCode> {
+    let relying_party = state.relying_party().ok_or(StatusCode::NOT_FOUND)?;
+
+    let challenge = user::pull_webauthn_registration(state.storage(), &username)
+        .map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn registration: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let credential = relying_party.register(&challenge, &req).map_err(|err| {
+        tracing::warn!("invalid WebAuthn registration of user {username}: {err}");
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    let mut credentials =
+        user::get_webauthn_credentials(state.storage(), &username).map_err(|err| {
+            tracing::error!("failed to retrieve WebAuthn credentials: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if credentials.iter().any(|other| other.id == credential.id) {
+        return Err(StatusCode::CONFLICT);
+    }
+    credentials.push(credential);
+    user::set_webauthn_credentials(state.storage(), &username, &credentials).map_err(|err| {
+        tracing::error!("failed to save WebAuthn credentials: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    tracing::info!("user {username} registered a security key");
+
+    let recovery = recovery::generate_if_missing(&state, &username)?;
+    Ok(Json(RegisterRes { recovery }))
+}
@@ -0,0 +1,64 @@
+//! Audit log
+//!
+//! Security relevant events are saved with the user, the most recent ones
+//! are kept, and emitted with the `audit` target.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    storage::{Storage, UserField},
+    time::DateTime,
+};
+
+/// Number of records kept for each user.
+const MAX_RECORDS: usize = 100;
+
+/// Audited event.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum AuditEvent {
+    /// A new set of recovery codes has been generated.
+    RecoveryCodesGenerated,
+    /// A recovery code has been used in place of the second factor.
+    RecoveryCodeUsed { remaining: usize },
+    /// The password has been verified against the legacy hash.
+    LegacyPasswordVerified,
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RecoveryCodesGenerated => f.write_str("recovery codes generated"),
+            Self::RecoveryCodeUsed { remaining } => {
+                write!(f, "recovery code used, {remaining} remaining")
+            }
+            Self::LegacyPasswordVerified => f.write_str("legacy password verified"),
+        }
+    }
+}
+
+/// Audit record of a user.
+#[derive(Deserialize, Serialize)]
+struct AuditRecord {
+    #[serde(flatten)]
+    event: AuditEvent,
+    created_at: DateTime,
+}
+
+/// Record the event in the audit log of the user.
+pub fn record(storage: &Storage, username: &str, event: AuditEvent) -> Result<()> {
+    tracing::info!(target: "audit", "user {username}: {event}");
+
+    let mut records = storage
+        .get_user::<Vec<AuditRecord>>(username, UserField::Audit)?
+        .unwrap_or_default();
+    records.push(AuditRecord {
+        event,
+        created_at: DateTime::now(),
+    });
+    if records.len() > MAX_RECORDS {
+        records.drain(..records.len() - MAX_RECORDS);
+    }
+    storage.set_user(username, UserField::Audit, &records)
+}
@@ -0,0 +1,393 @@
+//! Backup archives
+//!
+//! The archive contains the records of the users (password file, email,
+//! second factors and audit log), encrypted with the backup key. The header is
+//! in clear and authenticated with the records: it records the version of the
+//! format and the opaque signature of the exporting service. The password files
+//! are usable only with the signature used to register them, they are exported
+//! bound to it and the import checks that each one is known.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{Read, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    opaque::{OpaqueSignatures, PasswordFile},
+    record::Stored,
+    storage::{Storage, StorageKey, UserField},
+    time::DateTime,
+};
+
+/// Version of the archive format.
+const VERSION: u32 = 1;
+
+/// Backup archive, as saved in the file.
+#[derive(Deserialize, Serialize)]
+struct Archive {
+    header: Header,
+    /// Encrypted list of [`User`].
+    users: String,
+}
+
+/// Clear part of the archive.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    /// Version of the archive format.
+    version: u32,
+    /// Identifier of the current opaque signature of the exporting service.
+    signature: String,
+    /// Identifier of the backup key.
+    kid: String,
+    created_at: DateTime,
+}
+
+impl Header {
+    /// Returns the associated data binding the header to the users.
+    fn aad(&self) -> Vec<u8> {
+        let Self {
+            version,
+            signature,
+            kid,
+            created_at,
+        } = self;
+        format!(
+            "{version}:{signature}:{kid}:{}",
+            created_at.unix_timestamp()
+        )
+        .into_bytes()
+    }
+}
+
+/// Records of a user.
+#[derive(Deserialize, Serialize)]
+struct User {
+    username: String,
+    /// Records by field name.
+    records: BTreeMap<String, serde_json::Value>,
+}
+
+/// Write the archive of the users, returns the number of exported users.
+///
+/// The password files saved without setup identifier are bound to the
+/// signature used to register them, the importing service may not know it.
+pub fn export<W: Write>(
+    storage: &Storage,
+    signatures: &OpaqueSignatures,
+    key: &StorageKey,
+    usernames: &[String],
+    writer: W,
+) -> Result<usize> {
+    let mut users = Vec::new();
+    for username in usernames {
+        let mut records = BTreeMap::new();
+        for field in UserField::ALL {
+            let value = match field {
+                UserField::Password => storage
+                    .get_user::<Stored<PasswordFile>>(username, field)?
+                    .map(|Stored(mut password_file)| {
+                        if let Some(signature) = signatures.select(&password_file) {
+                            password_file.bind(signature);
+                        }
+                        serde_json::to_value(Stored(password_file))
+                    })
+                    .transpose()?,
+                _ => storage.get_user::<serde_json::Value>(username, field)?,
+            };
+            if let Some(value) = value {
+                records.insert(field.as_str().to_string(), value);
+            }
+        }
+        if !records.is_empty() {
+            users.push(User {
+                username: username.clone(),
+                records,
+            });
+        }
+    }
+
+    let header = Header {
+        version: VERSION,
+        signature: signatures.current().id().to_string(),
+        kid: key.kid().to_string(),
+        created_at: DateTime::now(),
+    };
+    let aad = header.aad();
+    let payload = Zeroizing::new(serde_json::to_vec(&users)?);
+    let archive = Archive {
+        users: key.seal(&aad, &payload)?,
+        header,
+    };
+    serde_json::to_writer(writer, &archive)?;
+    Ok(users.len())
+}
+
+/// Restore the users from the archive, returns the number of imported users.
+///
+/// Archives with password files bound to an opaque signature unknown to the
+/// service, or not bound at all, are refused unless `any_signature` is set:
+/// their users can sign in only after resetting the password. Users with any
+/// record in the storage, not only a password file, are skipped: their records
+/// would be mixed with the archived ones.
+///
+/// The whole archive is validated before writing any user.
+pub fn import<R: Read>(
+    storage: &Storage,
+    signatures: &OpaqueSignatures,
+    key: &StorageKey,
+    reader: R,
+    any_signature: bool,
+) -> Result<usize> {
+    let archive: Archive = serde_json::from_reader(reader)?;
+    let header = &archive.header;
+    if header.version != VERSION {
+        bail!("unsupported archive version {}", header.version);
+    }
+    if header.kid != key.kid() {
+        bail!("archive encrypted with backup key '{}'", header.kid);
+    }
+
+    let aad = header.aad();
+    let payload = Zeroizing::new(key.open(&aad, &archive.users)?);
+    let users: Vec<User> = serde_json::from_slice(&payload)?;
+
+    let mut usernames = HashSet::new();
+    let mut unknown_setups = 0;
+    let mut records = Vec::with_capacity(users.len());
+    for user in users {
+        let username = user.username;
+        if !usernames.insert(username.clone()) {
+            bail!("user {username} repeated in the archive");
+        }
+        let mut fields = Vec::with_capacity(user.records.len());
+        for (name, value) in user.records {
+            let field = UserField::ALL
+                .into_iter()
+                .find(|field| field.as_str() == name)
+                .ok_or_else(|| anyhow!("unknown record {name} of user {username}"))?;
+            if field == UserField::Password {
+                let Stored(password_file) = serde_json::from_value::<Stored<PasswordFile>>(
+                    value.clone(),
+                )
+                .map_err(|err| anyhow!("invalid password file of user {username}: {err}"))?;
+                let is_known = password_file
+                    .setup()
+                    .is_some_and(|id| signatures.find(id).is_some());
+                if !is_known {
+                    tracing::warn!("password file of user {username} uses an unknown server setup");
+                    unknown_setups += 1;
+                }
+            }
+            fields.push((field, value));
+        }
+        records.push((username, fields));
+    }
+    if unknown_setups > 0 {
+        if !any_signature {
+            bail!(
+                "{unknown_setups} password files use a server setup unknown to the service, \
+                 the archive was exported under opaque signature '{}'",
+                header.signature
+            );
+        }
+        tracing::warn!("{unknown_setups} users must reset their password");
+    }
+
+    let mut count = 0;
+    for (username, fields) in records {
+        if is_present(storage, &username)? {
+            tracing::warn!("user {username} already present, skipped");
+            continue;
+        }
+        for (field, value) in fields {
+            storage.set_user(&username, field, &value)?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Check if the user has any record in the storage.
+fn is_present(storage: &Storage, username: &str) -> Result<bool> {
+    for field in UserField::ALL {
+        if storage.has_user(username, field)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::{
+        opaque::{
+            tests::{signature, unversioned_password_file},
+            OpaqueSignature,
+        },
+        rng,
+        storage::tests::storage,
+        user::{self, UserTable},
+    };
+
+    fn signatures() -> OpaqueSignatures {
+        OpaqueSignatures::new(signature(), Vec::new())
+    }
+
+    fn backup_key() -> StorageKey {
+        assert_ok!(rng::with_crypto_rng(StorageKey::generate).parse())
+    }
+
+    /// Export a user registered before the first rotation, with the oldest
+    /// signature of the exporting service.
+    fn archive(signatures: &OpaqueSignatures, key: &StorageKey) -> Vec<u8> {
+        let signature = signatures.retired().first().unwrap_or(signatures.current());
+        let source = storage();
+        let password_file = unversioned_password_file(signature);
+        assert_ok!(source.register_user_password("user", password_file));
+        assert_ok!(source.set_user("user", UserField::Email, &"user@example.com"));
+
+        let mut archive = Vec::new();
+        let usernames = ["user".to_string(), "missing".to_string()];
+        let count = assert_ok!(export(&source, signatures, key, &usernames, &mut archive));
+        assert_eq!(count, 1);
+        archive
+    }
+
+    /// Replace the users of the archive.
+    fn replace_users(archive: &[u8], key: &StorageKey, users: serde_json::Value) -> Vec<u8> {
+        let mut archive: Archive = assert_ok!(serde_json::from_slice(archive));
+        let payload = assert_ok!(serde_json::to_vec(&users));
+        archive.users = assert_ok!(key.seal(&archive.header.aad(), &payload));
+        assert_ok!(serde_json::to_vec(&archive))
+    }
+
+    #[test]
+    fn restore_exported_users() {
+        let signatures = signatures();
+        let key = backup_key();
+        let archive = archive(&signatures, &key);
+
+        let target = storage();
+        let count = assert_ok!(import(&target, &signatures, &key, &*archive, false));
+        assert_eq!(count, 1);
+        let email = assert_ok!(target.get_user::<String>("user", UserField::Email));
+        assert_eq!(assert_some!(email), "user@example.com");
+        assert_none!(assert_ok!(
+            target.get_user::<String>("user", UserField::Totp)
+        ));
+        let password_file = assert_some!(assert_ok!(user::get_password_file(&target, "user")));
+        assert_eq!(password_file.setup(), Some(signatures.current().id()));
+    }
+
+    #[test]
+    fn refuse_other_signature() {
+        let key = backup_key();
+        let archive = archive(&signatures(), &key);
+
+        let target = storage();
+        assert_err!(import(&target, &signatures(), &key, &*archive, false));
+        assert!(!assert_ok!(target.user_is_registered("user")));
+        let count = assert_ok!(import(&target, &signatures(), &key, &*archive, true));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn refuse_retired_signature_of_exporter() {
+        let current = rng::with_crypto_rng(OpaqueSignature::generate);
+        let retired = rng::with_crypto_rng(OpaqueSignature::generate);
+        let source_signatures = OpaqueSignatures::new(
+            assert_ok!(OpaqueSignature::new(&current)),
+            vec![assert_ok!(OpaqueSignature::new(&retired))],
+        );
+        let key = backup_key();
+        let archive = archive(&source_signatures, &key);
+
+        // the importing service shares only the current signature
+        let target_signatures =
+            OpaqueSignatures::new(assert_ok!(OpaqueSignature::new(&current)), Vec::new());
+        assert_err!(import(
+            &storage(),
+            &target_signatures,
+            &key,
+            &*archive,
+            false
+        ));
+
+        let count = assert_ok!(import(
+            &storage(),
+            &source_signatures,
+            &key,
+            &*archive,
+            false
+        ));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn refuse_unbound_password_files() {
+        let signatures = signatures();
+        let key = backup_key();
+        let archive = archive(&signatures, &key);
+        let password_file = unversioned_password_file(signatures.current());
+        let users = serde_json::json!([{
+            "username": "user",
+            "records": { "password": assert_ok!(serde_json::to_value(Stored(password_file))) },
+        }]);
+        let archive = replace_users(&archive, &key, users);
+
+        assert_err!(import(&storage(), &signatures, &key, &*archive, false));
+    }
+
+    #[test]
+    fn validate_archive_before_writing() {
+        let signatures = signatures();
+        let key = backup_key();
+        let archive = archive(&signatures, &key);
+        let users = serde_json::json!([
+            { "username": "user", "records": { "email": "user@example.com" } },
+            { "username": "other", "records": { "unknown": "value" } },
+        ]);
+        let archive = replace_users(&archive, &key, users);
+
+        let target = storage();
+        assert_err!(import(&target, &signatures, &key, &*archive, false));
+        assert_none!(assert_ok!(
+            target.get_user::<String>("user", UserField::Email)
+        ));
+    }
+
+    #[test]
+    fn skip_users_with_any_record() {
+        let signatures = signatures();
+        let key = backup_key();
+        let archive = archive(&signatures, &key);
+
+        let target = storage();
+        assert_ok!(target.set_user("user", UserField::Email, &"other@example.com"));
+        let count = assert_ok!(import(&target, &signatures, &key, &*archive, false));
+        assert_eq!(count, 0);
+        assert!(!assert_ok!(target.user_is_registered("user")));
+        let email = assert_ok!(target.get_user::<String>("user", UserField::Email));
+        assert_eq!(assert_some!(email), "other@example.com");
+    }
+
+    #[test]
+    fn refuse_tampered_header() {
+        let signatures = signatures();
+        let key = backup_key();
+        let mut archive: serde_json::Value =
+            assert_ok!(serde_json::from_slice(&archive(&signatures, &key)));
+        archive["header"]["created_at"] = serde_json::json!("2000-01-01T00:00:00Z");
+        let archive = assert_ok!(serde_json::to_vec(&archive));
+
+        assert_err!(import(&storage(), &signatures, &key, &*archive, false));
+    }
+}
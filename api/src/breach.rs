@@ -0,0 +1,39 @@
+//! Breached password filter
+//!
+//! The filter is built from a list of breached passwords, its format is
+//! shared with the client by [`fresh_auth_suite::breach`].
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use fresh_auth_suite::breach::BreachFilter;
+
+/// Build the filter from a file with one password per line.
+pub fn from_file(path: &Path, false_positive_rate: f64) -> Result<BreachFilter> {
+    let open = || {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open {}", path.display()))
+    };
+
+    // the file is read twice, to avoid to keep all the passwords in memory
+    let mut capacity = 0;
+    for line in open()?.lines() {
+        if !line?.is_empty() {
+            capacity += 1;
+        }
+    }
+
+    let mut filter = BreachFilter::with_capacity(capacity, false_positive_rate)?;
+    for line in open()?.lines() {
+        let line = line?;
+        if !line.is_empty() {
+            filter.insert(&line);
+        }
+    }
+    Ok(filter)
+}
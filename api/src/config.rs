@@ -1,35 +1,250 @@
 use std::{
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use figment::{
     providers::{Env, Format, Serialized, Toml},
+    value::{Uncased, UncasedStr},
     Figment,
 };
 use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use crate::{
+    mailer::ConfigMail,
+    opaque::{ConfigOpaque, KsfParams},
+    policy::PasswordPolicy,
+    storage::StorageBackend,
+    token::ConfigToken,
+    webauthn::ConfigWebauthn,
+};
+
+/// Secret value, wiped from memory when dropped.
+pub type Secret = Zeroizing<String>;
+
+/// Environment variable with the path of the systemd credentials directory.
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
 
 #[derive(Deserialize)]
 pub struct Config {
     /// Listen address
     pub listen: SocketAddr,
+    /// Listen address of the metrics endpoint, disabled when missing.
+    pub metrics: Option<SocketAddr>,
     /// Username of administrator.
     pub admin: String,
+    /// Name of the service, shown by the authenticator apps.
+    pub issuer: String,
     /// Path to database.
     pub storage: PathBuf,
+    /// Backend of the storage.
+    #[serde(default)]
+    pub backend: StorageBackend,
     /// Private keys.
     pub key: ConfigKey,
+    /// Service tokens, indexed by name.
+    #[serde(default)]
+    pub tokens: HashMap<String, ConfigToken>,
+    /// Key stretching parameters for new registrations.
+    #[serde(default)]
+    pub ksf: KsfParams,
+    /// Server identity and context bound to the key exchange.
+    #[serde(default)]
+    pub opaque: ConfigOpaque,
+    /// Path to the breached password filter, served to the clients.
+    pub breach: Option<PathBuf>,
+    /// Password policy, enforced by the clients.
+    #[serde(default)]
+    pub password: PasswordPolicy,
+    /// WebAuthn relying party, security keys are disabled when missing.
+    pub webauthn: Option<ConfigWebauthn>,
+    /// Mailer of the account emails, emails are not sent when missing.
+    pub mail: Option<ConfigMail>,
+    /// Accept the password hashes imported from another system, the users
+    /// are registered with OPAQUE at their first sign in.
+    #[serde(default)]
+    pub legacy: bool,
 }
 
+/// Private keys.
+///
+/// Each key can be given inline or read from a file (`*_file` variants), when
+/// both are missing the key is searched in the systemd credentials directory.
+/// The keys are taken out of the configuration when loaded, so that the
+/// inline values are wiped as soon as they are parsed.
 #[derive(Deserialize)]
 pub struct ConfigKey {
     /// Opaque signature.
-    pub opaque: String,
+    opaque: Option<Secret>,
+    /// Path to the file containing the opaque signature.
+    opaque_file: Option<PathBuf>,
     /// Invitation key, used to sign generated user invitation.
-    pub invitation: String,
-    /// Session key, used to sign session id.
-    pub session: String,
+    invitation: Option<Secret>,
+    /// Path to the file containing the invitation key.
+    invitation_file: Option<PathBuf>,
+    /// Storage key, used to encrypt the stored values.
+    storage: Option<Secret>,
+    /// Path to the file containing the storage key.
+    storage_file: Option<PathBuf>,
+    /// Backup key, used to encrypt the exported users.
+    backup: Option<Secret>,
+    /// Path to the file containing the backup key.
+    backup_file: Option<PathBuf>,
+    /// Retired keys, accepted only for verification.
+    #[serde(default)]
+    retired: ConfigRetiredKey,
+}
+
+/// Retired keys, each one can be given inline or read from a file.
+#[derive(Default, Deserialize)]
+pub struct ConfigRetiredKey {
+    /// Previous opaque signatures, users registered with them are migrated to
+    /// the current one at their next sign in. They are listed from the
+    /// oldest: the password files saved without setup identifier are bound
+    /// to the first one.
+    #[serde(default)]
+    opaque: Vec<Secret>,
+    /// Paths to the files containing the previous opaque signatures, listed
+    /// after the inline ones.
+    #[serde(default)]
+    opaque_file: Vec<PathBuf>,
+    /// Previous invitation keys, used to verify outstanding invitations.
+    #[serde(default)]
+    invitation: Vec<Secret>,
+    /// Paths to the files containing the previous invitation keys.
+    #[serde(default)]
+    invitation_file: Vec<PathBuf>,
+    /// Previous storage keys, the data key is wrapped again with the current
+    /// one at startup.
+    #[serde(default)]
+    storage: Vec<Secret>,
+    /// Paths to the files containing the previous storage keys.
+    #[serde(default)]
+    storage_file: Vec<PathBuf>,
+}
+
+impl ConfigKey {
+    /// Returns the opaque signature.
+    pub fn opaque(&mut self) -> Result<Secret> {
+        load_secret("opaque", self.opaque.take(), self.opaque_file.as_deref())
+    }
+
+    /// Returns the invitation key.
+    pub fn invitation(&mut self) -> Result<Secret> {
+        load_secret(
+            "invitation",
+            self.invitation.take(),
+            self.invitation_file.as_deref(),
+        )
+    }
+
+    /// Returns the storage key.
+    pub fn storage(&mut self) -> Result<Secret> {
+        load_secret("storage", self.storage.take(), self.storage_file.as_deref())
+    }
+
+    /// Returns the backup key.
+    pub fn backup(&mut self) -> Result<Secret> {
+        load_secret("backup", self.backup.take(), self.backup_file.as_deref())
+    }
+
+    /// Returns the retired opaque signatures.
+    pub fn retired_opaque(&mut self) -> Result<Vec<Secret>> {
+        let retired = &mut self.retired;
+        load_retired("opaque", &mut retired.opaque, &retired.opaque_file)
+    }
+
+    /// Returns the retired invitation keys.
+    pub fn retired_invitation(&mut self) -> Result<Vec<Secret>> {
+        let retired = &mut self.retired;
+        load_retired(
+            "invitation",
+            &mut retired.invitation,
+            &retired.invitation_file,
+        )
+    }
+
+    /// Returns the retired storage keys.
+    pub fn retired_storage(&mut self) -> Result<Vec<Secret>> {
+        let retired = &mut self.retired;
+        load_retired("storage", &mut retired.storage, &retired.storage_file)
+    }
+}
+
+/// Load the secret from the inline value, from the file or from the systemd
+/// credentials directory, in this order.
+fn load_secret(name: &str, value: Option<Secret>, path: Option<&Path>) -> Result<Secret> {
+    match (value, path) {
+        (Some(_), Some(_)) => bail!("both key.{name} and key.{name}_file are set"),
+        (Some(value), None) => Ok(value),
+        (None, Some(path)) => read_secret(name, path),
+        (None, None) => match std::env::var_os(CREDENTIALS_DIRECTORY) {
+            Some(directory) => read_secret(name, &Path::new(&directory).join(name)),
+            None => bail!("missing key.{name} or key.{name}_file"),
+        },
+    }
+}
+
+/// Load the retired secrets, the inline values followed by the files.
+fn load_retired(name: &str, values: &mut Vec<Secret>, paths: &[PathBuf]) -> Result<Vec<Secret>> {
+    let mut secrets = std::mem::take(values);
+    for path in paths {
+        secrets.push(read_secret(&format!("retired.{name}"), path)?);
+    }
+    Ok(secrets)
+}
+
+/// Read the secret from the file.
+///
+/// Relative paths are resolved against the credentials directory, if any.
+fn read_secret(name: &str, path: &Path) -> Result<Secret> {
+    let path = match std::env::var_os(CREDENTIALS_DIRECTORY) {
+        Some(directory) if path.is_relative() => Path::new(&directory).join(path),
+        _ => path.to_path_buf(),
+    };
+
+    check_permissions(&path);
+    let content = std::fs::read_to_string(&path)
+        .map(Zeroizing::new)
+        .with_context(|| format!("failed to read key.{name} from {}", path.display()))?;
+    Ok(Zeroizing::new(content.trim().to_string()))
+}
+
+/// Warn if the secret file is accessible by other users.
+#[cfg(unix)]
+fn check_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    let mode = metadata.permissions().mode();
+    if mode & 0o077 != 0 {
+        tracing::warn!(
+            "secret file {} has permissions {:o}, it should be readable only by the owner",
+            path.display(),
+            mode & 0o777,
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) {}
+
+/// Map the name of an environment variable to a configuration key, the
+/// underscore separates nested keys except for the `_file` suffix.
+fn env_key(key: &UncasedStr) -> Uncased<'_> {
+    let key = key.as_str();
+    let (key, suffix) = match key.len().checked_sub("_file".len()) {
+        Some(n) if key.is_char_boundary(n) && key[n..].eq_ignore_ascii_case("_file") => {
+            key.split_at(n)
+        }
+        _ => (key, ""),
+    };
+    format!("{}{suffix}", key.replace('_', ".")).into()
 }
 
 impl Config {
@@ -37,6 +252,7 @@ impl Config {
         let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
         let default_listen = SocketAddr::new(localhost, 8080);
         let default_admin = "root";
+        let default_issuer = "fresh-auth";
 
         let mut config = Figment::new();
         if let Some(path) = path {
@@ -44,9 +260,10 @@ impl Config {
             config = config.merge(provider);
         }
         config = config
-            .merge(Env::raw().split('_'))
+            .merge(Env::raw().map(env_key))
             .join(Serialized::default("listen", default_listen))
-            .join(Serialized::default("admin", default_admin));
+            .join(Serialized::default("admin", default_admin))
+            .join(Serialized::default("issuer", default_issuer));
         config
             .extract()
             .map_err(|err| anyhow!("failed to load configuration, {}", err.kind))
@@ -62,25 +279,78 @@ mod tests {
     use claym::*;
     use figment::Jail;
 
+    use crate::{mailer::TransportKind, token::Scope};
+
     #[test]
     fn load_configuration_from_environment_variables() {
         Jail::expect_with(|jail| {
             jail.set_env("LISTEN", "[::1]:6789");
+            jail.set_env("METRICS", "[::1]:9090");
             jail.set_env("ADMIN", "xyz");
             jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("BACKEND", "sqlite");
+            jail.set_env("LEGACY", "true");
             jail.set_env("KEY_OPAQUE", "opaque-signature");
             jail.set_env("KEY_INVITATION", "invitation-private-key");
-            jail.set_env("KEY_SESSION", "session-signing-key");
+            jail.set_env("KEY_STORAGE", "storage-key");
+            jail.set_env("KSF_MEMORY", "65536");
+            jail.set_env("KSF_ITERATIONS", "3");
+            jail.set_env("KSF_PARALLELISM", "4");
+            jail.set_env("OPAQUE_IDENTITY", "auth.example.com");
+            jail.set_env("OPAQUE_CONTEXT", "fresh-auth production");
+            jail.set_env("PASSWORD_SCORE", "4");
+            jail.set_env("WEBAUTHN_ID", "example.com");
+            jail.set_env("WEBAUTHN_ORIGIN", "https://example.com");
 
             let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 6789);
 
-            let config = assert_ok!(Config::load(None));
+            let mut config = assert_ok!(Config::load(None));
             assert_eq!(config.listen, addr);
+            let metrics = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 9090);
+            assert_eq!(config.metrics, Some(metrics));
             assert_eq!(config.admin, "xyz");
             assert_eq!(config.storage, Path::new("/tmp/storage.sqlite"));
-            assert_eq!(config.key.opaque, "opaque-signature");
-            assert_eq!(config.key.invitation, "invitation-private-key");
-            assert_eq!(config.key.session, "session-signing-key");
+            assert_eq!(config.backend, StorageBackend::Sqlite);
+            assert!(config.legacy);
+            assert_eq!(*assert_ok!(config.key.opaque()), "opaque-signature");
+            assert_eq!(
+                *assert_ok!(config.key.invitation()),
+                "invitation-private-key"
+            );
+            assert_eq!(*assert_ok!(config.key.storage()), "storage-key");
+            assert!(assert_ok!(config.key.retired_opaque()).is_empty());
+            assert!(assert_ok!(config.key.retired_invitation()).is_empty());
+            assert_eq!(config.ksf.memory, 65536);
+            assert_eq!(config.ksf.iterations, 3);
+            assert_eq!(config.ksf.parallelism, 4);
+            assert_eq!(config.opaque.identity.as_deref(), Some("auth.example.com"));
+            assert_eq!(
+                config.opaque.context.as_deref(),
+                Some("fresh-auth production")
+            );
+            assert_eq!(config.password.score, 4);
+            assert_eq!(config.password.length, 8);
+            let webauthn = assert_some!(config.webauthn);
+            assert_eq!(webauthn.id, "example.com");
+            assert_eq!(webauthn.origin, "https://example.com");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_retired_keys_from_environment_variables() {
+        Jail::expect_with(|jail| {
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("KEY_OPAQUE", "opaque-signature");
+            jail.set_env("KEY_INVITATION", "invitation-private-key");
+            jail.set_env("KEY_RETIRED_INVITATION", "[old-key-1, old-key-2]");
+
+            let mut config = assert_ok!(Config::load(None));
+            let retired = assert_ok!(config.key.retired_invitation());
+            assert_eq!(retired.len(), 2);
+            assert_eq!(*retired[0], "old-key-1");
+            assert_eq!(*retired[1], "old-key-2");
 
             Ok(())
         });
@@ -99,20 +369,173 @@ mod tests {
                 [key]
                 opaque = "opaque-signature"
                 invitation = "invitation-private-key"
-                session = "session-signing-key"
+                storage = "storage-key"
+
+                [key.retired]
+                opaque = ["old-opaque-signature"]
+                invitation = ["old-invitation-key"]
+
+                [tokens.frontend]
+                hash = "token-hash"
+                scopes = ["session:read"]
+
+                [password]
+                length = 12
+                breach = true
+
+                [mail]
+                from = "noreply@example.com"
+                url = "https://example.com"
+                transport = "smtp"
+
+                [mail.smtp]
+                host = "smtp.example.com"
+                port = 587
                 "#,
             ));
 
             let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 6789);
 
             let config_file = Path::new("config.toml");
-            let config = assert_ok!(Config::load(Some(config_file)));
+            let mut config = assert_ok!(Config::load(Some(config_file)));
             assert_eq!(config.listen, addr);
             assert_eq!(config.admin, "xyz");
+            assert_eq!(config.issuer, "fresh-auth");
             assert_eq!(config.storage, Path::new("/tmp/storage.sqlite"));
-            assert_eq!(config.key.opaque, "opaque-signature");
-            assert_eq!(config.key.invitation, "invitation-private-key");
-            assert_eq!(config.key.session, "session-signing-key");
+            assert_eq!(config.backend, StorageBackend::Kv);
+            assert_eq!(*assert_ok!(config.key.opaque()), "opaque-signature");
+            assert_eq!(
+                *assert_ok!(config.key.invitation()),
+                "invitation-private-key"
+            );
+            assert_eq!(*assert_ok!(config.key.storage()), "storage-key");
+            assert_eq!(
+                *assert_ok!(config.key.retired_opaque())[0],
+                "old-opaque-signature"
+            );
+            assert_eq!(
+                *assert_ok!(config.key.retired_invitation())[0],
+                "old-invitation-key"
+            );
+            assert_none!(config.opaque.identity);
+            assert_none!(config.opaque.context);
+            let token = assert_some!(config.tokens.get("frontend"));
+            assert_eq!(token.hash, "token-hash");
+            assert_eq!(token.scopes, [Scope::SessionRead]);
+            assert_eq!(config.password.score, 3);
+            assert_eq!(config.password.length, 12);
+            assert!(config.password.breach);
+            assert_none!(config.webauthn);
+            let mail = assert_some!(config.mail);
+            assert_eq!(mail.transport, TransportKind::Smtp);
+            let smtp = assert_some!(mail.smtp);
+            assert_eq!(smtp.host, "smtp.example.com");
+            assert_eq!(smtp.port, Some(587));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn require_mail_transport() {
+        Jail::expect_with(|jail| {
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("MAIL_FROM", "noreply@example.com");
+            jail.set_env("MAIL_URL", "https://example.com");
+            assert!(Config::load(None).is_err());
+
+            jail.set_env("MAIL_TRANSPORT", "log");
+            let config = assert_ok!(Config::load(None));
+            let mail = assert_some!(config.mail);
+            assert_eq!(mail.transport, TransportKind::Log);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_keys_from_files() {
+        Jail::expect_with(|jail| {
+            assert_ok!(jail.create_file("opaque.key", "opaque-signature\n"));
+            assert_ok!(jail.create_file("invitation.key", "invitation-private-key\n"));
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("KEY_OPAQUE_FILE", "opaque.key");
+            jail.set_env("KEY_INVITATION_FILE", "invitation.key");
+
+            let mut config = assert_ok!(Config::load(None));
+            assert_eq!(*assert_ok!(config.key.opaque()), "opaque-signature");
+            assert_eq!(
+                *assert_ok!(config.key.invitation()),
+                "invitation-private-key"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_retired_keys_from_files() {
+        Jail::expect_with(|jail| {
+            assert_ok!(jail.create_file("old-opaque.key", "old-opaque-signature\n"));
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("KEY_RETIRED_OPAQUE", "[inline-opaque-signature]");
+            jail.set_env("KEY_RETIRED_OPAQUE_FILE", "[old-opaque.key]");
+
+            let mut config = assert_ok!(Config::load(None));
+            let retired = assert_ok!(config.key.retired_opaque());
+            assert_eq!(retired.len(), 2);
+            assert_eq!(*retired[0], "inline-opaque-signature");
+            assert_eq!(*retired[1], "old-opaque-signature");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn take_inline_keys_out_of_configuration() {
+        Jail::expect_with(|jail| {
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("KEY_OPAQUE", "opaque-signature");
+            jail.set_env("KEY_RETIRED_STORAGE", "[old-storage-key]");
+
+            let mut config = assert_ok!(Config::load(None));
+            assert_eq!(*assert_ok!(config.key.opaque()), "opaque-signature");
+            assert!(config.key.opaque().is_err());
+            assert_eq!(assert_ok!(config.key.retired_storage()).len(), 1);
+            assert!(assert_ok!(config.key.retired_storage()).is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_keys_from_credentials_directory() {
+        Jail::expect_with(|jail| {
+            assert_ok!(jail.create_file("opaque", "opaque-signature"));
+            assert_ok!(jail.create_file("invitation", "invitation-private-key"));
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env(CREDENTIALS_DIRECTORY, jail.directory().display());
+
+            let mut config = assert_ok!(Config::load(None));
+            assert_eq!(*assert_ok!(config.key.opaque()), "opaque-signature");
+            assert_eq!(
+                *assert_ok!(config.key.invitation()),
+                "invitation-private-key"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_both_inline_and_file_keys() {
+        Jail::expect_with(|jail| {
+            jail.set_env("STORAGE", "/tmp/storage.sqlite");
+            jail.set_env("KEY_OPAQUE", "opaque-signature");
+            jail.set_env("KEY_OPAQUE_FILE", "opaque.key");
+
+            let mut config = assert_ok!(Config::load(None));
+            assert!(config.key.opaque().is_err());
 
             Ok(())
         });
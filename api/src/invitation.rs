@@ -1,19 +1,24 @@
 use std::str::FromStr;
 
 use anyhow::Result;
-use base64ct::{Base64Url, Encoding};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
 use ed25519_dalek::{
     ed25519::signature::Signer, SecretKey, Signature, SigningKey, SECRET_KEY_LENGTH,
 };
 use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 use crate::time::{DateTime, Duration};
 
+/// Length in bytes of the key identifier.
+const KID_BYTES: usize = 8;
+
 /// Invitation key, used to sign the [`Invitation`].
 pub struct InvitationKey {
     key: SigningKey,
+    kid: String,
 }
 
 impl InvitationKey {
@@ -22,36 +27,47 @@ impl InvitationKey {
         let mut key = SecretKey::default();
         rng.fill_bytes(&mut key);
 
-        Self {
-            key: SigningKey::from_bytes(&key),
-        }
+        Self::from_signing_key(SigningKey::from_bytes(&key))
+    }
+
+    /// Create the invitation key and compute its identifier.
+    fn from_signing_key(key: SigningKey) -> Self {
+        let digest = Sha256::digest(key.verifying_key().as_bytes());
+        let kid = Base64UrlUnpadded::encode_string(&digest[..KID_BYTES]);
+        Self { key, kid }
+    }
+
+    /// Returns the key identifier, derived from the public key.
+    pub fn kid(&self) -> &str {
+        &self.kid
     }
 
     /// Sign an [`Invitation`] and generate a new [`InvitationCode`].
+    ///
+    /// The key identifier is stored in the invitation payload.
     pub fn sign(&self, invitation: &Invitation) -> InvitationCode {
+        let invitation = Invitation {
+            kid: Some(self.kid.clone()),
+            username: invitation.username.clone(),
+            expiration: invitation.expiration,
+        };
         let invitation = serde_json::to_string(&invitation).unwrap();
         let signature = self.key.sign(invitation.as_bytes());
         InvitationCode::from_parts(&invitation, signature)
     }
 
-    /// Verify and [`InvitationCode`] and return the [`Invitation`].
-    pub fn verify(&self, code: &InvitationCode) -> Result<Invitation, InvalidInvitationCode> {
-        let (invitation, signature) = code.into_parts()?;
+    /// Verify the signature of an invitation payload.
+    fn verify_signature(
+        &self,
+        invitation: &str,
+        signature: &Signature,
+    ) -> Result<(), InvalidInvitationCode> {
         self.key
-            .verify(invitation.as_bytes(), &signature)
+            .verify(invitation.as_bytes(), signature)
             .map_err(|err| {
                 tracing::error!("failed to verify invitation: {err}");
-                InvalidInvitationCode
-            })?;
-        let invitation: Invitation = serde_json::from_str(&invitation).map_err(|err| {
-            tracing::error!("invitation payload is not a valid json: {err}");
-            InvalidInvitationCode
-        })?;
-        if invitation.is_expired() {
-            tracing::error!("used expired invitation");
-            return Err(InvalidInvitationCode);
-        }
-        Ok(invitation)
+                InvalidInvitationCode::Signature
+            })
     }
 
     /// Returns an object for printing the invitation key.
@@ -92,21 +108,79 @@ impl FromStr for InvitationKey {
     type Err = InvalidInvitationKey;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut key = SecretKey::default();
+        let mut key = Zeroizing::new(SecretKey::default());
         let decoded_key =
-            Base64Url::decode(s.as_bytes(), &mut key).map_err(|_| InvalidInvitationKey)?;
+            Base64Url::decode(s.as_bytes(), key.as_mut()).map_err(|_| InvalidInvitationKey)?;
         if decoded_key.len() != SECRET_KEY_LENGTH {
             return Err(InvalidInvitationKey);
         }
 
         let key = SigningKey::from_bytes(&key);
-        Ok(Self { key })
+        Ok(Self::from_signing_key(key))
+    }
+}
+
+/// Set of invitation keys.
+///
+/// Only the active key is used to sign new invitations, the retired ones are
+/// kept to verify the invitations still outstanding. The key used for the
+/// verification is selected using the key identifier stored in the invitation.
+pub struct InvitationKeys {
+    active: InvitationKey,
+    retired: Vec<InvitationKey>,
+}
+
+impl InvitationKeys {
+    /// Create a new set of keys from the active one and the retired ones.
+    pub fn new(active: InvitationKey, retired: Vec<InvitationKey>) -> Self {
+        Self { active, retired }
+    }
+
+    /// Sign an [`Invitation`] using the active key.
+    pub fn sign(&self, invitation: &Invitation) -> InvitationCode {
+        self.active.sign(invitation)
+    }
+
+    /// Verify and [`InvitationCode`] and return the [`Invitation`].
+    pub fn verify(&self, code: &InvitationCode) -> Result<Invitation, InvalidInvitationCode> {
+        let (invitation, signature) = code.into_parts()?;
+        let payload: Invitation = serde_json::from_str(&invitation).map_err(|err| {
+            tracing::error!("invitation payload is not a valid json: {err}");
+            InvalidInvitationCode::Malformed
+        })?;
+
+        // invitations signed before the introduction of key identifiers are
+        // verified with the active key
+        let key = match payload.kid.as_deref() {
+            Some(kid) => self.find(kid).ok_or_else(|| {
+                tracing::error!("invitation signed with unknown key '{kid}'");
+                InvalidInvitationCode::UnknownKey
+            })?,
+            None => &self.active,
+        };
+        key.verify_signature(&invitation, &signature)?;
+
+        if payload.is_expired() {
+            tracing::error!("used expired invitation");
+            return Err(InvalidInvitationCode::Expired);
+        }
+        Ok(payload)
+    }
+
+    /// Search the key with the given identifier.
+    fn find(&self, kid: &str) -> Option<&InvitationKey> {
+        std::iter::once(&self.active)
+            .chain(&self.retired)
+            .find(|key| key.kid() == kid)
     }
 }
 
 /// Sign up invitation.
 #[derive(Deserialize, Serialize)]
 pub struct Invitation {
+    /// Identifier of the key used to sign the invitation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
     /// Invited username (should match on registration).
     pub username: String,
     /// Expiration of the this invitation.
@@ -124,6 +198,9 @@ impl Invitation {
     /// Admin invitation lifetime (10 minutes).
     const ADMIN_LIFETIME: Duration = Duration::minutes(10);
 
+    /// Password reset lifetime (1 hour).
+    const RESET_LIFETIME: Duration = Duration::hours(1);
+
     /// Create a new invitation for the user and with default lifetime.
     pub fn new(username: &str) -> Self {
         Self::with_lifetime(username, Self::DEFAULT_LIFETIME)
@@ -134,14 +211,25 @@ impl Invitation {
         Self::with_lifetime(username, Self::ADMIN_LIFETIME)
     }
 
+    /// Create a new invitation to reset the password of a registered user.
+    pub fn reset(username: &str) -> Self {
+        Self::with_lifetime(username, Self::RESET_LIFETIME)
+    }
+
     /// Create a new invitation for the user and with the given lifetime.
     fn with_lifetime(username: &str, lifetime: Duration) -> Self {
         Self {
+            kid: None,
             username: username.to_string(),
             expiration: DateTime::now() + lifetime,
         }
     }
 
+    /// Returns the expiration of the invitation.
+    pub fn expiration(&self) -> DateTime {
+        self.expiration
+    }
+
     /// Check if the invitation is expired.
     fn is_expired(&self) -> bool {
         self.expiration < DateTime::now()
@@ -166,6 +254,13 @@ impl std::fmt::Display for InvitationCode {
 }
 
 impl InvitationCode {
+    /// Returns the digest of the code, used to remember the redeemed
+    /// invitations without saving the codes.
+    pub fn digest(&self) -> String {
+        let digest = Sha256::digest(self.0.as_bytes());
+        Base64UrlUnpadded::encode_string(&digest)
+    }
+
     /// Compose the invitation code from the parts.
     fn from_parts(invitation: &str, signature: Signature) -> Self {
         let invitation = Base64Url::encode_string(invitation.as_bytes());
@@ -176,20 +271,22 @@ impl InvitationCode {
 
     /// Split the invitation code into its parts.
     fn into_parts(&self) -> Result<(String, Signature), InvalidInvitationCode> {
-        let (invitation, signature) = self.0.split_once('.').ok_or(InvalidInvitationCode)?;
+        use InvalidInvitationCode::Malformed;
 
-        let invitation = Base64Url::decode_vec(invitation).map_err(|_| InvalidInvitationCode)?;
-        let invitation = String::from_utf8(invitation).map_err(|_| InvalidInvitationCode)?;
+        let (invitation, signature) = self.0.split_once('.').ok_or(Malformed)?;
+
+        let invitation = Base64Url::decode_vec(invitation).map_err(|_| Malformed)?;
+        let invitation = String::from_utf8(invitation).map_err(|_| Malformed)?;
 
         let mut bytes = [0u8; Signature::BYTE_SIZE];
         let signature_len = Base64Url::decode(signature.as_bytes(), &mut bytes)
-            .map_err(|_| InvalidInvitationCode)?
+            .map_err(|_| Malformed)?
             .len();
         if signature_len != Signature::BYTE_SIZE {
             dbg!(signature_len);
             dbg!(Signature::BYTE_SIZE);
             tracing::error!("inviation signature has wrong length");
-            return Err(InvalidInvitationCode);
+            return Err(Malformed);
         }
         let signature = Signature::from_bytes(&bytes);
 
@@ -198,5 +295,59 @@ impl InvitationCode {
 }
 
 /// An error which can be returned when parsing a [`InvitationCode`].
-#[derive(Clone, Copy, Debug)]
-pub struct InvalidInvitationCode;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidInvitationCode {
+    /// The code can not be decoded.
+    Malformed,
+    /// The code is signed with a key unknown to the service.
+    UnknownKey,
+    /// The signature does not match the invitation.
+    Signature,
+    /// The invitation is expired.
+    Expired,
+}
+
+impl InvalidInvitationCode {
+    /// Returns the name of the failure reason.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::UnknownKey => "unknown_key",
+            Self::Signature => "signature",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::rng;
+
+    #[test]
+    fn verify_invitation_signed_with_retired_key() {
+        let retired = rng::with_crypto_rng(InvitationKey::generate);
+        let code = retired.sign(&Invitation::new("user"));
+
+        let active = rng::with_crypto_rng(InvitationKey::generate);
+        let keys = InvitationKeys::new(active, vec![retired]);
+
+        let invitation = assert_ok!(keys.verify(&code));
+        assert_eq!(invitation.username, "user");
+    }
+
+    #[test]
+    fn reject_invitation_signed_with_unknown_key() {
+        let unknown = rng::with_crypto_rng(InvitationKey::generate);
+        let code = unknown.sign(&Invitation::new("user"));
+
+        let active = rng::with_crypto_rng(InvitationKey::generate);
+        let keys = InvitationKeys::new(active, vec![]);
+
+        let err = assert_err!(keys.verify(&code));
+        assert_eq!(err, InvalidInvitationCode::UnknownKey);
+    }
+}
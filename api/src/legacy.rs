@@ -0,0 +1,200 @@
+//! Legacy password hashes
+//!
+//! Users of another system are imported with their Argon2 hash, in the PHC
+//! string format. At the first sign in the client sends the password once, it
+//! is verified against the hash and the user registers with OPAQUE, removing
+//! the hash.
+//!
+//! The parameters of the hashes are chosen by the other system, the imported
+//! ones are capped so a sign in cannot exhaust the memory of the server. The
+//! users without a hash are verified against a decoy with the most common
+//! parameters of the imported hashes: they cannot be told apart by the
+//! response time from the users whose hash has these parameters.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{password_hash::PasswordHash, Algorithm, Argon2, Params, PasswordVerifier};
+use serde::{Deserialize, Serialize};
+
+/// Maximum memory cost of the imported hashes, in KiB.
+const MAX_M_COST: u32 = 256 * 1024;
+/// Maximum number of iterations of the imported hashes.
+const MAX_T_COST: u32 = 16;
+/// Maximum degree of parallelism of the imported hashes.
+const MAX_P_COST: u32 = 16;
+
+/// Salt of the decoy hash.
+const DECOY_SALT: &str = "ZGVjb3lkZWNveWRlY295ZA";
+/// Output of the decoy hash, no password matches it.
+const DECOY_OUTPUT: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+/// Argon2 password hash in the PHC string format.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct LegacyHash(String);
+
+impl LegacyHash {
+    /// Parse the password hash, only the Argon2 variants with parameters
+    /// within the limits are accepted.
+    pub fn new(hash: &str) -> Result<Self> {
+        let parsed = PasswordHash::new(hash).map_err(|err| anyhow!("invalid hash: {err}"))?;
+        Algorithm::try_from(parsed.algorithm)
+            .map_err(|_| anyhow!("unsupported algorithm {}", parsed.algorithm))?;
+        let params =
+            Params::try_from(&parsed).map_err(|err| anyhow!("invalid parameters: {err}"))?;
+        if params.m_cost() > MAX_M_COST {
+            bail!("memory cost {} above {MAX_M_COST}", params.m_cost());
+        }
+        if params.t_cost() > MAX_T_COST {
+            bail!("iterations {} above {MAX_T_COST}", params.t_cost());
+        }
+        if params.p_cost() > MAX_P_COST {
+            bail!("parallelism {} above {MAX_P_COST}", params.p_cost());
+        }
+        Ok(Self(hash.to_string()))
+    }
+
+    /// Returns the hash verified in place of a missing one, with the most
+    /// common parameters of the hashes, the default ones without hashes. No
+    /// password matches it.
+    pub fn decoy(hashes: &[LegacyHash]) -> Self {
+        let mut counts = HashMap::<_, usize>::new();
+        for params in hashes.iter().filter_map(LegacyHash::params) {
+            let params = (params.m_cost(), params.t_cost(), params.p_cost());
+            *counts.entry(params).or_default() += 1;
+        }
+        // on a tie, the most costly parameters are kept
+        let (m_cost, t_cost, p_cost) = counts
+            .into_iter()
+            .max_by_key(|&((m_cost, t_cost, _), count)| {
+                (count, u64::from(m_cost) * u64::from(t_cost))
+            })
+            .map(|(params, _)| params)
+            .unwrap_or((
+                Params::DEFAULT_M_COST,
+                Params::DEFAULT_T_COST,
+                Params::DEFAULT_P_COST,
+            ));
+        Self(format!(
+            "$argon2id$v=19$m={m_cost},t={t_cost},p={p_cost}${DECOY_SALT}${DECOY_OUTPUT}"
+        ))
+    }
+
+    /// Verify the password against the hash, using its own parameters.
+    pub fn verify(&self, password: &str) -> bool {
+        PasswordHash::new(&self.0).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    /// Returns the parameters of the hash.
+    fn params(&self) -> Option<Params> {
+        let hash = PasswordHash::new(&self.0).ok()?;
+        Params::try_from(&hash).ok()
+    }
+}
+
+/// Read the hashes from a file with one `username:hash` pair per line.
+pub fn read_file(path: &Path) -> Result<Vec<(String, LegacyHash)>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+
+    let mut hashes = Vec::new();
+    for (line, number) in BufReader::new(file).lines().zip(1..) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (username, hash) = line
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("missing username at line {number}"))?;
+        let hash = LegacyHash::new(hash.trim()).with_context(|| format!("at line {number}"))?;
+        hashes.push((username.to_string(), hash));
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use argon2::{password_hash::SaltString, PasswordHasher};
+    use claym::*;
+    use rand::rngs::OsRng;
+
+    fn hash(password: &str) -> String {
+        hash_with(password, 8, 1, 1)
+    }
+
+    fn hash_with(password: &str, m_cost: u32, t_cost: u32, p_cost: u32) -> String {
+        let params = assert_ok!(Params::new(m_cost, t_cost, p_cost, None));
+        let argon2 = Argon2::new(Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = assert_ok!(argon2.hash_password(password.as_bytes(), &salt));
+        hash.to_string()
+    }
+
+    #[test]
+    fn verify_with_hash_parameters() {
+        let hash = assert_ok!(LegacyHash::new(&hash("password")));
+        assert!(hash.verify("password"));
+        assert!(!hash.verify("wrong password"));
+    }
+
+    #[test]
+    fn reject_other_algorithms() {
+        let pbkdf2 =
+            "$pbkdf2-sha256$i=1000$c2FsdHNhbHQ$3p6Sg7KMjJ7kwRrJ8oBjhC2S8xlhJkcvwoTIPYqJLvY";
+        assert!(LegacyHash::new(pbkdf2).is_err());
+        assert!(LegacyHash::new("password").is_err());
+    }
+
+    #[test]
+    fn reject_excessive_parameters() {
+        assert_ok!(LegacyHash::new(&hash_with("password", 64, 2, 2)));
+        assert!(LegacyHash::new(&hash_with("password", 8, MAX_T_COST + 1, 1)).is_err());
+        assert!(LegacyHash::new(&hash_with(
+            "password",
+            8 * (MAX_P_COST + 1),
+            1,
+            MAX_P_COST + 1
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn decoy_matches_no_password() {
+        let decoy = LegacyHash::decoy(&[]);
+        assert_ok!(LegacyHash::new(&decoy.0));
+        let params = assert_some!(decoy.params());
+        assert_eq!(params.m_cost(), Params::DEFAULT_M_COST);
+        assert!(!decoy.verify(""));
+        assert!(!decoy.verify("password"));
+    }
+
+    #[test]
+    fn decoy_with_most_common_parameters() {
+        let hashes = [
+            hash_with("password", 16, 2, 1),
+            hash_with("password", 8, 1, 1),
+            hash_with("password", 16, 2, 1),
+            hash_with("password", 32, 3, 2),
+        ]
+        .map(|hash| assert_ok!(LegacyHash::new(&hash)));
+        let decoy = LegacyHash::decoy(&hashes);
+        assert_ok!(LegacyHash::new(&decoy.0));
+        let params = assert_some!(decoy.params());
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (16, 2, 1)
+        );
+        assert!(!decoy.verify("password"));
+    }
+}
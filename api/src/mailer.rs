@@ -0,0 +1,263 @@
+//! Account emails
+//!
+//! The emails are sent with SMTP, dropped in a directory (one `.eml` file for
+//! each message, useful for local testing) or only logged, without their body
+//! which holds the codes. The transport is always chosen explicitly. Users
+//! without an email address are skipped.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+
+use crate::{config::Secret, invitation::InvitationCode, session::SessionId, time::DateTime};
+
+/// Mailer configuration.
+#[derive(Deserialize)]
+pub struct ConfigMail {
+    /// Sender of the emails, like `Fresh Auth <noreply@example.com>`.
+    pub from: String,
+    /// Public URL of the application, used to build the links.
+    pub url: String,
+    /// Transport of the emails.
+    pub transport: TransportKind,
+    /// SMTP server, required by the `smtp` transport.
+    pub smtp: Option<ConfigSmtp>,
+    /// Destination directory, required by the `file` transport.
+    pub dir: Option<PathBuf>,
+}
+
+/// SMTP server configuration.
+#[derive(Deserialize)]
+pub struct ConfigSmtp {
+    /// Host name of the server, the connection is always encrypted.
+    pub host: String,
+    /// Port of the server, the submission port is used when missing.
+    pub port: Option<u16>,
+    /// Username of the server account.
+    pub username: Option<String>,
+    /// Password of the server account.
+    pub password: Option<Secret>,
+}
+
+/// Transport of the emails.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Send the emails to the SMTP server.
+    Smtp,
+    /// Write the emails in the destination directory.
+    File,
+    /// Log the recipients and the subject of the emails, nothing is
+    /// delivered.
+    Log,
+}
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Log,
+}
+
+/// Email sent to a user.
+pub enum Mail<'a> {
+    /// Invitation to sign up.
+    Invitation {
+        username: &'a str,
+        code: &'a InvitationCode,
+    },
+    /// Link to choose a new password.
+    Reset {
+        username: &'a str,
+        code: &'a InvitationCode,
+    },
+    /// Sign in from a new device.
+    Signin { username: &'a str, at: DateTime },
+    /// Link to confirm a new email address.
+    ConfirmEmail {
+        username: &'a str,
+        token: &'a SessionId,
+    },
+}
+
+/// Mailer of the account emails.
+pub struct Mailer {
+    from: Mailbox,
+    url: String,
+    transport: Transport,
+}
+
+impl Mailer {
+    /// Create the mailer from the configuration.
+    pub fn new(config: &ConfigMail) -> Result<Self> {
+        let from = config.from.parse().context("invalid sender of emails")?;
+        let transport = match config.transport {
+            TransportKind::Smtp => {
+                let Some(smtp) = &config.smtp else {
+                    bail!("missing configuration of SMTP server");
+                };
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?;
+                if let Some(port) = smtp.port {
+                    builder = builder.port(port);
+                }
+                if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+                    let credentials = Credentials::new(username.clone(), password.to_string());
+                    builder = builder.credentials(credentials);
+                }
+                Transport::Smtp(builder.build())
+            }
+            TransportKind::File => {
+                let Some(dir) = &config.dir else {
+                    bail!("missing destination directory of emails");
+                };
+                std::fs::create_dir_all(dir)?;
+                Transport::File(AsyncFileTransport::new(dir))
+            }
+            TransportKind::Log => Transport::Log,
+        };
+        Ok(Self {
+            from,
+            url: config.url.trim_end_matches('/').to_string(),
+            transport,
+        })
+    }
+
+    /// Compose the email to the given address.
+    pub fn message(&self, to: &str, mail: Mail<'_>) -> Result<Message> {
+        let (subject, body) = self.render(&mail);
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse().context("invalid email address")?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?;
+        Ok(message)
+    }
+
+    /// Send the composed email.
+    pub async fn send(&self, message: Message) -> Result<()> {
+        match &self.transport {
+            Transport::Smtp(transport) => {
+                transport.send(message).await?;
+            }
+            Transport::File(transport) => {
+                transport.send(message).await?;
+            }
+            Transport::Log => {
+                // the body holds the codes, it must not end up in the logs
+                let to = message
+                    .envelope()
+                    .to()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let subject = message.headers().get_raw("Subject").unwrap_or_default();
+                tracing::info!("email to {to}: {subject}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the subject and the body of the email.
+    fn render(&self, mail: &Mail<'_>) -> (String, String) {
+        let url = &self.url;
+        match mail {
+            Mail::Invitation { username, code } => (
+                "Invitation".to_string(),
+                format!(
+                    "Hello {username},\n\n\
+                     you have been invited, choose your password at the following link:\n\n\
+                     {url}/signup?code={code}\n\n\
+                     The link expires in 24 hours.\n"
+                ),
+            ),
+            Mail::Reset { username, code } => (
+                "Password reset".to_string(),
+                format!(
+                    "Hello {username},\n\n\
+                     a password reset has been requested, choose a new password at the following link:\n\n\
+                     {url}/signup?code={code}\n\n\
+                     The link expires in 1 hour. If you did not request it, ignore this email.\n"
+                ),
+            ),
+            Mail::Signin { username, at } => (
+                "Sign in from a new device".to_string(),
+                format!(
+                    "Hello {username},\n\n\
+                     your account has been used to sign in from a new device at {at}.\n\n\
+                     If it was not you, change your password at {url}.\n"
+                ),
+            ),
+            Mail::ConfirmEmail { username, token } => (
+                "Confirm your email address".to_string(),
+                format!(
+                    "Hello {username},\n\n\
+                     confirm this email address for your account at the following link:\n\n\
+                     {url}/email?token={}\n\n\
+                     The link expires in 1 hour. If you did not request it, ignore this email.\n",
+                    token.display()
+                ),
+            ),
+        }
+    }
+}
+
+/// Check if the email address is valid.
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<lettre::Address>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::invitation::{Invitation, InvitationKey};
+    use crate::rng;
+
+    #[test]
+    fn render_reset_link() {
+        let config = ConfigMail {
+            from: "Fresh Auth <noreply@example.com>".to_string(),
+            url: "https://example.com/".to_string(),
+            transport: TransportKind::Log,
+            smtp: None,
+            dir: None,
+        };
+        let mailer = assert_ok!(Mailer::new(&config));
+
+        let key = rng::with_crypto_rng(InvitationKey::generate);
+        let code = key.sign(&Invitation::reset("xyz"));
+        let (_, body) = mailer.render(&Mail::Reset {
+            username: "xyz",
+            code: &code,
+        });
+        assert!(body.contains(&format!("https://example.com/signup?code={code}")));
+    }
+
+    #[test]
+    fn validate_email_addresses() {
+        assert!(is_valid_address("user@example.com"));
+        assert!(!is_valid_address("user"));
+        assert!(!is_valid_address("user@"));
+    }
+
+    #[test]
+    fn require_transport_configuration() {
+        let config = ConfigMail {
+            from: "noreply@example.com".to_string(),
+            url: "https://example.com".to_string(),
+            transport: TransportKind::Smtp,
+            smtp: None,
+            dir: None,
+        };
+        assert_err!(Mailer::new(&config));
+    }
+}
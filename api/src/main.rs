@@ -1,27 +1,80 @@
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{Parser, Subcommand};
 
-use crate::{config::Config, invitation::InvitationKey, opaque::OpaqueSignature};
+use crate::{
+    config::Config,
+    invitation::InvitationKey,
+    opaque::{OpaqueSignature, OpaqueSignatures},
+    storage::{self, Storage, StorageBackend, StorageKey, StorageKeys},
+    token::ServiceToken,
+    user::{self, UserTable},
+};
 
 mod api;
+mod audit;
+mod backup;
+mod breach;
 mod config;
 mod invitation;
+mod legacy;
+mod mailer;
+mod metrics;
 mod opaque;
+mod policy;
+mod record;
+mod recovery;
 mod rng;
 mod session;
+mod storage;
+mod throttle;
 mod time;
+mod token;
+mod totp;
 mod user;
+mod webauthn;
 
 fn main() -> Result<()> {
     let args = Args::parse();
     match args.command {
-        Commands::Genkey { kind } => genkey(kind),
+        Commands::Genkey { kind } => genkey(kind)?,
         Commands::Run(cmd) => {
             let config = Config::load(cmd.config.as_deref())?;
             run(config)?;
         }
+        Commands::BreachFilter(cmd) => breach_filter(cmd)?,
+        Commands::Rekey(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            rekey(config)?;
+        }
+        Commands::IndexUsers(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            index_users(config, cmd)?;
+        }
+        Commands::MigrateStorage(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            migrate_storage(config, cmd)?;
+        }
+        Commands::Export(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            export(config, cmd)?;
+        }
+        Commands::Import(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            import(config, cmd)?;
+        }
+        Commands::Migrate(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            migrate(config, cmd)?;
+        }
+        Commands::ImportLegacy(cmd) => {
+            let config = Config::load(cmd.config.as_deref())?;
+            import_legacy(config, cmd)?;
+        }
     }
     Ok(())
 }
@@ -41,17 +94,48 @@ enum Commands {
     },
     /// Starts the service and blocks indefinitely.
     Run(RunArgs),
+    /// Build the breached password filter from a list of passwords.
+    BreachFilter(BreachFilterArgs),
+    /// Wrap the data key with the current storage key, it can be used while
+    /// the service is running.
+    Rekey(RunArgs),
+    /// Add the given users to the list of users of the key-value storage,
+    /// required by the storages created before the list.
+    IndexUsers(IndexUsersArgs),
+    /// Copy the users, their sessions and the redeemed invitations to a new
+    /// storage, the service should be stopped.
+    MigrateStorage(MigrateStorageArgs),
+    /// Rewrite the password files and the sessions in the current format,
+    /// older records are otherwise upgraded when read. The records are
+    /// encrypted and the storage is sealed: the values without encryption
+    /// are refused afterwards.
+    Migrate(MigrateArgs),
+    /// Write the users to an archive encrypted with the backup key.
+    Export(ExportArgs),
+    /// Restore the users from an archive, the users already present are
+    /// skipped.
+    Import(ImportArgs),
+    /// Import the Argon2 password hashes of another system, the users are
+    /// registered with OPAQUE at their first sign in.
+    ImportLegacy(ImportLegacyArgs),
 }
 
 #[derive(Subcommand)]
 enum GenkeyKind {
     /// Generate a random key to sign invitation.
     Invitation,
-    /// Generate a new opaque signature.
+    /// Generate a new opaque signature, the public key to be pinned by the
+    /// clients is written to stderr.
     Signature,
+    /// Generate a random key to encrypt the storage.
+    Storage,
+    /// Generate a random key to encrypt the backup archives.
+    Backup,
+    /// Generate a random service token and the hash to be configured.
+    Token,
 }
 
-fn genkey(kind: GenkeyKind) {
+fn genkey(kind: GenkeyKind) -> Result<()> {
     match kind {
         GenkeyKind::Invitation => {
             let invitation_key = rng::with_crypto_rng(InvitationKey::generate);
@@ -59,9 +143,21 @@ fn genkey(kind: GenkeyKind) {
         }
         GenkeyKind::Signature => {
             let signature = rng::with_crypto_rng(OpaqueSignature::generate);
+            let public_key = OpaqueSignature::new(&signature)?.public_key().to_string();
             println!("{signature}");
+            eprintln!("public key: {public_key}");
+        }
+        GenkeyKind::Storage | GenkeyKind::Backup => {
+            let key = rng::with_crypto_rng(StorageKey::generate);
+            println!("{key}");
+        }
+        GenkeyKind::Token => {
+            let (token, hash) = rng::with_crypto_rng(ServiceToken::generate);
+            println!("token: {token}");
+            println!("hash:  {hash}");
         }
     }
+    Ok(())
 }
 
 #[derive(Parser)]
@@ -78,5 +174,255 @@ fn run(config: Config) -> Result<()> {
         .enable_all()
         .build()
         .expect("failed to build tokio runtime");
-    runtime.block_on(api::serve(&config))
+    runtime.block_on(api::serve(config))
+}
+
+#[derive(Parser)]
+struct BreachFilterArgs {
+    /// File with one breached password per line
+    #[arg(short, long)]
+    input: PathBuf,
+    /// Output filter file
+    #[arg(short, long)]
+    output: PathBuf,
+    /// False positive rate of the filter
+    #[arg(long, default_value_t = 0.001)]
+    false_positive_rate: f64,
+}
+
+fn breach_filter(args: BreachFilterArgs) -> Result<()> {
+    let filter = breach::from_file(&args.input, args.false_positive_rate)?;
+    let mut output = std::fs::File::create(&args.output)?;
+    output.write_all(&filter.to_bytes())?;
+    Ok(())
+}
+
+fn rekey(mut config: Config) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    let keys = StorageKeys::load(&mut config.key)?;
+    Storage::open(config.backend, &config.storage, &keys)?;
+    tracing::info!("data key wrapped with storage key '{}'", keys.current_kid());
+    Ok(())
+}
+
+#[derive(Parser)]
+struct IndexUsersArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Users to add to the list, the ones without records are skipped
+    #[arg(short, long = "user", required = true)]
+    users: Vec<String>,
+}
+
+fn index_users(mut config: Config, args: IndexUsersArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    let storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+
+    let mut usernames = args.users;
+    usernames.push(config.admin);
+    usernames.sort();
+    usernames.dedup();
+
+    let count = storage.index_users(&usernames)?;
+    tracing::info!("{count} users added to the list");
+    Ok(())
+}
+
+#[derive(Parser)]
+struct MigrateStorageArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Backend of the new storage
+    #[arg(short, long, value_enum)]
+    backend: StorageBackend,
+    /// Path to the new storage
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Additional users to copy, besides the listed ones
+    #[arg(short, long = "user")]
+    users: Vec<String>,
+}
+
+fn migrate_storage(mut config: Config, args: MigrateStorageArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    if args.output == Path::new(storage::MEMORY) {
+        bail!("storage cannot be migrated in memory");
+    }
+
+    let keys = StorageKeys::load(&mut config.key)?;
+    let source = Storage::open(config.backend, &config.storage, &keys)?;
+    let target = Storage::open(args.backend, &args.output, &keys)?;
+    if !target.usernames()?.is_empty() {
+        bail!("storage {} is not empty", args.output.display());
+    }
+
+    let mut usernames = source.usernames()?;
+    usernames.push(config.admin);
+    usernames.extend(args.users);
+    usernames.sort();
+    usernames.dedup();
+
+    let count = source.copy_to(&target, &usernames)?;
+    tracing::info!("{count} users copied to {}", args.output.display());
+    Ok(())
+}
+
+#[derive(Parser)]
+struct MigrateArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Additional users to migrate, besides the listed ones
+    #[arg(short, long = "user")]
+    users: Vec<String>,
+}
+
+fn migrate(mut config: Config, args: MigrateArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    let mut storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+
+    let mut usernames = storage.usernames()?;
+    usernames.push(config.admin);
+    usernames.extend(args.users);
+    usernames.sort();
+    usernames.dedup();
+
+    let mut count = 0;
+    for username in &usernames {
+        if user::migrate_records(&storage, username)? {
+            count += 1;
+        }
+    }
+    tracing::info!("records of {count} users migrated");
+    storage.seal()?;
+    tracing::info!("storage sealed, the values without encryption are refused");
+    Ok(())
+}
+
+#[derive(Parser)]
+struct ExportArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Output archive file
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Additional users to export, besides the listed ones
+    #[arg(short, long = "user")]
+    users: Vec<String>,
+}
+
+fn export(mut config: Config, args: ExportArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    let storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+    let signatures = OpaqueSignatures::load(&mut config.key)?;
+    let backup_key: StorageKey = config.key.backup()?.parse()?;
+
+    let mut usernames = storage.usernames()?;
+    usernames.push(config.admin);
+    usernames.extend(args.users);
+    usernames.sort();
+    usernames.dedup();
+
+    let output = std::fs::File::create(&args.output)?;
+    let writer = std::io::BufWriter::new(output);
+    let count = backup::export(&storage, &signatures, &backup_key, &usernames, writer)?;
+    tracing::info!("{count} users exported to {}", args.output.display());
+    Ok(())
+}
+
+#[derive(Parser)]
+struct ImportArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Input archive file
+    #[arg(short, long)]
+    input: PathBuf,
+    /// Import an archive exported under an unknown opaque signature, its users
+    /// must reset their password
+    #[arg(long)]
+    allow_other_signature: bool,
+}
+
+fn import(mut config: Config, args: ImportArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    let storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+    let signatures = OpaqueSignatures::load(&mut config.key)?;
+    let backup_key: StorageKey = config.key.backup()?.parse()?;
+
+    let input = std::fs::File::open(&args.input)?;
+    let reader = std::io::BufReader::new(input);
+    let count = backup::import(
+        &storage,
+        &signatures,
+        &backup_key,
+        reader,
+        args.allow_other_signature,
+    )?;
+    tracing::info!("{count} users imported from {}", args.input.display());
+    Ok(())
+}
+
+#[derive(Parser)]
+struct ImportLegacyArgs {
+    /// Configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// File with one `username:hash` pair per line, the hashes are in the PHC
+    /// string format, the import fails if their parameters are too costly
+    #[arg(short, long)]
+    input: PathBuf,
+}
+
+fn import_legacy(mut config: Config, args: ImportLegacyArgs) -> Result<()> {
+    mello::trace::init(&Default::default())?;
+
+    if !config.legacy {
+        tracing::warn!("legacy is not enabled, the imported users cannot sign in");
+    }
+    let storage = Storage::open(
+        config.backend,
+        &config.storage,
+        &StorageKeys::load(&mut config.key)?,
+    )?;
+
+    let mut count = 0;
+    for (username, hash) in legacy::read_file(&args.input)? {
+        if storage.user_is_registered(&username)? {
+            tracing::warn!("user {username} already registered, skipped");
+            continue;
+        }
+        user::set_legacy_hash(&storage, &username, &hash)?;
+        count += 1;
+    }
+    tracing::info!(
+        "{count} legacy users imported from {}, restart the service so the decoy hash matches them",
+        args.input.display()
+    );
+    Ok(())
 }
@@ -0,0 +1,392 @@
+//! Prometheus metrics
+//!
+//! The metrics are exposed in the OpenMetrics text format on a separate
+//! listener, so that the endpoint is not reachable through the public one.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use parking_lot::Mutex;
+use prometheus_client::{
+    encoding::{text, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::{Registry, Unit},
+};
+
+use crate::{invitation::InvalidInvitationCode, time::DateTime};
+
+/// Step of the signup and signin flows.
+#[derive(Clone, Copy, Debug)]
+pub enum Step {
+    Start,
+    Finish,
+    /// Sign in with a legacy password hash.
+    Legacy,
+    /// Second factor verified with a TOTP code.
+    Totp,
+    /// Second factor verified with a security key.
+    Webauthn,
+    /// Second factor verified with a recovery code.
+    Recovery,
+    /// Start of the forced re-registration.
+    ReregisterStart,
+    /// Finish of the forced re-registration.
+    ReregisterFinish,
+}
+
+impl Step {
+    /// Returns the name of the step.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Finish => "finish",
+            Self::Legacy => "legacy",
+            Self::Totp => "totp",
+            Self::Webauthn => "webauthn",
+            Self::Recovery => "recovery",
+            Self::ReregisterStart => "reregister_start",
+            Self::ReregisterFinish => "reregister_finish",
+        }
+    }
+}
+
+/// Outcome of a step accepted by the server.
+#[derive(Clone, Copy, Debug)]
+pub enum Outcome {
+    /// The step is completed, the session is started at the end of the sign in.
+    Success,
+    /// The password is verified, the second factor is required.
+    SecondFactor,
+    /// The password is verified, it has to be registered again.
+    Reregister,
+}
+
+impl Outcome {
+    /// Returns the name of the outcome.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::SecondFactor => "second_factor",
+            Self::Reregister => "reregister",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StepLabels {
+    step: &'static str,
+    /// One of the [`Outcome`], `rejected` for the client errors or `error`.
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OutcomeLabels {
+    /// `success`, `rejected` for the client errors or `error`.
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct InvitationLabels {
+    /// `success` or the reason of the refusal.
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StorageLabels {
+    operation: &'static str,
+}
+
+/// Metrics of the service.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    signup: Family<StepLabels, Counter>,
+    signin: Family<StepLabels, Counter>,
+    password_resets: Family<OutcomeLabels, Counter>,
+    invitations: Family<InvitationLabels, Counter>,
+    upstream_errors: Counter,
+    storage: StorageMetrics,
+}
+
+impl Metrics {
+    /// Create and register the metrics.
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("fresh_auth");
+
+        let signup = Family::default();
+        registry.register(
+            "signup",
+            "Signup requests by step and outcome",
+            signup.clone(),
+        );
+        let signin = Family::default();
+        registry.register(
+            "signin",
+            "Signin requests by step and outcome",
+            signin.clone(),
+        );
+        let password_resets = Family::default();
+        registry.register(
+            "password_resets",
+            "Password reset requests by outcome",
+            password_resets.clone(),
+        );
+        let invitations = Family::default();
+        registry.register(
+            "invitations",
+            "Invitation code verifications by outcome",
+            invitations.clone(),
+        );
+        let upstream_errors = Counter::default();
+        registry.register(
+            "upstream_errors",
+            "Requests to the frontend failed by the reverse proxy",
+            upstream_errors.clone(),
+        );
+        let storage = StorageMetrics::new();
+        registry.register_with_unit(
+            "storage_duration",
+            "Latency of the storage operations",
+            Unit::Seconds,
+            storage.latency.clone(),
+        );
+        registry.register(
+            "active_sessions",
+            "Sessions not yet expired",
+            storage.sessions.gauge.clone(),
+        );
+
+        Self {
+            registry: Arc::new(registry),
+            signup,
+            signin,
+            password_resets,
+            invitations,
+            upstream_errors,
+            storage,
+        }
+    }
+
+    /// Record the outcome of a signup step.
+    pub fn signup<T>(&self, step: Step, result: &Result<T, StatusCode>) {
+        self.signup.get_or_create(&labels(step, result)).inc();
+    }
+
+    /// Record the outcome of a signin step.
+    pub fn signin<T>(&self, step: Step, result: &Result<T, StatusCode>) {
+        self.signin_outcome(step, result.as_ref().map(|_| Outcome::Success));
+    }
+
+    /// Record the outcome of a signin step which may require a second factor
+    /// or a re-registration.
+    pub fn signin_outcome(&self, step: Step, result: Result<Outcome, &StatusCode>) {
+        self.signin.get_or_create(&labels(step, result)).inc();
+    }
+
+    /// Record the outcome of a password reset request.
+    pub fn password_reset<T>(&self, result: &Result<T, StatusCode>) {
+        let labels = OutcomeLabels {
+            outcome: outcome(result.as_ref().map(|_| Outcome::Success)),
+        };
+        self.password_resets.get_or_create(&labels).inc();
+    }
+
+    /// Record an accepted invitation code.
+    pub fn invitation_verified(&self) {
+        self.invitation("success");
+    }
+
+    /// Record a refused invitation code.
+    pub fn invitation_failure(&self, err: InvalidInvitationCode) {
+        self.invitation(err.as_str());
+    }
+
+    /// Record an invitation already used to complete a registration.
+    pub fn invitation_redeemed(&self) {
+        self.invitation("redeemed");
+    }
+
+    fn invitation(&self, outcome: &'static str) {
+        let labels = InvitationLabels { outcome };
+        self.invitations.get_or_create(&labels).inc();
+    }
+
+    /// Forget the expired sessions, so the number of active sessions is up to
+    /// date.
+    pub fn expire_sessions(&self) {
+        self.storage.sessions.expire();
+    }
+
+    /// Record a failed request to the frontend.
+    pub fn upstream_error(&self) {
+        self.upstream_errors.inc();
+    }
+
+    /// Returns the metrics of the storage.
+    pub fn storage(&self) -> StorageMetrics {
+        self.storage.clone()
+    }
+
+    /// Encode the metrics in the OpenMetrics text format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = String::new();
+        text::encode(&mut buffer, &self.registry)?;
+        Ok(buffer)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the labels of the step.
+fn labels(step: Step, result: Result<Outcome, &StatusCode>) -> StepLabels {
+    StepLabels {
+        step: step.as_str(),
+        outcome: outcome(result),
+    }
+}
+
+/// Returns the name of the outcome, the client errors are rejected requests.
+fn outcome(result: Result<Outcome, &StatusCode>) -> &'static str {
+    match result {
+        Ok(outcome) => outcome.as_str(),
+        Err(status) if status.is_server_error() => "error",
+        Err(_) => "rejected",
+    }
+}
+
+/// Latency of the storage operations and number of the active sessions.
+#[derive(Clone)]
+pub struct StorageMetrics {
+    latency: Family<StorageLabels, Histogram, fn() -> Histogram>,
+    sessions: SessionMetrics,
+}
+
+impl StorageMetrics {
+    fn new() -> Self {
+        // from 100µs to about 3s
+        let latency = Family::new_with_constructor(
+            (|| Histogram::new(exponential_buckets(0.0001, 2.0, 16))) as fn() -> Histogram,
+        );
+        Self {
+            latency,
+            sessions: SessionMetrics::default(),
+        }
+    }
+
+    /// Count the session until its expiration, keyed by its storage key.
+    pub fn session_started(&self, id: &str, expiration: DateTime) {
+        self.sessions.started(id, expiration);
+    }
+
+    /// Stop counting the session.
+    pub fn session_ended(&self, id: &str) {
+        self.sessions.ended(id);
+    }
+
+    /// Record the duration of the storage operation.
+    pub fn observe(&self, operation: &'static str, duration: Duration) {
+        let labels = StorageLabels { operation };
+        self.latency
+            .get_or_create(&labels)
+            .observe(duration.as_secs_f64());
+    }
+}
+
+/// Active sessions, tracked in memory as they are started and ended so the
+/// storage is not scanned at each scrape.
+#[derive(Clone, Default)]
+struct SessionMetrics {
+    gauge: Gauge,
+    /// Expiration of the sessions, by storage key.
+    expirations: Arc<Mutex<HashMap<String, DateTime>>>,
+}
+
+impl SessionMetrics {
+    fn started(&self, id: &str, expiration: DateTime) {
+        let mut expirations = self.expirations.lock();
+        expirations.insert(id.to_string(), expiration);
+        self.set(expirations.len());
+    }
+
+    fn ended(&self, id: &str) {
+        let mut expirations = self.expirations.lock();
+        expirations.remove(id);
+        self.set(expirations.len());
+    }
+
+    fn expire(&self) {
+        let now = DateTime::now();
+        let mut expirations = self.expirations.lock();
+        expirations.retain(|_, expiration| *expiration > now);
+        self.set(expirations.len());
+    }
+
+    fn set(&self, count: usize) {
+        self.gauge.set(count.try_into().unwrap_or(i64::MAX));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn encode_outcomes() {
+        let metrics = Metrics::new();
+        metrics.signin(Step::Start, &Ok(()));
+        metrics.signin(Step::Finish, &Err::<(), _>(StatusCode::UNAUTHORIZED));
+        metrics.signup(
+            Step::Finish,
+            &Err::<(), _>(StatusCode::INTERNAL_SERVER_ERROR),
+        );
+        metrics.signin_outcome(Step::Finish, Ok(Outcome::SecondFactor));
+        metrics.signin(Step::Totp, &Ok(()));
+        metrics.password_reset(&Err::<(), _>(StatusCode::TOO_MANY_REQUESTS));
+        metrics.invitation_verified();
+        metrics.invitation_failure(InvalidInvitationCode::Expired);
+        metrics
+            .storage()
+            .observe("get_user", Duration::from_millis(1));
+
+        let encoded = assert_ok!(metrics.encode());
+        assert!(encoded.contains(r#"fresh_auth_signin_total{step="start",outcome="success"} 1"#));
+        assert!(encoded.contains(r#"fresh_auth_signin_total{step="finish",outcome="rejected"} 1"#));
+        assert!(encoded.contains(r#"fresh_auth_signup_total{step="finish",outcome="error"} 1"#));
+        assert!(
+            encoded.contains(r#"fresh_auth_signin_total{step="finish",outcome="second_factor"} 1"#)
+        );
+        assert!(encoded.contains(r#"fresh_auth_signin_total{step="totp",outcome="success"} 1"#));
+        assert!(encoded.contains(r#"fresh_auth_password_resets_total{outcome="rejected"} 1"#));
+        assert!(encoded.contains(r#"fresh_auth_invitations_total{outcome="success"} 1"#));
+        assert!(encoded.contains(r#"fresh_auth_invitations_total{outcome="expired"} 1"#));
+        assert!(encoded
+            .contains(r#"fresh_auth_storage_duration_seconds_count{operation="get_user"} 1"#));
+    }
+
+    #[test]
+    fn count_active_sessions() {
+        let metrics = Metrics::new();
+        let storage = metrics.storage();
+        let now = DateTime::now();
+        storage.session_started("a", now + crate::time::Duration::hours(1));
+        storage.session_started("b", now + crate::time::Duration::hours(1));
+        storage.session_started("c", now);
+        storage.session_ended("b");
+        metrics.expire_sessions();
+
+        let encoded = assert_ok!(metrics.encode());
+        assert!(encoded.contains("fresh_auth_active_sessions 1"));
+    }
+}
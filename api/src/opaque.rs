@@ -1,21 +1,99 @@
 use anyhow::Result;
-use base64ct::{Base64Url, Encoding};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
+use fresh_auth_suite::{
+    envelope::{self, MessageType},
+    CipherSuite,
+};
+use hmac::{Hmac, Mac};
 use rand_core::CryptoRngCore;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
-/// Cipher suite definition
-pub struct CipherSuite;
+use crate::{
+    config::ConfigKey,
+    record::{self, Upgrade, Versioned},
+};
 
-impl opaque_ke::CipherSuite for CipherSuite {
-    type OprfCs = opaque_ke::Ristretto255;
-    type KeGroup = opaque_ke::Ristretto255;
-    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
-    type Ksf = argon2::Argon2<'static>;
+/// Length in bytes of the server setup identifier.
+const SETUP_ID_BYTES: usize = 8;
+
+/// Domain separation of the key used to pick the parameters of the unknown
+/// users.
+const DECOY_KEY: &[u8] = b"fresh-auth decoy key";
+
+/// Parameters of the key stretching function (Argon2id), executed by the client.
+///
+/// They are stored with each password file, since the client needs the same
+/// parameters used for the registration to sign in.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KsfParams {
+    /// Memory size in KiB.
+    pub memory: u32,
+    /// Number of iterations.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for KsfParams {
+    fn default() -> Self {
+        Self {
+            memory: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KsfParams {
+    /// Check if the parameters are accepted by Argon2.
+    pub fn validate(&self) -> Result<()> {
+        argon2::Params::new(self.memory, self.iterations, self.parallelism, None)
+            .map_err(|err| anyhow::anyhow!("invalid key stretching parameters, {err}"))?;
+        Ok(())
+    }
+}
+
+/// Identifiers bound to the key exchange.
+///
+/// The server identity is bound to the registrations: it is stored with each
+/// password file and the users registered with a different one are migrated
+/// at their next sign in. The context is bound only to the sign in, so it can
+/// be changed at any time.
+#[derive(Clone, Default, Deserialize)]
+pub struct ConfigOpaque {
+    /// Identity of the server, the server public key is used when missing.
+    pub identity: Option<String>,
+    /// Context of the application, it separates the transcripts of different
+    /// deployments.
+    pub context: Option<String>,
+    /// Parameters of the registrations before their last change.
+    #[serde(default)]
+    pub retired: Vec<ConfigRetiredOpaque>,
+}
+
+/// Parameters of older registrations, some users may be still registered
+/// with them.
+///
+/// The unknown users are given the current parameters or one of these,
+/// always the same for each username, so that they cannot be told apart from
+/// the users not yet migrated.
+#[derive(Clone, Deserialize)]
+pub struct ConfigRetiredOpaque {
+    /// Key stretching parameters.
+    pub ksf: KsfParams,
+    /// Identity of the server, the server public key was used when missing.
+    pub identity: Option<String>,
 }
 
 /// Server signature
 pub struct OpaqueSignature {
+    id: String,
+    public_key: String,
     server_setup: opaque_ke::ServerSetup<CipherSuite>,
+    decoy_key: Zeroizing<[u8; 32]>,
 }
 
 impl OpaqueSignature {
@@ -28,9 +106,108 @@ impl OpaqueSignature {
 
     /// Create a new signature.
     pub fn new(signature: &str) -> Result<Self> {
-        let signature = Base64Url::decode_vec(signature)?;
+        let signature = Zeroizing::new(Base64Url::decode_vec(signature)?);
         let server_setup = opaque_ke::ServerSetup::<CipherSuite>::deserialize(&signature)?;
-        Ok(Self { server_setup })
+        let digest = Sha256::digest(signature.as_slice());
+        let id = Base64UrlUnpadded::encode_string(&digest[..SETUP_ID_BYTES]);
+        let public_key = server_setup.keypair().public().serialize();
+        let public_key = Base64Url::encode_string(&public_key);
+        let mut decoy_key = Zeroizing::new([0_u8; 32]);
+        decoy_key.copy_from_slice(
+            &Sha256::new_with_prefix(DECOY_KEY)
+                .chain_update(signature.as_slice())
+                .finalize(),
+        );
+        Ok(Self {
+            id,
+            public_key,
+            server_setup,
+            decoy_key,
+        })
+    }
+
+    /// Returns the identifier of the server setup.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the static public key of the server (base64url encoded), the
+    /// clients can pin it.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Returns an index lower than `len` derived from the username, the same
+    /// for each call but unpredictable without the server setup.
+    pub fn pick(&self, username: &str, len: usize) -> usize {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.decoy_key.as_slice()).expect("any key length");
+        mac.update(username.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let value = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"));
+        (value % len.max(1) as u64) as usize
+    }
+}
+
+/// Set of server signatures.
+///
+/// The current signature is used for new registrations, the retired ones are
+/// kept to let the users registered with them to sign in and migrate to the
+/// current one.
+pub struct OpaqueSignatures {
+    current: OpaqueSignature,
+    retired: Vec<OpaqueSignature>,
+}
+
+impl OpaqueSignatures {
+    /// Create a new set of signatures from the current one and the retired ones.
+    pub fn new(current: OpaqueSignature, retired: Vec<OpaqueSignature>) -> Self {
+        Self { current, retired }
+    }
+
+    /// Load the signatures from the configuration.
+    pub fn load(config: &mut ConfigKey) -> Result<Self> {
+        let current = OpaqueSignature::new(&config.opaque()?)?;
+        let retired = config
+            .retired_opaque()?
+            .iter()
+            .map(|signature| OpaqueSignature::new(signature))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(current, retired))
+    }
+
+    /// Returns the signature used for new registrations.
+    pub fn current(&self) -> &OpaqueSignature {
+        &self.current
+    }
+
+    /// Returns the retired signatures.
+    pub fn retired(&self) -> &[OpaqueSignature] {
+        &self.retired
+    }
+
+    /// Check if the password file should be migrated to the current signature.
+    pub fn needs_migration(&self, password_file: &PasswordFile) -> bool {
+        self.select(password_file).map(OpaqueSignature::id) != Some(self.current.id())
+    }
+
+    /// Search the current or retired signature with the given identifier.
+    pub fn find(&self, id: &str) -> Option<&OpaqueSignature> {
+        std::iter::once(&self.current)
+            .chain(&self.retired)
+            .find(|signature| signature.id() == id)
+    }
+
+    /// Search the signature used to register the password file.
+    ///
+    /// Password files stored without setup identifier were registered before
+    /// the first rotation, they are bound to the oldest retired signature or
+    /// to the current one when none has been retired yet.
+    pub fn select(&self, password_file: &PasswordFile) -> Option<&OpaqueSignature> {
+        match password_file.setup.as_deref() {
+            Some(id) => self.find(id),
+            None => Some(self.retired.first().unwrap_or(&self.current)),
+        }
     }
 }
 
@@ -53,21 +230,56 @@ pub fn registration_start(
 }
 
 /// Finish the registration process and generate a password file.
-pub fn registration_finish(upload: RegistrationUpload) -> PasswordFile {
+///
+/// The parameters of the key stretching function and the server identity are
+/// the ones used by the client to finish the registration.
+pub fn registration_finish(
+    signature: &OpaqueSignature,
+    ksf: KsfParams,
+    identity: Option<&str>,
+    upload: RegistrationUpload,
+) -> PasswordFile {
     let registration = opaque_ke::ServerRegistration::finish(upload.message);
-    PasswordFile { registration }
+    PasswordFile {
+        setup: Some(signature.id().to_string()),
+        ksf,
+        identity: identity.map(str::to_string),
+        registration,
+    }
 }
 
 /// From the client's bindled password returns a response to be sent back to the client.
+///
+/// The signature is selected using the setup identifier of the password file,
+/// the server identity must be the one used for the registration.
 pub fn login_start<R: CryptoRngCore>(
     rng: &mut R,
-    signature: &OpaqueSignature,
+    signatures: &OpaqueSignatures,
     username: &str,
     password_file: Option<PasswordFile>,
+    identity: Option<&str>,
+    context: Option<&str>,
     request: LoginRequest,
 ) -> Result<(LoginResponse, LoginState)> {
-    let params = opaque_ke::ServerLoginStartParameters::default();
-    let registration = password_file.map(|pf| pf.registration);
+    let params = opaque_ke::ServerLoginStartParameters {
+        context: context.map(str::as_bytes),
+        identifiers: opaque_ke::Identifiers {
+            client: None,
+            server: identity.map(str::as_bytes),
+        },
+    };
+    let (signature, registration) = match password_file {
+        Some(password_file) => match signatures.select(&password_file) {
+            Some(signature) => (signature, Some(password_file.registration)),
+            None => {
+                // the login continues as for an unknown user, so that the
+                // response is indistinguishable
+                tracing::error!("password file of user {username} uses an unknown server setup");
+                (signatures.current(), None)
+            }
+        },
+        None => (signatures.current(), None),
+    };
     let credential_request = request.message;
 
     let server_login = opaque_ke::ServerLogin::start(
@@ -105,8 +317,9 @@ impl<'de> Deserialize<'de> for RegistrationRequest {
     where
         D: Deserializer<'de>,
     {
-        let encoded_message: &str = Deserialize::deserialize(deserializer)?;
-        let buffer = Base64Url::decode_vec(&encoded_message).map_err(serde::de::Error::custom)?;
+        let envelope: &str = Deserialize::deserialize(deserializer)?;
+        let buffer = envelope::open(MessageType::RegistrationRequest, envelope)
+            .map_err(serde::de::Error::custom)?;
         let message = opaque_ke::RegistrationRequest::deserialize(&buffer)
             .map_err(serde::de::Error::custom)?;
         Ok(Self { message })
@@ -123,9 +336,9 @@ impl Serialize for RegistrationResponse {
     where
         S: Serializer,
     {
-        let serialized_state = self.message.serialize();
-        let encoded_state = Base64Url::encode_string(&serialized_state);
-        serializer.serialize_str(&encoded_state)
+        let serialized_message = self.message.serialize();
+        let envelope = envelope::seal(MessageType::RegistrationResponse, &serialized_message);
+        serializer.serialize_str(&envelope)
     }
 }
 
@@ -139,8 +352,9 @@ impl<'de> Deserialize<'de> for RegistrationUpload {
     where
         D: Deserializer<'de>,
     {
-        let encoded_message: &str = Deserialize::deserialize(deserializer)?;
-        let buffer = Base64Url::decode_vec(&encoded_message).map_err(serde::de::Error::custom)?;
+        let envelope: &str = Deserialize::deserialize(deserializer)?;
+        let buffer = envelope::open(MessageType::RegistrationUpload, envelope)
+            .map_err(serde::de::Error::custom)?;
         let message = opaque_ke::RegistrationUpload::deserialize(&buffer)
             .map_err(serde::de::Error::custom)?;
         Ok(Self { message })
@@ -157,8 +371,9 @@ impl<'de> Deserialize<'de> for LoginRequest {
     where
         D: Deserializer<'de>,
     {
-        let encoded_message: &str = Deserialize::deserialize(deserializer)?;
-        let buffer = Base64Url::decode_vec(&encoded_message).map_err(serde::de::Error::custom)?;
+        let envelope: &str = Deserialize::deserialize(deserializer)?;
+        let buffer = envelope::open(MessageType::CredentialRequest, envelope)
+            .map_err(serde::de::Error::custom)?;
         let message =
             opaque_ke::CredentialRequest::deserialize(&buffer).map_err(serde::de::Error::custom)?;
         Ok(Self { message })
@@ -176,8 +391,8 @@ impl Serialize for LoginResponse {
         S: Serializer,
     {
         let serialized_message = self.message.serialize();
-        let encoded_message = Base64Url::encode_string(&serialized_message);
-        serializer.serialize_str(&encoded_message)
+        let envelope = envelope::seal(MessageType::CredentialResponse, &serialized_message);
+        serializer.serialize_str(&envelope)
     }
 }
 
@@ -191,8 +406,9 @@ impl<'de> Deserialize<'de> for LoginFinalization {
     where
         D: Deserializer<'de>,
     {
-        let encoded_message: &str = Deserialize::deserialize(deserializer)?;
-        let buffer = Base64Url::decode_vec(&encoded_message).map_err(serde::de::Error::custom)?;
+        let envelope: &str = Deserialize::deserialize(deserializer)?;
+        let buffer = envelope::open(MessageType::CredentialFinalization, envelope)
+            .map_err(serde::de::Error::custom)?;
         let message = opaque_ke::CredentialFinalization::deserialize(&buffer)
             .map_err(serde::de::Error::custom)?;
         Ok(Self { message })
@@ -229,20 +445,113 @@ impl Serialize for LoginState {
 }
 
 /// User registration password file.
+///
+/// It is stored together with the identifier of the server setup used for
+/// the registration. Password files without it were registered before the
+/// first rotation, they belong to the oldest retired setup or to the current
+/// one when none has been retired yet.
 pub struct PasswordFile {
+    setup: Option<String>,
+    ksf: KsfParams,
+    identity: Option<String>,
     registration: opaque_ke::ServerRegistration<CipherSuite>,
 }
 
+impl PasswordFile {
+    /// Record the identifier of the signature, if missing.
+    ///
+    /// Returns `false` if the password file has already one.
+    pub fn bind(&mut self, signature: &OpaqueSignature) -> bool {
+        if self.setup.is_some() {
+            return false;
+        }
+        self.setup = Some(signature.id().to_string());
+        true
+    }
+
+    /// Returns the identifier of the server setup, `None` if saved before the
+    /// first rotation.
+    pub fn setup(&self) -> Option<&str> {
+        self.setup.as_deref()
+    }
+
+    /// Returns the parameters of the key stretching function used by the client.
+    pub fn ksf(&self) -> KsfParams {
+        self.ksf
+    }
+
+    /// Returns the server identity used by the client, `None` if it is the
+    /// server public key.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+}
+
+impl Versioned for PasswordFile {
+    const UPGRADES: &'static [Upgrade] = &[upgrade_password_file_v0];
+}
+
+/// Key stretching parameters of the registrations before they were
+/// configurable, the Argon2 defaults at that time.
+const UNVERSIONED_KSF: KsfParams = KsfParams {
+    memory: 19 * 1024,
+    iterations: 2,
+    parallelism: 1,
+};
+
+/// Version 1: password files saved as plain registration string, or without
+/// the key stretching parameters, get all the fields.
+fn upgrade_password_file_v0(value: Value) -> Result<Value> {
+    let mut value = match value {
+        Value::String(registration) => json!({ "registration": registration }),
+        value => value,
+    };
+    let password_file = record::object(&mut value)?;
+    if !password_file.contains_key("ksf") {
+        password_file.insert("ksf".to_string(), serde_json::to_value(UNVERSIONED_KSF)?);
+    }
+    Ok(value)
+}
+
+#[derive(Deserialize)]
+struct EncodedPasswordFile {
+    setup: Option<String>,
+    ksf: KsfParams,
+    identity: Option<String>,
+    registration: String,
+}
+
+#[derive(Serialize)]
+struct EncodedPasswordFileRef<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    setup: Option<&'a str>,
+    ksf: KsfParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<&'a str>,
+    registration: &'a str,
+}
+
 impl<'de> Deserialize<'de> for PasswordFile {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let encoded_message: &str = Deserialize::deserialize(deserializer)?;
-        let buffer = Base64Url::decode_vec(&encoded_message).map_err(serde::de::Error::custom)?;
+        let EncodedPasswordFile {
+            setup,
+            ksf,
+            identity,
+            registration: encoded_registration,
+        } = Deserialize::deserialize(deserializer)?;
+        let buffer =
+            Base64Url::decode_vec(&encoded_registration).map_err(serde::de::Error::custom)?;
         let registration = opaque_ke::ServerRegistration::deserialize(&buffer)
             .map_err(serde::de::Error::custom)?;
-        Ok(Self { registration })
+        Ok(Self {
+            setup,
+            ksf,
+            identity,
+            registration,
+        })
     }
 }
 
@@ -253,6 +562,138 @@ impl Serialize for PasswordFile {
     {
         let serialized_registration = self.registration.serialize();
         let encoded_registration = Base64Url::encode_string(&serialized_registration);
-        serializer.serialize_str(&encoded_registration)
+        EncodedPasswordFileRef {
+            setup: self.setup.as_deref(),
+            ksf: self.ksf,
+            identity: self.identity.as_deref(),
+            registration: &encoded_registration,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use claym::*;
+    use rand::rngs::OsRng;
+
+    use crate::rng;
+
+    pub fn signature() -> OpaqueSignature {
+        let signature = rng::with_crypto_rng(OpaqueSignature::generate);
+        assert_ok!(OpaqueSignature::new(&signature))
+    }
+
+    /// Register a password with the signature, without setup identifier.
+    pub fn unversioned_password_file(signature: &OpaqueSignature) -> PasswordFile {
+        let ksf = KsfParams {
+            memory: 8,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let argon2 = argon2::Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            assert_ok!(argon2::Params::new(
+                ksf.memory,
+                ksf.iterations,
+                ksf.parallelism,
+                None
+            )),
+        );
+        let start = assert_ok!(opaque_ke::ClientRegistration::<CipherSuite>::start(
+            &mut OsRng,
+            b"password"
+        ));
+        let request = RegistrationRequest {
+            message: start.message,
+        };
+        let response = assert_ok!(registration_start(signature, "user", request));
+        let params = opaque_ke::ClientRegistrationFinishParameters::new(
+            opaque_ke::Identifiers {
+                client: None,
+                server: None,
+            },
+            Some(&argon2),
+        );
+        let finish =
+            assert_ok!(start
+                .state
+                .finish(&mut OsRng, b"password", response.message, params));
+        let upload = RegistrationUpload {
+            message: finish.message,
+        };
+        let mut password_file = registration_finish(signature, ksf, None, upload);
+        password_file.setup = None;
+        password_file
+    }
+
+    #[test]
+    fn select_oldest_signature_for_unversioned_password_file() {
+        let oldest = signature();
+        let password_file = unversioned_password_file(&oldest);
+        let oldest_id = oldest.id().to_string();
+        let signatures = OpaqueSignatures::new(signature(), vec![oldest, signature()]);
+
+        let selected = signatures.select(&password_file).map(OpaqueSignature::id);
+        assert_eq!(selected, Some(oldest_id.as_str()));
+        assert!(signatures.needs_migration(&password_file));
+    }
+
+    #[test]
+    fn keep_unversioned_password_file_without_rotation() {
+        let current = signature();
+        let mut password_file = unversioned_password_file(&current);
+        let signatures = OpaqueSignatures::new(current, Vec::new());
+        assert!(!signatures.needs_migration(&password_file));
+
+        assert!(password_file.bind(signatures.current()));
+        assert!(!password_file.bind(signatures.current()));
+        assert!(!signatures.needs_migration(&password_file));
+    }
+
+    fn upgrade(value: Value) -> Value {
+        assert_ok!(record::upgrade::<PasswordFile>(value))
+    }
+
+    #[test]
+    fn upgrade_plain_password_file() {
+        let password_file = upgrade(json!("cmVnaXN0cmF0aW9u"));
+        let expected = json!({
+            "ksf": { "memory": 19456, "iterations": 2, "parallelism": 1 },
+            "registration": "cmVnaXN0cmF0aW9u",
+        });
+        assert_eq!(password_file, expected);
+    }
+
+    #[test]
+    fn upgrade_password_file_without_ksf() {
+        let password_file = upgrade(json!({
+            "setup": "setup-id",
+            "registration": "cmVnaXN0cmF0aW9u",
+        }));
+        let expected = json!({
+            "setup": "setup-id",
+            "ksf": { "memory": 19456, "iterations": 2, "parallelism": 1 },
+            "registration": "cmVnaXN0cmF0aW9u",
+        });
+        assert_eq!(password_file, expected);
+    }
+
+    #[test]
+    fn keep_password_file_with_ksf() {
+        let value = json!({
+            "setup": "setup-id",
+            "ksf": { "memory": 65536, "iterations": 3, "parallelism": 4 },
+            "identity": "auth.example.com",
+            "registration": "cmVnaXN0cmF0aW9u",
+        });
+        assert_eq!(upgrade(value.clone()), value);
+
+        let mut current = value.clone();
+        current["version"] = json!(1);
+        assert_eq!(upgrade(current), value);
     }
 }
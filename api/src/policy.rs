@@ -0,0 +1,50 @@
+//! Password policy
+//!
+//! The policy is enforced by the client, the server never sees the password:
+//! it is served to the client so it can be changed without rebuilding the
+//! frontend.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Highest score of the strength estimator.
+const MAX_SCORE: u8 = 4;
+
+/// Requirements of the passwords chosen by the users.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    /// Minimum strength score, from 0 (too guessable) to 4 (very unguessable).
+    pub score: u8,
+    /// Minimum number of characters.
+    pub length: u32,
+    /// Minimum edit distance between the username and the password.
+    pub distance: u32,
+    /// Reject the passwords found in the breached password filter, the client
+    /// fails if the filter is not available.
+    pub breach: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            score: 3,
+            length: 8,
+            distance: 5,
+            breach: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Check if the policy can be enforced.
+    pub fn validate(&self, has_breach_filter: bool) -> Result<()> {
+        if self.score > MAX_SCORE {
+            bail!("invalid password policy, score must be at most {MAX_SCORE}");
+        }
+        if self.breach && !has_breach_filter {
+            bail!("invalid password policy, breach check requires a breached password filter");
+        }
+        Ok(())
+    }
+}
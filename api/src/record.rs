@@ -0,0 +1,136 @@
+//! Versioned records
+//!
+//! The records are saved with a `version` field. Records written by older
+//! releases, down to the ones without the field (version 0), are upgraded
+//! when read applying the missing upgrades in order; the `migrate` command
+//! rewrites the stored records in the current format.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Name of the version field.
+const VERSION: &str = "version";
+
+/// Upgrade of a record to the next version.
+pub type Upgrade = fn(Value) -> Result<Value>;
+
+/// Record saved with its version.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Upgrades of the older formats, the n-th one converts a record from
+    /// version n to n + 1. The current version is their number.
+    ///
+    /// Never change an already released upgrade, add a new one.
+    const UPGRADES: &'static [Upgrade];
+}
+
+/// Record with its version, upgraded to the current format when read.
+pub struct Stored<T>(pub T);
+
+#[derive(Serialize)]
+struct Tagged<'a, T> {
+    version: usize,
+    #[serde(flatten)]
+    record: &'a T,
+}
+
+impl<T: Versioned> Serialize for Stored<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tagged = Tagged {
+            version: T::UPGRADES.len(),
+            record: &self.0,
+        };
+        tagged.serialize(serializer)
+    }
+}
+
+impl<'de, T: Versioned> Deserialize<'de> for Stored<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let value = upgrade::<T>(value).map_err(serde::de::Error::custom)?;
+        serde_json::from_value(value)
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Upgrade the record to the current format, the version field is removed.
+pub fn upgrade<T: Versioned>(mut value: Value) -> Result<Value> {
+    let version = match value
+        .as_object_mut()
+        .and_then(|record| record.remove(VERSION))
+    {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid record version {version}"))?,
+        None => 0,
+    };
+    let Some(upgrades) = T::UPGRADES.get(version as usize..) else {
+        bail!("record version {version} is newer than the supported one");
+    };
+    for upgrade in upgrades {
+        value = upgrade(value)?;
+    }
+    Ok(value)
+}
+
+/// Returns the object of the record, to be modified by the upgrades.
+pub fn object(value: &mut Value) -> Result<&mut serde_json::Map<String, Value>> {
+    value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("record is not an object"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+    use serde_json::json;
+
+    #[derive(Deserialize, Serialize)]
+    struct Record {
+        name: String,
+        count: u32,
+    }
+
+    impl Versioned for Record {
+        const UPGRADES: &'static [Upgrade] = &[
+            |value| Ok(json!({ "name": value })),
+            |mut value| {
+                object(&mut value)?.insert("count".to_string(), json!(0));
+                Ok(value)
+            },
+        ];
+    }
+
+    #[test]
+    fn upgrade_older_versions() {
+        let Stored(record) = assert_ok!(serde_json::from_value::<Stored<Record>>(json!("name")));
+        assert_eq!(record.name, "name");
+        assert_eq!(record.count, 0);
+
+        let value = json!({ "version": 1, "name": "name" });
+        let Stored(record) = assert_ok!(serde_json::from_value::<Stored<Record>>(value));
+        assert_eq!(record.count, 0);
+    }
+
+    #[test]
+    fn write_current_version() {
+        let record = Record {
+            name: "name".to_string(),
+            count: 1,
+        };
+        let value = assert_ok!(serde_json::to_value(Stored(record)));
+        assert_eq!(value, json!({ "version": 2, "name": "name", "count": 1 }));
+
+        let Stored(record) = assert_ok!(serde_json::from_value::<Stored<Record>>(value));
+        assert_eq!(record.count, 1);
+    }
+
+    #[test]
+    fn reject_newer_versions() {
+        let value = json!({ "version": 3, "name": "name", "count": 1 });
+        assert!(serde_json::from_value::<Stored<Record>>(value).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Recovery codes
+//!
+//! Single-use codes replacing the second factor when the authenticator is
+//! lost. They are shown to the user only once, only their digests are saved.
+
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::config::Secret;
+
+/// Number of codes of each set.
+const CODES: usize = 10;
+
+/// Number of characters of each code, excluding the separators.
+const CODE_LENGTH: usize = 16;
+
+/// Characters are grouped for readability.
+const GROUP_LENGTH: usize = 4;
+
+/// Alphabet of the codes, easily readable characters (Crockford base32).
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Digests of the unused recovery codes of a user.
+#[derive(Deserialize, Serialize)]
+pub struct RecoveryCodes {
+    digests: Vec<[u8; 32]>,
+}
+
+impl RecoveryCodes {
+    /// Generate a new set of codes, returns the codes to be shown to the user
+    /// and the set to be saved.
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> (Vec<Secret>, Self) {
+        let codes = (0..CODES)
+            .map(|_| {
+                let mut code = Zeroizing::new(String::with_capacity(CODE_LENGTH + 3));
+                for i in 0..CODE_LENGTH {
+                    if i > 0 && i % GROUP_LENGTH == 0 {
+                        code.push('-');
+                    }
+                    let index = rng.next_u32() as usize % ALPHABET.len();
+                    code.push(ALPHABET[index] as char);
+                }
+                code
+            })
+            .collect::<Vec<_>>();
+        let digests = codes.iter().filter_map(|code| digest(code)).collect();
+        (codes, Self { digests })
+    }
+
+    /// Returns the number of unused codes.
+    pub fn remaining(&self) -> usize {
+        self.digests.len()
+    }
+
+    /// Redeem the code, it is removed from the set so it can be used only
+    /// once.
+    pub fn redeem(&mut self, code: &str) -> bool {
+        let Some(digest) = digest(code) else {
+            return false;
+        };
+        let position = self
+            .digests
+            .iter()
+            .enumerate()
+            .fold(None, |found, (index, other)| {
+                if bool::from(other[..].ct_eq(&digest[..])) {
+                    Some(index)
+                } else {
+                    found
+                }
+            });
+        match position {
+            Some(index) => {
+                self.digests.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Compute the digest of the normalized code, returns `None` if the code is
+/// malformed.
+///
+/// The separators and the case are ignored, the characters easily confused
+/// are mapped as in Crockford base32.
+fn digest(code: &str) -> Option<[u8; 32]> {
+    let normalized = Zeroizing::new(
+        code.chars()
+            .filter(|c| !matches!(c, '-' | ' '))
+            .map(|c| match c.to_ascii_uppercase() {
+                'O' => '0',
+                'I' | 'L' => '1',
+                c => c,
+            })
+            .collect::<String>(),
+    );
+    let is_valid =
+        normalized.len() == CODE_LENGTH && normalized.bytes().all(|c| ALPHABET.contains(&c));
+    is_valid.then(|| Sha256::digest(normalized.as_bytes()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rng;
+
+    #[test]
+    fn redeem_each_code_only_once() {
+        let (codes, mut recovery) = rng::with_crypto_rng(RecoveryCodes::generate);
+        assert_eq!(codes.len(), CODES);
+        assert_eq!(recovery.remaining(), CODES);
+
+        assert!(recovery.redeem(&codes[3]));
+        assert_eq!(recovery.remaining(), CODES - 1);
+        assert!(!recovery.redeem(&codes[3]));
+        assert!(recovery.redeem(&codes[0]));
+        assert!(!recovery.redeem("0000-0000-0000-0000"));
+        assert!(!recovery.redeem("not a code"));
+    }
+
+    #[test]
+    fn ignore_separators_and_case() {
+        let (codes, mut recovery) = rng::with_crypto_rng(RecoveryCodes::generate);
+        let code = codes[0].replace('-', " ").to_lowercase();
+        assert!(recovery.redeem(&code));
+    }
+}
@@ -1,9 +1,10 @@
 use std::{cell::RefCell, str::FromStr};
 
 use anyhow::ensure;
-use base64ct::{Base64Url, Encoding};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaChaRng;
+use sha2::{Digest, Sha256};
 
 const SESSION_BYTES: usize = 96;
 const ENCODED_BYTES: usize = 128;
@@ -30,6 +31,13 @@ impl SessionId {
         DisplaySessionId { bytes: &self.bytes }
     }
 
+    /// Returns the digest of the session id, used as storage key so that the
+    /// session id itself is never saved.
+    pub fn digest(&self) -> String {
+        let digest = Sha256::digest(self.bytes);
+        Base64UrlUnpadded::encode_string(&digest)
+    }
+
     /// Serializer function
     pub fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -0,0 +1,254 @@
+//! Key-value backend
+//!
+//! Each record is saved under a `prefix:key` string, the lists of users and
+//! of their sessions are kept as separate records.
+//!
+//! The keys cannot be enumerated, so the list of users of a storage created
+//! before its introduction is missing: until it is rebuilt with the
+//! `index-users` command, the users cannot be listed.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use mello::kvstorage::KVStorage;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Backend, Handshake, UserField, WrappedDataKey, SEALED_PREFIX, SESSION};
+use crate::time::DateTime;
+
+/// Storage key of the wrapped data key.
+const DATA_KEY: &str = "data-key";
+
+/// Storage key of the list of users.
+const USERS: &str = "users";
+
+/// Prefix of the list of sessions of each user.
+const USER_SESSIONS: &str = "user-sessions";
+
+/// Storage key of the list of redeemed invitations.
+const INVITATIONS: &str = "invitations";
+
+/// Redeemed invitation, kept until its expiration.
+#[derive(Deserialize, Serialize)]
+struct RedeemedInvitation {
+    id: String,
+    expiration: DateTime,
+}
+
+/// Storage backed by [`KVStorage`].
+pub struct KvBackend {
+    kv: KVStorage,
+    /// Held by the operations that read a record and write it back, so
+    /// concurrent requests cannot overwrite each other.
+    lock: Mutex<()>,
+}
+
+impl KvBackend {
+    /// Open the key-value storage, a new storage starts with an empty list of
+    /// users.
+    pub fn open(path: &Path) -> Result<Self> {
+        let is_new = !path.exists();
+        let kv = KVStorage::open(path)?;
+        let backend = Self {
+            kv,
+            lock: Mutex::new(()),
+        };
+        if is_new {
+            backend.set(USERS, &Vec::<String>::new())?;
+        }
+        Ok(backend)
+    }
+
+    /// Returns the value of the key.
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = self.kv.read()?.get::<_, serde_json::Value>(key)?;
+        Ok(value.map(into_string))
+    }
+
+    /// Set the value of the key.
+    fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        self.kv.write().set(key, value)?;
+        Ok(())
+    }
+
+    /// Save the record of the user and list the user, the lock must be held.
+    fn save_user(&self, username: &str, field: UserField, value: &str) -> Result<()> {
+        self.set(&format!("{}:{username}", field.as_str()), &value)?;
+
+        // a missing list is left to `index-users`, a partial one would hide
+        // the users written before
+        let users = self.kv.read()?.get::<_, Vec<String>>(USERS)?;
+        if let Some(mut users) = users {
+            if !users.iter().any(|user| user == username) {
+                users.push(username.to_string());
+                self.set(USERS, &users)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the list saved in the key, empty if missing.
+    fn list<T: DeserializeOwned>(&self, key: &str) -> Result<Vec<T>> {
+        let list = self.kv.read()?.get::<_, Vec<T>>(key)?;
+        Ok(list.unwrap_or_default())
+    }
+
+    /// Returns the sessions of the user not yet removed.
+    fn live_sessions(&self, username: &str) -> Result<Vec<String>> {
+        let mut sessions = Vec::new();
+        for id in self.list::<String>(&format!("{USER_SESSIONS}:{username}"))? {
+            let key = format!("{SESSION}:{id}");
+            if self.kv.read()?.has(key.as_str())? {
+                sessions.push(id);
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+/// Values saved before the encryption are returned as JSON.
+fn into_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) if value.starts_with(SEALED_PREFIX) => value,
+        value => value.to_string(),
+    }
+}
+
+impl Backend for KvBackend {
+    fn data_key(&self) -> Result<Option<WrappedDataKey>> {
+        let data_key = self.kv.read()?.get::<_, WrappedDataKey>(DATA_KEY)?;
+        Ok(data_key)
+    }
+
+    fn set_data_key(&self, data_key: &WrappedDataKey) -> Result<()> {
+        self.set(DATA_KEY, data_key)
+    }
+
+    fn usernames(&self) -> Result<Vec<String>> {
+        let Some(users) = self.kv.read()?.get::<_, Vec<String>>(USERS)? else {
+            bail!("the users of the storage are not listed, run the `index-users` command");
+        };
+        Ok(users)
+    }
+
+    fn index_users(&self, usernames: &[String]) -> Result<usize> {
+        let _lock = self.lock.lock();
+        let mut users = self.list::<String>(USERS)?;
+        let mut indexed = 0;
+        for username in usernames {
+            if users.contains(username) {
+                continue;
+            }
+            let mut found = false;
+            for field in UserField::ALL {
+                let key = format!("{}:{username}", field.as_str());
+                if self.kv.read()?.has(key.as_str())? {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                tracing::warn!("user {username} not found");
+                continue;
+            }
+            users.push(username.clone());
+            indexed += 1;
+        }
+        self.set(USERS, &users)?;
+        Ok(indexed)
+    }
+
+    fn get_user(&self, username: &str, field: UserField) -> Result<Option<String>> {
+        self.get(&format!("{}:{username}", field.as_str()))
+    }
+
+    fn set_user(&self, username: &str, field: UserField, value: &str) -> Result<()> {
+        let _lock = self.lock.lock();
+        self.save_user(username, field, value)
+    }
+
+    fn update_user(
+        &self,
+        username: &str,
+        field: UserField,
+        update: &mut dyn FnMut(Option<&str>) -> Result<Option<String>>,
+    ) -> Result<()> {
+        let _lock = self.lock.lock();
+        let value = self.get(&format!("{}:{username}", field.as_str()))?;
+        if let Some(value) = update(value.as_deref())? {
+            self.save_user(username, field, &value)?;
+        }
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str, field: UserField) -> Result<()> {
+        let key = format!("{}:{username}", field.as_str());
+        self.kv.write().del(key.as_str())?;
+        Ok(())
+    }
+
+    fn insert_session(&self, id: &str, username: &str, value: &str) -> Result<()> {
+        let _lock = self.lock.lock();
+        self.set(&format!("{SESSION}:{id}"), &value)?;
+
+        let mut sessions = self.live_sessions(username)?;
+        sessions.push(id.to_string());
+        self.set(&format!("{USER_SESSIONS}:{username}"), &sessions)
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<String>> {
+        self.get(&format!("{SESSION}:{id}"))
+    }
+
+    fn delete_session(&self, id: &str) -> Result<()> {
+        let key = format!("{SESSION}:{id}");
+        self.kv.write().del(key.as_str())?;
+        Ok(())
+    }
+
+    fn user_sessions(&self, username: &str) -> Result<Vec<String>> {
+        self.live_sessions(username)
+    }
+
+    fn redeem_invitation(&self, id: &str, expiration: DateTime) -> Result<bool> {
+        let _lock = self.lock.lock();
+        let now = DateTime::now();
+        let mut invitations = self.list::<RedeemedInvitation>(INVITATIONS)?;
+        invitations.retain(|invitation| invitation.expiration >= now);
+        if invitations.iter().any(|invitation| invitation.id == id) {
+            return Ok(false);
+        }
+
+        invitations.push(RedeemedInvitation {
+            id: id.to_string(),
+            expiration,
+        });
+        self.set(INVITATIONS, &invitations)?;
+        Ok(true)
+    }
+
+    fn invitations(&self) -> Result<Vec<(String, DateTime)>> {
+        let now = DateTime::now();
+        let invitations = self
+            .list::<RedeemedInvitation>(INVITATIONS)?
+            .into_iter()
+            .filter(|invitation| invitation.expiration >= now)
+            .map(|invitation| (invitation.id, invitation.expiration))
+            .collect();
+        Ok(invitations)
+    }
+
+    fn push_handshake(&self, kind: Handshake, id: &str, value: &str) -> Result<()> {
+        self.set(&format!("{}:{id}", kind.as_str()), &value)
+    }
+
+    fn pull_handshake(&self, kind: Handshake, id: &str) -> Result<Option<String>> {
+        let key = format!("{}:{id}", kind.as_str());
+        let value = self
+            .kv
+            .write()
+            .extract::<_, serde_json::Value>(key.as_str())?;
+        Ok(value.map(into_string))
+    }
+}
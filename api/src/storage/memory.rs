@@ -0,0 +1,173 @@
+//! In-memory backend
+//!
+//! The records are lost when the service stops, it is meant for the tests and
+//! for ephemeral deployments.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use super::{Backend, Handshake, UserField, WrappedDataKey};
+use crate::time::{DateTime, Duration};
+
+/// Abandoned handshakes are removed after this time.
+const HANDSHAKE_RETENTION: Duration = Duration::hours(1);
+
+/// Storage kept in memory.
+#[derive(Default)]
+pub struct MemoryBackend {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    data_key: Option<WrappedDataKey>,
+    users: BTreeMap<String, HashMap<UserField, String>>,
+    /// Sessions with the name of their user.
+    sessions: HashMap<String, (String, String)>,
+    invitations: HashMap<String, DateTime>,
+    /// Handshakes with their creation time.
+    handshakes: HashMap<(Handshake, String), (String, DateTime)>,
+}
+
+impl MemoryBackend {
+    /// Create an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn data_key(&self) -> Result<Option<WrappedDataKey>> {
+        Ok(self.inner.lock().data_key.clone())
+    }
+
+    fn set_data_key(&self, data_key: &WrappedDataKey) -> Result<()> {
+        self.inner.lock().data_key = Some(data_key.clone());
+        Ok(())
+    }
+
+    fn usernames(&self) -> Result<Vec<String>> {
+        Ok(self.inner.lock().users.keys().cloned().collect())
+    }
+
+    fn index_users(&self, _usernames: &[String]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn get_user(&self, username: &str, field: UserField) -> Result<Option<String>> {
+        let inner = self.inner.lock();
+        let value = inner
+            .users
+            .get(username)
+            .and_then(|user| user.get(&field))
+            .cloned();
+        Ok(value)
+    }
+
+    fn set_user(&self, username: &str, field: UserField, value: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .users
+            .entry(username.to_string())
+            .or_default()
+            .insert(field, value.to_string());
+        Ok(())
+    }
+
+    fn update_user(
+        &self,
+        username: &str,
+        field: UserField,
+        update: &mut dyn FnMut(Option<&str>) -> Result<Option<String>>,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let value = inner.users.get(username).and_then(|user| user.get(&field));
+        if let Some(value) = update(value.map(String::as_str))? {
+            inner
+                .users
+                .entry(username.to_string())
+                .or_default()
+                .insert(field, value);
+        }
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str, field: UserField) -> Result<()> {
+        if let Some(user) = self.inner.lock().users.get_mut(username) {
+            user.remove(&field);
+        }
+        Ok(())
+    }
+
+    fn insert_session(&self, id: &str, username: &str, value: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .sessions
+            .insert(id.to_string(), (username.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<String>> {
+        let inner = self.inner.lock();
+        Ok(inner.sessions.get(id).map(|(_, value)| value.clone()))
+    }
+
+    fn delete_session(&self, id: &str) -> Result<()> {
+        self.inner.lock().sessions.remove(id);
+        Ok(())
+    }
+
+    fn user_sessions(&self, username: &str) -> Result<Vec<String>> {
+        let inner = self.inner.lock();
+        let sessions = inner
+            .sessions
+            .iter()
+            .filter(|(_, (user, _))| user == username)
+            .map(|(id, _)| id.clone())
+            .collect();
+        Ok(sessions)
+    }
+
+    fn redeem_invitation(&self, id: &str, expiration: DateTime) -> Result<bool> {
+        let now = DateTime::now();
+        let mut inner = self.inner.lock();
+        inner.invitations.retain(|_, expiration| *expiration >= now);
+        if inner.invitations.contains_key(id) {
+            return Ok(false);
+        }
+        inner.invitations.insert(id.to_string(), expiration);
+        Ok(true)
+    }
+
+    fn invitations(&self) -> Result<Vec<(String, DateTime)>> {
+        let now = DateTime::now();
+        let inner = self.inner.lock();
+        let invitations = inner
+            .invitations
+            .iter()
+            .filter(|(_, expiration)| **expiration >= now)
+            .map(|(id, expiration)| (id.clone(), *expiration))
+            .collect();
+        Ok(invitations)
+    }
+
+    fn push_handshake(&self, kind: Handshake, id: &str, value: &str) -> Result<()> {
+        let now = DateTime::now();
+        let mut inner = self.inner.lock();
+        inner
+            .handshakes
+            .retain(|_, (_, created_at)| now.duration_since(*created_at) <= HANDSHAKE_RETENTION);
+        inner
+            .handshakes
+            .insert((kind, id.to_string()), (value.to_string(), now));
+        Ok(())
+    }
+
+    fn pull_handshake(&self, kind: Handshake, id: &str) -> Result<Option<String>> {
+        let mut inner = self.inner.lock();
+        let handshake = inner.handshakes.remove(&(kind, id.to_string()));
+        Ok(handshake.map(|(value, _)| value))
+    }
+}
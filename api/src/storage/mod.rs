@@ -0,0 +1,783 @@
+//! Encrypted storage
+//!
+//! Values are encrypted using a data key, randomly generated when the storage
+//! is created. The data key is stored in the storage itself, wrapped with the
+//! storage key from the configuration: rotating the storage key only requires
+//! to wrap again the data key, the values are left untouched.
+//!
+//! The values saved before the encryption are read as they are until the
+//! `migrate` command encrypts them, then the storage is marked as sealed and
+//! the values without encryption are refused. A new storage is sealed from
+//! the start.
+//!
+//! The encrypted records are saved by a [`Backend`]: the key-value storage,
+//! where each record is saved under a `prefix:key` string, or a SQLite
+//! database with a table for each kind of record. When the path is
+//! `:memory:` the records are kept in memory and lost at exit.
+
+use std::{path::Path, str::FromStr, time::Instant};
+
+use anyhow::{anyhow, bail, Result};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand_core::{CryptoRngCore, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{config::ConfigKey, metrics::StorageMetrics, rng, time::DateTime};
+
+mod kv;
+mod memory;
+mod sqlite;
+
+/// Path selecting the in-memory storage.
+pub const MEMORY: &str = ":memory:";
+
+/// Prefix of the encrypted values.
+const SEALED_PREFIX: &str = "$enc1$";
+
+/// Length in bytes of the keys.
+const KEY_BYTES: usize = 32;
+
+/// Length in bytes of the key identifier.
+const KID_BYTES: usize = 8;
+
+/// Length in bytes of the nonce.
+const NONCE_BYTES: usize = 24;
+
+/// Key used to wrap the data key, the backup archives are encrypted with a
+/// key of the same kind.
+pub struct StorageKey {
+    key: Zeroizing<[u8; KEY_BYTES]>,
+    kid: String,
+}
+
+impl StorageKey {
+    /// Generate a new random storage key, returned encoded.
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> String {
+        let mut key = Zeroizing::new([0_u8; KEY_BYTES]);
+        rng.fill_bytes(key.as_mut());
+        Base64Url::encode_string(key.as_slice())
+    }
+
+    /// Returns the key identifier.
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// Encrypt the message with the key, the associated data is
+    /// authenticated but not encrypted.
+    pub fn seal(&self, aad: &[u8], msg: &[u8]) -> Result<String> {
+        seal(&self.key, aad, msg)
+    }
+
+    /// Decrypt the message produced by [`StorageKey::seal`].
+    pub fn open(&self, aad: &[u8], sealed: &str) -> Result<Vec<u8>> {
+        open(&self.key, aad, sealed)
+    }
+
+    /// Encrypt the data key.
+    fn wrap_data_key(&self, data_key: &[u8; KEY_BYTES]) -> Result<String> {
+        self.seal(self.kid.as_bytes(), data_key)
+    }
+
+    /// Decrypt the data key.
+    fn unwrap_data_key(&self, wrapped_data_key: &str) -> Result<Zeroizing<[u8; KEY_BYTES]>> {
+        let data_key = Zeroizing::new(self.open(self.kid.as_bytes(), wrapped_data_key)?);
+        let data_key: [u8; KEY_BYTES] = data_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("data key has wrong length"))?;
+        Ok(Zeroizing::new(data_key))
+    }
+}
+
+impl FromStr for StorageKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut key = Zeroizing::new([0_u8; KEY_BYTES]);
+        let decoded_key = Base64Url::decode(s.as_bytes(), key.as_mut())
+            .map_err(|_| anyhow!("invalid storage key"))?;
+        if decoded_key.len() != KEY_BYTES {
+            bail!("invalid storage key");
+        }
+
+        let digest = Sha256::digest(key.as_slice());
+        let kid = Base64UrlUnpadded::encode_string(&digest[..KID_BYTES]);
+        Ok(Self { key, kid })
+    }
+}
+
+/// Set of storage keys.
+///
+/// The current key wraps the data key, the retired ones are used only to
+/// unwrap a data key not yet migrated.
+pub struct StorageKeys {
+    current: StorageKey,
+    retired: Vec<StorageKey>,
+}
+
+impl StorageKeys {
+    /// Create a new set of keys from the current one and the retired ones.
+    pub fn new(current: StorageKey, retired: Vec<StorageKey>) -> Self {
+        Self { current, retired }
+    }
+
+    /// Load the storage keys from the configuration.
+    pub fn load(config: &mut ConfigKey) -> Result<Self> {
+        let current = config.storage()?.parse()?;
+        let retired = config
+            .retired_storage()?
+            .iter()
+            .map(|key| key.parse())
+            .collect::<Result<Vec<StorageKey>>>()?;
+        Ok(Self::new(current, retired))
+    }
+
+    /// Returns the identifier of the current key.
+    pub fn current_kid(&self) -> &str {
+        self.current.kid()
+    }
+
+    /// Search the key with the given identifier.
+    fn find(&self, kid: &str) -> Option<&StorageKey> {
+        std::iter::once(&self.current)
+            .chain(&self.retired)
+            .find(|key| key.kid() == kid)
+    }
+}
+
+/// Wrapped data key, as saved in the storage.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WrappedDataKey {
+    /// Identifier of the storage key.
+    kid: String,
+    /// Encrypted data key.
+    key: String,
+    /// All the values are encrypted, the ones without encryption are refused.
+    #[serde(default)]
+    sealed: bool,
+}
+
+/// Backend of the storage.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Key-value storage, each record is saved under a `prefix:key` string.
+    #[default]
+    Kv,
+    /// SQLite database, each kind of record is saved in its own table.
+    Sqlite,
+}
+
+/// Record of the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UserField {
+    /// OPAQUE password file, the user is registered when present.
+    Password,
+    /// Email address.
+    Email,
+    /// TOTP authenticator.
+    Totp,
+    /// Recovery codes.
+    Recovery,
+    /// Known devices.
+    Devices,
+    /// WebAuthn credentials.
+    Webauthn,
+    /// Audit log.
+    Audit,
+    /// Password hash imported from another system, removed when the user
+    /// registers with OPAQUE.
+    Legacy,
+}
+
+impl UserField {
+    /// All the records of the user.
+    pub const ALL: [UserField; 8] = [
+        Self::Password,
+        Self::Email,
+        Self::Totp,
+        Self::Recovery,
+        Self::Devices,
+        Self::Webauthn,
+        Self::Audit,
+        Self::Legacy,
+    ];
+
+    /// Returns the name of the record.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::Email => "email",
+            Self::Totp => "totp",
+            Self::Recovery => "recovery",
+            Self::Devices => "devices",
+            Self::Webauthn => "webauthn",
+            Self::Audit => "audit",
+            Self::Legacy => "legacy",
+        }
+    }
+}
+
+/// Pending handshake, removed from the storage when completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Handshake {
+    /// Registration started with an invitation.
+    Signup,
+    /// Login waiting for the finalization.
+    Signin,
+    /// Re-registration granted by a login, waiting for the registration
+    /// request.
+    Reregister,
+    /// Re-registration waiting for the password file.
+    ReregisterUpload,
+    /// Login waiting for the second factor.
+    Mfa,
+    /// Registration of a security key.
+    WebauthnRegistration,
+    /// New email address waiting for the confirmation.
+    EmailConfirmation,
+}
+
+impl Handshake {
+    /// Returns the name of the handshake.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Signup => "signup-session",
+            Self::Signin => "signin-session",
+            Self::Reregister => "reregister-session",
+            Self::ReregisterUpload => "reregister-upload",
+            Self::Mfa => "mfa-session",
+            Self::WebauthnRegistration => "webauthn-registration",
+            Self::EmailConfirmation => "email-confirmation",
+        }
+    }
+}
+
+/// Name of the user sessions, used as prefix of the associated data.
+const SESSION: &str = "session";
+
+/// Operations on the stored records.
+///
+/// The values are saved as they are, they are already encrypted by
+/// [`Storage`].
+pub trait Backend: Send + Sync {
+    /// Returns the wrapped data key.
+    fn data_key(&self) -> Result<Option<WrappedDataKey>>;
+
+    /// Save the wrapped data key.
+    fn set_data_key(&self, data_key: &WrappedDataKey) -> Result<()>;
+
+    /// Returns the names of the known users.
+    fn usernames(&self) -> Result<Vec<String>>;
+
+    /// Add the users with at least a record to the list of known users,
+    /// returns the number of added users. Only the key-value storage can miss
+    /// some users, the other backends always list all of them.
+    fn index_users(&self, usernames: &[String]) -> Result<usize>;
+
+    /// Returns the record of the user.
+    fn get_user(&self, username: &str, field: UserField) -> Result<Option<String>>;
+
+    /// Save the record of the user, the user is created if missing.
+    fn set_user(&self, username: &str, field: UserField, value: &str) -> Result<()>;
+
+    /// Replace the record of the user with the one returned by `update`,
+    /// given the current one, with no other write in between. The record is
+    /// left unchanged when `update` returns `None`.
+    fn update_user(
+        &self,
+        username: &str,
+        field: UserField,
+        update: &mut dyn FnMut(Option<&str>) -> Result<Option<String>>,
+    ) -> Result<()>;
+
+    /// Remove the record of the user.
+    fn delete_user(&self, username: &str, field: UserField) -> Result<()>;
+
+    /// Save the session of the user.
+    fn insert_session(&self, id: &str, username: &str, value: &str) -> Result<()>;
+
+    /// Returns the session.
+    fn get_session(&self, id: &str) -> Result<Option<String>>;
+
+    /// Remove the session.
+    fn delete_session(&self, id: &str) -> Result<()>;
+
+    /// Returns the identifiers of the sessions of the user.
+    fn user_sessions(&self, username: &str) -> Result<Vec<String>>;
+
+    /// Mark the invitation as redeemed, returns `false` if it was already.
+    fn redeem_invitation(&self, id: &str, expiration: DateTime) -> Result<bool>;
+
+    /// Returns the redeemed invitations not yet expired.
+    fn invitations(&self) -> Result<Vec<(String, DateTime)>>;
+
+    /// Save the pending handshake, replacing the one with the same identifier.
+    fn push_handshake(&self, kind: Handshake, id: &str, value: &str) -> Result<()>;
+
+    /// Remove the pending handshake and returns it.
+    fn pull_handshake(&self, kind: Handshake, id: &str) -> Result<Option<String>>;
+}
+
+/// Storage with encrypted values.
+pub struct Storage {
+    backend: Box<dyn Backend>,
+    data_key: Zeroizing<[u8; KEY_BYTES]>,
+    sealed: bool,
+    metrics: Option<StorageMetrics>,
+}
+
+impl Storage {
+    /// Open the storage, generating the data key on first use.
+    ///
+    /// If the data key is wrapped with a retired storage key, it is wrapped
+    /// again with the current one. The path [`MEMORY`] selects the in-memory
+    /// storage, it is accepted only with the default backend.
+    pub fn open(backend: StorageBackend, path: &Path, keys: &StorageKeys) -> Result<Self> {
+        let backend: Box<dyn Backend> = match backend {
+            StorageBackend::Kv if path == Path::new(MEMORY) => {
+                tracing::warn!("storage is kept in memory, the records are lost at exit");
+                Box::new(memory::MemoryBackend::new())
+            }
+            StorageBackend::Kv => Box::new(kv::KvBackend::open(path)?),
+            StorageBackend::Sqlite if path == Path::new(MEMORY) => {
+                bail!("the sqlite backend cannot be kept in memory");
+            }
+            StorageBackend::Sqlite => Box::new(sqlite::SqliteBackend::open(path)?),
+        };
+
+        let (data_key, sealed) = match backend.data_key()? {
+            Some(WrappedDataKey { kid, key, sealed }) => {
+                let storage_key = keys
+                    .find(&kid)
+                    .ok_or_else(|| anyhow!("data key wrapped with unknown storage key '{kid}'"))?;
+                let data_key = storage_key.unwrap_data_key(&key)?;
+                if kid != keys.current.kid() {
+                    tracing::info!(
+                        "wrapping data key with storage key '{}'",
+                        keys.current.kid()
+                    );
+                    write_data_key(backend.as_ref(), &keys.current, &data_key, sealed)?;
+                }
+                (data_key, sealed)
+            }
+            None => {
+                tracing::info!("generating a new data key");
+                let mut data_key = Zeroizing::new([0_u8; KEY_BYTES]);
+                rng::with_crypto_rng(|rng| rng.fill_bytes(data_key.as_mut()));
+                write_data_key(backend.as_ref(), &keys.current, &data_key, true)?;
+                (data_key, true)
+            }
+        };
+
+        Ok(Self {
+            backend,
+            data_key,
+            sealed,
+            metrics: None,
+        })
+    }
+
+    /// Mark the storage as sealed once all the values are encrypted, from
+    /// now on the values without encryption are refused.
+    pub fn seal(&mut self) -> Result<()> {
+        if self.sealed {
+            return Ok(());
+        }
+        let mut wrapped_data_key = self
+            .backend
+            .data_key()?
+            .ok_or_else(|| anyhow!("missing data key"))?;
+        wrapped_data_key.sealed = true;
+        self.backend.set_data_key(&wrapped_data_key)?;
+        self.sealed = true;
+        Ok(())
+    }
+
+    /// Record the latency of the operations on the backend.
+    pub fn with_metrics(self, metrics: StorageMetrics) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Returns the metrics of the storage, if recorded.
+    pub fn metrics(&self) -> Option<&StorageMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Returns the names of the known users.
+    pub fn usernames(&self) -> Result<Vec<String>> {
+        self.timed("usernames", |backend| backend.usernames())
+    }
+
+    /// Add the users with at least a record to the list of known users,
+    /// returns the number of added users.
+    pub fn index_users(&self, usernames: &[String]) -> Result<usize> {
+        self.timed("index_users", |backend| backend.index_users(usernames))
+    }
+
+    /// Check if the record of the user is present.
+    pub fn has_user(&self, username: &str, field: UserField) -> Result<bool> {
+        Ok(self
+            .timed("get_user", |backend| backend.get_user(username, field))?
+            .is_some())
+    }
+
+    /// Returns the record of the user.
+    pub fn get_user<T: DeserializeOwned>(
+        &self,
+        username: &str,
+        field: UserField,
+    ) -> Result<Option<T>> {
+        let aad = format!("{}:{username}", field.as_str());
+        let value = self.timed("get_user", |backend| backend.get_user(username, field))?;
+        value.map(|value| self.decrypt(&aad, &value)).transpose()
+    }
+
+    /// Set the record of the user.
+    pub fn set_user<T: Serialize>(
+        &self,
+        username: &str,
+        field: UserField,
+        value: &T,
+    ) -> Result<()> {
+        let aad = format!("{}:{username}", field.as_str());
+        let value = self.encrypt(&aad, value)?;
+        self.timed("set_user", |backend| {
+            backend.set_user(username, field, &value)
+        })
+    }
+
+    /// Update the record of the user with no other write in between, `update`
+    /// changes the current record and returns `None` to leave it unchanged.
+    /// Returns the output of `update`, `None` if the record is missing.
+    pub fn update_user<T: Serialize + DeserializeOwned, R>(
+        &self,
+        username: &str,
+        field: UserField,
+        update: impl FnOnce(&mut T) -> Result<Option<R>>,
+    ) -> Result<Option<R>> {
+        let aad = format!("{}:{username}", field.as_str());
+        let mut update = Some(update);
+        let mut output = None;
+        self.timed("update_user", |backend| {
+            backend.update_user(username, field, &mut |value| {
+                let (Some(value), Some(update)) = (value, update.take()) else {
+                    return Ok(None);
+                };
+                let mut record = self.decrypt(&aad, value)?;
+                output = update(&mut record)?;
+                if output.is_none() {
+                    return Ok(None);
+                }
+                self.encrypt(&aad, &record).map(Some)
+            })
+        })?;
+        Ok(output)
+    }
+
+    /// Encrypt the records of the user saved before the encryption, returns
+    /// the number of rewritten records.
+    pub fn seal_user(&self, username: &str) -> Result<usize> {
+        let mut sealed = 0;
+        for field in UserField::ALL {
+            let value = self.timed("get_user", |backend| backend.get_user(username, field))?;
+            let Some(value) = value.filter(|value| !value.starts_with(SEALED_PREFIX)) else {
+                continue;
+            };
+            let value: serde_json::Value = serde_json::from_str(&value)?;
+            self.set_user(username, field, &value)?;
+            sealed += 1;
+        }
+        Ok(sealed)
+    }
+
+    /// Remove the record of the user.
+    pub fn delete_user(&self, username: &str, field: UserField) -> Result<()> {
+        self.timed("delete_user", |backend| {
+            backend.delete_user(username, field)
+        })
+    }
+
+    /// Save the session of the user.
+    pub fn insert_session<T: Serialize>(
+        &self,
+        id: &str,
+        username: &str,
+        session: &T,
+    ) -> Result<()> {
+        let value = self.encrypt(&format!("{SESSION}:{id}"), session)?;
+        self.timed("insert_session", |backend| {
+            backend.insert_session(id, username, &value)
+        })
+    }
+
+    /// Returns the session.
+    pub fn get_session<T: DeserializeOwned>(&self, id: &str) -> Result<Option<T>> {
+        let value = self.timed("get_session", |backend| backend.get_session(id))?;
+        value
+            .map(|value| self.decrypt(&format!("{SESSION}:{id}"), &value))
+            .transpose()
+    }
+
+    /// Remove the session.
+    pub fn delete_session(&self, id: &str) -> Result<()> {
+        self.timed("delete_session", |backend| backend.delete_session(id))
+    }
+
+    /// Returns the identifiers of the sessions of the user.
+    pub fn user_sessions(&self, username: &str) -> Result<Vec<String>> {
+        self.timed("user_sessions", |backend| backend.user_sessions(username))
+    }
+
+    /// Mark the invitation as redeemed, returns `false` if it was already.
+    pub fn redeem_invitation(&self, id: &str, expiration: DateTime) -> Result<bool> {
+        self.timed("redeem_invitation", |backend| {
+            backend.redeem_invitation(id, expiration)
+        })
+    }
+
+    /// Save the pending handshake, replacing the one with the same identifier.
+    pub fn push_handshake<T: Serialize>(&self, kind: Handshake, id: &str, value: &T) -> Result<()> {
+        let value = self.encrypt(&format!("{}:{id}", kind.as_str()), value)?;
+        self.timed("push_handshake", |backend| {
+            backend.push_handshake(kind, id, &value)
+        })
+    }
+
+    /// Remove the pending handshake and returns it.
+    pub fn pull_handshake<T: DeserializeOwned>(
+        &self,
+        kind: Handshake,
+        id: &str,
+    ) -> Result<Option<T>> {
+        let value = self.timed("pull_handshake", |backend| backend.pull_handshake(kind, id))?;
+        value
+            .map(|value| self.decrypt(&format!("{}:{id}", kind.as_str()), &value))
+            .transpose()
+    }
+
+    /// Copy the records of the users, their sessions and the redeemed
+    /// invitations to the target storage, returns the number of users.
+    ///
+    /// The records are encrypted again with the data key of the target, the
+    /// ones saved before the encryption included.
+    ///
+    /// The pending handshakes are short lived, they are not copied.
+    pub fn copy_to(&self, target: &Storage, usernames: &[String]) -> Result<usize> {
+        let mut copied = 0;
+        for username in usernames {
+            let mut found = false;
+            for field in UserField::ALL {
+                if let Some(value) = self.get_user::<serde_json::Value>(username, field)? {
+                    target.set_user(username, field, &value)?;
+                    found = true;
+                }
+            }
+            if !found {
+                tracing::warn!("user {username} not found");
+                continue;
+            }
+            for id in self.user_sessions(username)? {
+                if let Some(session) = self.get_session::<serde_json::Value>(&id)? {
+                    target.insert_session(&id, username, &session)?;
+                }
+            }
+            copied += 1;
+        }
+
+        for (id, expiration) in self.backend.invitations()? {
+            target.redeem_invitation(&id, expiration)?;
+        }
+        Ok(copied)
+    }
+
+    /// Run the operation on the backend, observing its latency.
+    fn timed<T>(
+        &self,
+        operation: &'static str,
+        f: impl FnOnce(&dyn Backend) -> Result<T>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = f(self.backend.as_ref());
+        if let Some(metrics) = &self.metrics {
+            metrics.observe(operation, start.elapsed());
+        }
+        result
+    }
+
+    /// Encrypt the value, the associated data binds it to its record.
+    fn encrypt<T: Serialize>(&self, aad: &str, value: &T) -> Result<String> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(value)?);
+        let sealed = seal(&self.data_key, aad.as_bytes(), &plaintext)?;
+        Ok(format!("{SEALED_PREFIX}{sealed}"))
+    }
+
+    /// Decrypt the value, values stored before the encryption are accepted
+    /// as they are until the storage is sealed by the `migrate` command or
+    /// copied by `migrate-storage`.
+    fn decrypt<T: DeserializeOwned>(&self, aad: &str, value: &str) -> Result<T> {
+        let Some(sealed) = value.strip_prefix(SEALED_PREFIX) else {
+            if self.sealed {
+                bail!("value of {aad} is not encrypted");
+            }
+            return serde_json::from_str(value).map_err(Into::into);
+        };
+
+        let plaintext = Zeroizing::new(open(&self.data_key, aad.as_bytes(), sealed)?);
+        serde_json::from_slice(&plaintext).map_err(Into::into)
+    }
+}
+
+/// Wrap the data key and save it in the storage.
+fn write_data_key(
+    backend: &dyn Backend,
+    storage_key: &StorageKey,
+    data_key: &[u8; KEY_BYTES],
+    sealed: bool,
+) -> Result<()> {
+    let wrapped_data_key = WrappedDataKey {
+        kid: storage_key.kid().to_string(),
+        key: storage_key.wrap_data_key(data_key)?,
+        sealed,
+    };
+    backend.set_data_key(&wrapped_data_key)
+}
+
+/// Encrypt the message, the nonce is prepended to the ciphertext.
+fn seal(key: &[u8; KEY_BYTES], aad: &[u8], msg: &[u8]) -> Result<String> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let mut nonce = XNonce::default();
+    rng::with_crypto_rng(|rng| rng.fill_bytes(&mut nonce));
+
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg, aad })
+        .map_err(|_| anyhow!("failed to encrypt value"))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(Base64Url::encode_string(&sealed))
+}
+
+/// Decrypt the message produced by [`seal`].
+fn open(key: &[u8; KEY_BYTES], aad: &[u8], sealed: &str) -> Result<Vec<u8>> {
+    let sealed = Base64Url::decode_vec(sealed)?;
+    if sealed.len() < NONCE_BYTES {
+        bail!("encrypted value is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("failed to decrypt value"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use claym::*;
+
+    fn keys() -> StorageKeys {
+        let storage_key = assert_ok!(rng::with_crypto_rng(StorageKey::generate).parse());
+        StorageKeys::new(storage_key, Vec::new())
+    }
+
+    /// Open an empty storage in memory, with a new storage key.
+    pub fn storage() -> Storage {
+        assert_ok!(Storage::open(
+            StorageBackend::Kv,
+            Path::new(MEMORY),
+            &keys()
+        ))
+    }
+
+    #[test]
+    fn open_sealed_message() {
+        let key = [7_u8; KEY_BYTES];
+        let sealed = assert_ok!(seal(&key, b"password:user", b"message"));
+        let message = assert_ok!(open(&key, b"password:user", &sealed));
+        assert_eq!(message, b"message");
+    }
+
+    #[test]
+    fn reject_sealed_message_moved_to_another_key() {
+        let key = [7_u8; KEY_BYTES];
+        let sealed = assert_ok!(seal(&key, b"password:user", b"message"));
+        assert!(open(&key, b"password:admin", &sealed).is_err());
+    }
+
+    #[test]
+    fn unwrap_data_key_with_storage_key() {
+        let storage_key: StorageKey =
+            assert_ok!(rng::with_crypto_rng(StorageKey::generate).parse());
+        let data_key = [42_u8; KEY_BYTES];
+        let wrapped_data_key = assert_ok!(storage_key.wrap_data_key(&data_key));
+        let unwrapped_data_key = assert_ok!(storage_key.unwrap_data_key(&wrapped_data_key));
+        assert_eq!(*unwrapped_data_key, data_key);
+    }
+
+    #[test]
+    fn reject_sqlite_backend_in_memory() {
+        let storage = Storage::open(StorageBackend::Sqlite, Path::new(MEMORY), &keys());
+        assert!(storage.is_err());
+    }
+
+    #[test]
+    fn seal_records_saved_before_encryption() {
+        let storage = storage();
+        assert_ok!(storage
+            .backend
+            .set_user("user", UserField::Email, r#""user@example.com""#));
+
+        assert_eq!(assert_ok!(storage.seal_user("user")), 1);
+        assert_eq!(assert_ok!(storage.seal_user("user")), 0);
+        let value = assert_some!(assert_ok!(storage
+            .backend
+            .get_user("user", UserField::Email)));
+        assert!(value.starts_with(SEALED_PREFIX));
+        let email = assert_ok!(storage.get_user::<String>("user", UserField::Email));
+        assert_eq!(email.as_deref(), Some("user@example.com"));
+    }
+
+    #[test]
+    fn refuse_plaintext_once_sealed() {
+        let mut storage = storage();
+        assert!(storage.sealed);
+        let email = r#""user@example.com""#;
+        assert_ok!(storage.backend.set_user("user", UserField::Email, email));
+        assert!(storage
+            .get_user::<String>("user", UserField::Email)
+            .is_err());
+
+        // storage created before the encryption
+        let mut wrapped_data_key = assert_some!(assert_ok!(storage.backend.data_key()));
+        wrapped_data_key.sealed = false;
+        assert_ok!(storage.backend.set_data_key(&wrapped_data_key));
+        storage.sealed = false;
+        assert_ok!(storage.get_user::<String>("user", UserField::Email));
+
+        assert_ok!(storage.seal());
+        assert!(storage
+            .get_user::<String>("user", UserField::Email)
+            .is_err());
+        let wrapped_data_key = assert_some!(assert_ok!(storage.backend.data_key()));
+        assert!(wrapped_data_key.sealed);
+    }
+}
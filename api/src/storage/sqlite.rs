@@ -0,0 +1,374 @@
+//! SQLite backend
+//!
+//! Each kind of record is saved in its own table, the schema is upgraded at
+//! startup applying the missing migrations.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use super::{Backend, Handshake, UserField, WrappedDataKey};
+use crate::time::DateTime;
+
+/// Schema migrations, the version of the schema (`user_version`) is the
+/// number of the applied ones. Never change an already released migration.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    password TEXT,
+    email TEXT,
+    totp TEXT,
+    recovery TEXT,
+    devices TEXT,
+    webauthn TEXT,
+    audit TEXT
+);
+
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users (username) ON DELETE CASCADE,
+    value TEXT NOT NULL
+);
+CREATE INDEX sessions_username ON sessions (username);
+
+CREATE TABLE invitations (
+    id TEXT PRIMARY KEY,
+    expires_at INTEGER NOT NULL
+);
+CREATE INDEX invitations_expires_at ON invitations (expires_at);
+
+CREATE TABLE handshakes (
+    kind TEXT NOT NULL,
+    id TEXT NOT NULL,
+    value TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (kind, id)
+);
+CREATE INDEX handshakes_created_at ON handshakes (created_at);
+"#,
+    r#"
+ALTER TABLE users ADD COLUMN legacy TEXT;
+"#,
+];
+
+/// Key of the wrapped data key in the `meta` table.
+const DATA_KEY: &str = "data-key";
+
+/// Abandoned handshakes are removed after this number of seconds.
+const HANDSHAKE_RETENTION: i64 = 60 * 60;
+
+/// Storage backed by a SQLite database.
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// Open the database, applying the missing migrations.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Prepare the connection, applying the missing migrations.
+    fn new(mut conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Apply the migrations not yet applied, each one in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let Some(pending) = MIGRATIONS.get(version as usize..) else {
+        bail!("storage schema version {version} is newer than the supported one");
+    };
+
+    for (migration, version) in pending.iter().zip(version + 1..) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        tracing::info!("storage schema migrated to version {version}");
+    }
+    Ok(())
+}
+
+impl Backend for SqliteBackend {
+    fn data_key(&self) -> Result<Option<WrappedDataKey>> {
+        let value = self
+            .conn
+            .lock()
+            .query_row("SELECT value FROM meta WHERE key = ?1", [DATA_KEY], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?;
+        let data_key = value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?;
+        Ok(data_key)
+    }
+
+    fn set_data_key(&self, data_key: &WrappedDataKey) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![DATA_KEY, serde_json::to_string(data_key)?],
+        )?;
+        Ok(())
+    }
+
+    fn usernames(&self) -> Result<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT username FROM users ORDER BY username")?;
+        let usernames = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(usernames)
+    }
+
+    fn index_users(&self, _usernames: &[String]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn get_user(&self, username: &str, field: UserField) -> Result<Option<String>> {
+        let column = field.as_str();
+        let value = self
+            .conn
+            .lock()
+            .query_row(
+                &format!("SELECT {column} FROM users WHERE username = ?1"),
+                [username],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?;
+        Ok(value.flatten())
+    }
+
+    fn set_user(&self, username: &str, field: UserField, value: &str) -> Result<()> {
+        let column = field.as_str();
+        self.conn.lock().execute(
+            &format!(
+                "INSERT INTO users (username, {column}) VALUES (?1, ?2)
+                 ON CONFLICT (username) DO UPDATE SET {column} = excluded.{column}"
+            ),
+            params![username, value],
+        )?;
+        Ok(())
+    }
+
+    fn update_user(
+        &self,
+        username: &str,
+        field: UserField,
+        update: &mut dyn FnMut(Option<&str>) -> Result<Option<String>>,
+    ) -> Result<()> {
+        let column = field.as_str();
+        let mut conn = self.conn.lock();
+        // the write lock is taken first so other processes can't write in between
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let value = tx
+            .query_row(
+                &format!("SELECT {column} FROM users WHERE username = ?1"),
+                [username],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?
+            .flatten();
+        if let Some(value) = update(value.as_deref())? {
+            tx.execute(
+                &format!(
+                    "INSERT INTO users (username, {column}) VALUES (?1, ?2)
+                     ON CONFLICT (username) DO UPDATE SET {column} = excluded.{column}"
+                ),
+                params![username, value],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str, field: UserField) -> Result<()> {
+        let column = field.as_str();
+        self.conn.lock().execute(
+            &format!("UPDATE users SET {column} = NULL WHERE username = ?1"),
+            [username],
+        )?;
+        Ok(())
+    }
+
+    fn insert_session(&self, id: &str, username: &str, value: &str) -> Result<()> {
+        self.conn.lock().execute(
+            "INSERT OR REPLACE INTO sessions (id, username, value) VALUES (?1, ?2, ?3)",
+            params![id, username, value],
+        )?;
+        Ok(())
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<String>> {
+        let value = self
+            .conn
+            .lock()
+            .query_row("SELECT value FROM sessions WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(value)
+    }
+
+    fn delete_session(&self, id: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn user_sessions(&self, username: &str) -> Result<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT id FROM sessions WHERE username = ?1")?;
+        let sessions = stmt
+            .query_map([username], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(sessions)
+    }
+
+    fn redeem_invitation(&self, id: &str, expiration: DateTime) -> Result<bool> {
+        let conn = self.conn.lock();
+        conn.execute(
+            "DELETE FROM invitations WHERE expires_at < ?1",
+            [DateTime::now().unix_timestamp()],
+        )?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO invitations (id, expires_at) VALUES (?1, ?2)",
+            params![id, expiration.unix_timestamp()],
+        )?;
+        Ok(inserted == 1)
+    }
+
+    fn invitations(&self) -> Result<Vec<(String, DateTime)>> {
+        let conn = self.conn.lock();
+        let mut stmt =
+            conn.prepare("SELECT id, expires_at FROM invitations WHERE expires_at >= ?1")?;
+        let invitations = stmt
+            .query_map([DateTime::now().unix_timestamp()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        invitations
+            .into_iter()
+            .map(|(id, expires_at)| Ok((id, DateTime::from_unix_timestamp(expires_at)?)))
+            .collect()
+    }
+
+    fn push_handshake(&self, kind: Handshake, id: &str, value: &str) -> Result<()> {
+        let now = DateTime::now().unix_timestamp();
+        let conn = self.conn.lock();
+        conn.execute(
+            "DELETE FROM handshakes WHERE created_at < ?1",
+            [now - HANDSHAKE_RETENTION],
+        )?;
+        conn.execute(
+            "INSERT OR REPLACE INTO handshakes (kind, id, value, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![kind.as_str(), id, value, now],
+        )?;
+        Ok(())
+    }
+
+    fn pull_handshake(&self, kind: Handshake, id: &str) -> Result<Option<String>> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        let value = tx
+            .query_row(
+                "SELECT value FROM handshakes WHERE kind = ?1 AND id = ?2",
+                [kind.as_str(), id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        tx.execute(
+            "DELETE FROM handshakes WHERE kind = ?1 AND id = ?2",
+            [kind.as_str(), id],
+        )?;
+        tx.commit()?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::time::Duration;
+
+    fn backend() -> SqliteBackend {
+        let conn = assert_ok!(Connection::open_in_memory());
+        assert_ok!(SqliteBackend::new(conn))
+    }
+
+    #[test]
+    fn apply_migrations_once() {
+        let mut conn = assert_ok!(Connection::open_in_memory());
+        assert_ok!(migrate(&mut conn));
+        assert_ok!(migrate(&mut conn));
+
+        let version: u32 =
+            assert_ok!(conn.pragma_query_value(None, "user_version", |row| row.get(0)));
+        assert_eq!(version as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn set_user_fields_independently() {
+        let backend = backend();
+        assert_ok!(backend.set_user("user", UserField::Email, "email"));
+        assert_ok!(backend.set_user("user", UserField::Password, "password"));
+
+        let email = assert_ok!(backend.get_user("user", UserField::Email));
+        assert_eq!(assert_some!(email), "email");
+        assert_none!(assert_ok!(backend.get_user("user", UserField::Totp)));
+        assert_eq!(assert_ok!(backend.usernames()), ["user"]);
+
+        assert_ok!(backend.delete_user("user", UserField::Email));
+        assert_none!(assert_ok!(backend.get_user("user", UserField::Email)));
+        assert_some!(assert_ok!(backend.get_user("user", UserField::Password)));
+    }
+
+    #[test]
+    fn index_sessions_by_user() {
+        let backend = backend();
+        assert_ok!(backend.set_user("user", UserField::Password, "password"));
+        assert_ok!(backend.insert_session("first", "user", "session"));
+        assert_ok!(backend.insert_session("second", "user", "session"));
+        assert_ok!(backend.delete_session("first"));
+
+        assert_eq!(assert_ok!(backend.user_sessions("user")), ["second"]);
+    }
+
+    #[test]
+    fn redeem_invitation_once() {
+        let backend = backend();
+        let expiration = DateTime::now() + Duration::hours(1);
+        assert!(assert_ok!(backend.redeem_invitation("code", expiration)));
+        assert!(!assert_ok!(backend.redeem_invitation("code", expiration)));
+    }
+
+    #[test]
+    fn pull_handshake_once() {
+        let backend = backend();
+        assert_ok!(backend.push_handshake(Handshake::Signin, "id", "handshake"));
+
+        let handshake = assert_ok!(backend.pull_handshake(Handshake::Signin, "id"));
+        assert_eq!(assert_some!(handshake), "handshake");
+        assert_none!(assert_ok!(backend.pull_handshake(Handshake::Signin, "id")));
+    }
+}
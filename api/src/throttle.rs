@@ -0,0 +1,131 @@
+//! Request throttling
+//!
+//! The attempts are counted in fixed windows, both for the target username
+//! and for the address of the client: the first limit slows down the guessing
+//! on a single account, the second the spraying on many accounts. The counters
+//! are kept in memory, they are lost at restart.
+
+use std::{collections::HashMap, hash::Hash, net::IpAddr};
+
+use parking_lot::Mutex;
+
+use crate::time::{DateTime, Duration};
+
+/// Limits of the attempts of an operation.
+pub struct Throttle {
+    by_username: Counters<String>,
+    by_address: Counters<IpAddr>,
+}
+
+impl Throttle {
+    /// Create the throttle allowing the given number of attempts for each
+    /// username and for each address in the window.
+    pub fn new(per_username: u32, per_address: u32, window: Duration) -> Self {
+        Self {
+            by_username: Counters::new(per_username, window),
+            by_address: Counters::new(per_address, window),
+        }
+    }
+
+    /// Count the attempt, returns `false` if one of the limits is exceeded.
+    pub fn check(&self, username: &str, address: IpAddr) -> bool {
+        let by_username = self.by_username.check(username.to_string());
+        let by_address = self.by_address.check(address);
+        by_username && by_address
+    }
+}
+
+/// Attempts in the current window, by key.
+struct Counters<K> {
+    limit: u32,
+    window: Duration,
+    inner: Mutex<Inner<K>>,
+}
+
+struct Inner<K> {
+    windows: HashMap<K, Window>,
+    /// Last removal of the expired windows.
+    pruned_at: DateTime,
+}
+
+struct Window {
+    started_at: DateTime,
+    attempts: u32,
+}
+
+impl<K: Eq + Hash> Counters<K> {
+    fn new(limit: u32, window: Duration) -> Self {
+        let inner = Inner {
+            windows: HashMap::new(),
+            pruned_at: DateTime::now(),
+        };
+        Self {
+            limit,
+            window,
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Count the attempt, returns `false` if the limit is exceeded.
+    fn check(&self, key: K) -> bool {
+        let now = DateTime::now();
+        let mut inner = self.inner.lock();
+
+        // the expired windows are removed once per window, so the counters of
+        // the clients gone away do not pile up
+        if now.duration_since(inner.pruned_at) > self.window {
+            let window = self.window;
+            inner
+                .windows
+                .retain(|_, counter| now.duration_since(counter.started_at) <= window);
+            inner.pruned_at = now;
+        }
+
+        let counter = inner.windows.entry(key).or_insert(Window {
+            started_at: now,
+            attempts: 0,
+        });
+        if now.duration_since(counter.started_at) > self.window {
+            counter.started_at = now;
+            counter.attempts = 0;
+        }
+        counter.attempts = counter.attempts.saturating_add(1);
+        counter.attempts <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn limit_attempts_by_username() {
+        let throttle = Throttle::new(2, 10, Duration::hours(1));
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(throttle.check("user", address));
+        assert!(throttle.check("user", address));
+        assert!(!throttle.check("user", address));
+        assert!(throttle.check("other", address));
+    }
+
+    #[test]
+    fn limit_attempts_by_address() {
+        let throttle = Throttle::new(10, 2, Duration::hours(1));
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(throttle.check("user", address));
+        assert!(throttle.check("other", address));
+        assert!(!throttle.check("another", address));
+        assert!(throttle.check("another", IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn reset_attempts_after_window() {
+        let throttle = Throttle::new(1, 1, Duration::ZERO);
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(throttle.check("user", address));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        assert!(throttle.check("user", address));
+    }
+}
@@ -38,6 +38,16 @@ impl DateTime {
         Self(time::OffsetDateTime::now_utc())
     }
 
+    /// Returns the number of seconds since the unix epoch.
+    pub fn unix_timestamp(&self) -> i64 {
+        self.0.unix_timestamp()
+    }
+
+    /// Create from the number of seconds since the unix epoch.
+    pub fn from_unix_timestamp(timestamp: i64) -> Result<Self, time::error::ComponentRange> {
+        time::OffsetDateTime::from_unix_timestamp(timestamp).map(Self)
+    }
+
     /// Returns the amount of time elapsed.
     pub fn duration_since(&self, earlier: DateTime) -> Duration {
         Duration(self.0 - earlier.0)
@@ -63,6 +73,16 @@ impl DateTime {
     }
 }
 
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let formatted = self
+            .0
+            .format(&time::format_description::well_known::Rfc3339)
+            .map_err(|_| std::fmt::Error)?;
+        f.write_str(&formatted)
+    }
+}
+
 impl Add<Duration> for DateTime {
     type Output = Self;
 
@@ -0,0 +1,153 @@
+//! Service tokens
+//!
+//! Services calling the privileged endpoints are authenticated using a bearer
+//! token. Only the digest of each token is saved in the configuration, with
+//! the list of scopes granted to it.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
+use rand_core::CryptoRngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Length in bytes of the generated tokens.
+const TOKEN_BYTES: usize = 32;
+
+/// Length in bytes of the token digest.
+const DIGEST_BYTES: usize = 32;
+
+/// Permission granted to a service token.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum Scope {
+    /// Read the user sessions.
+    #[serde(rename = "session:read")]
+    SessionRead,
+    /// Generate invitations for new users.
+    #[serde(rename = "admin:invite")]
+    AdminInvite,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SessionRead => f.write_str("session:read"),
+            Self::AdminInvite => f.write_str("admin:invite"),
+        }
+    }
+}
+
+/// Service token configuration.
+#[derive(Deserialize)]
+pub struct ConfigToken {
+    /// Token digest, as printed by `genkey token`.
+    pub hash: String,
+    /// Granted scopes.
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+/// Named service token.
+pub struct ServiceToken {
+    name: String,
+    digest: [u8; DIGEST_BYTES],
+    scopes: Vec<Scope>,
+}
+
+impl ServiceToken {
+    /// Generate a new random token, returns the token and its digest.
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> (String, String) {
+        let mut bytes = [0_u8; TOKEN_BYTES];
+        rng.fill_bytes(&mut bytes);
+        let token = Base64UrlUnpadded::encode_string(&bytes);
+        let digest = Base64Url::encode_string(&digest(&token));
+        (token, digest)
+    }
+
+    /// Returns the name of the token.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check if the scope is granted to the token.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+/// Set of service tokens.
+pub struct ServiceTokens {
+    tokens: Vec<ServiceToken>,
+}
+
+impl ServiceTokens {
+    /// Create the set of tokens from the configuration.
+    pub fn new(config: &HashMap<String, ConfigToken>) -> Result<Self> {
+        let tokens = config
+            .iter()
+            .map(|(name, token)| {
+                let mut digest = [0_u8; DIGEST_BYTES];
+                let decoded = Base64Url::decode(token.hash.as_bytes(), &mut digest)
+                    .map_err(|_| anyhow!("invalid hash of service token '{name}'"))?;
+                if decoded.len() != DIGEST_BYTES {
+                    return Err(anyhow!("invalid hash of service token '{name}'"));
+                }
+                Ok(ServiceToken {
+                    name: name.clone(),
+                    digest,
+                    scopes: token.scopes.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { tokens })
+    }
+
+    /// Search the service token, the digests are compared in constant time.
+    pub fn authenticate(&self, token: &str) -> Option<&ServiceToken> {
+        let digest = digest(token);
+        self.tokens.iter().fold(None, |found, service_token| {
+            let is_equal = service_token.digest[..].ct_eq(&digest[..]);
+            if bool::from(is_equal) {
+                Some(service_token)
+            } else {
+                found
+            }
+        })
+    }
+}
+
+/// Compute the digest of the token.
+fn digest(token: &str) -> [u8; DIGEST_BYTES] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    use crate::rng;
+
+    #[test]
+    fn authenticate_service_token() {
+        let (token, hash) = rng::with_crypto_rng(ServiceToken::generate);
+        let config = HashMap::from([(
+            "frontend".to_string(),
+            ConfigToken {
+                hash,
+                scopes: vec![Scope::SessionRead],
+            },
+        )]);
+        let tokens = assert_ok!(ServiceTokens::new(&config));
+
+        let service_token = tokens.authenticate(&token).unwrap();
+        assert_eq!(service_token.name(), "frontend");
+        assert!(service_token.has_scope(Scope::SessionRead));
+        assert!(!service_token.has_scope(Scope::AdminInvite));
+
+        assert!(tokens.authenticate("invalid-token").is_none());
+    }
+}
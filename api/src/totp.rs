@@ -0,0 +1,174 @@
+//! Time-based one-time passwords (RFC 6238)
+//!
+//! The codes are generated with HMAC-SHA1, 6 digits and a period of 30
+//! seconds, the defaults supported by every authenticator app. The secret is
+//! shared with the authenticator using a provisioning URI (`otpauth://`),
+//! usually rendered as a QR code.
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use rand_core::CryptoRngCore;
+use sha1::Sha1;
+use zeroize::Zeroizing;
+
+use crate::config::Secret;
+
+/// Length in bytes of the generated secrets.
+const SECRET_BYTES: usize = 20;
+
+/// Number of digits of the codes.
+const DIGITS: u32 = 6;
+
+/// Validity of each code in seconds.
+const PERIOD: i64 = 30;
+
+/// Number of periods accepted before and after the current one, to tolerate
+/// clock drifts.
+const SKEW: i64 = 1;
+
+/// Alphabet of the base32 encoding (RFC 4648).
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Shared secret of the TOTP authenticator.
+pub struct TotpSecret {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl TotpSecret {
+    /// Generate a new random secret.
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> Self {
+        let mut secret = Zeroizing::new(vec![0_u8; SECRET_BYTES]);
+        rng.fill_bytes(&mut secret);
+        Self { secret }
+    }
+
+    /// Decode the secret from base32.
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let mut secret = Zeroizing::new(Vec::with_capacity(encoded.len() * 5 / 8));
+        let mut buffer = 0_u32;
+        let mut bits = 0;
+        for c in encoded.bytes().filter(|c| *c != b'=') {
+            let Some(value) = BASE32_ALPHABET
+                .iter()
+                .position(|a| *a == c.to_ascii_uppercase())
+            else {
+                bail!("invalid TOTP secret");
+            };
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                secret.push((buffer >> bits) as u8);
+            }
+        }
+        Ok(Self { secret })
+    }
+
+    /// Encode the secret in base32, without padding.
+    pub fn encode(&self) -> Secret {
+        let mut encoded = Zeroizing::new(String::with_capacity(self.secret.len() * 8 / 5 + 1));
+        let mut buffer = 0_u32;
+        let mut bits = 0;
+        for byte in self.secret.iter() {
+            buffer = (buffer << 8) | u32::from(*byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+        }
+        encoded
+    }
+
+    /// Returns the URI used to configure the authenticator app.
+    pub fn provisioning_uri(&self, issuer: &str, username: &str) -> Secret {
+        let issuer = percent_encode(issuer);
+        let username = percent_encode(username);
+        let secret = self.encode();
+        Zeroizing::new(format!(
+            "otpauth://totp/{issuer}:{username}?secret={}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+            secret.as_str()
+        ))
+    }
+
+    /// Verify the code at the given unix timestamp, returns the time step of
+    /// the code.
+    ///
+    /// The codes of the time steps up to `last_step` are rejected, so each
+    /// code can be used only once.
+    pub fn verify(&self, code: &str, timestamp: i64, last_step: Option<u64>) -> Option<u64> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize || !code.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let code: u32 = code.parse().ok()?;
+
+        let current = timestamp.div_euclid(PERIOD);
+        let first_step = last_step.map_or(0, |last_step| last_step + 1);
+        (current - SKEW..=current + SKEW)
+            .filter_map(|step| u64::try_from(step).ok())
+            .filter(|step| *step >= first_step)
+            .find(|step| self.code(*step) == code)
+    }
+
+    /// Compute the code of the time step (RFC 4226).
+    fn code(&self, step: u64) -> u32 {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).expect("any key length");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        binary % 10_u32.pow(DIGITS)
+    }
+}
+
+/// Percent encoding of the URI components, only unreserved characters are
+/// left as they are.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|c| match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (c as char).to_string()
+            }
+            _ => format!("%{c:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    /// Secret of the test vectors of RFC 6238.
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn verify_rfc_test_vectors() {
+        let secret = assert_ok!(TotpSecret::decode(RFC_SECRET));
+        assert_eq!(secret.encode().as_str(), RFC_SECRET);
+
+        assert_some!(secret.verify("287082", 59, None));
+        assert_some!(secret.verify("081804", 1111111109, None));
+        assert_some!(secret.verify("005924", 1234567890, None));
+        assert_none!(secret.verify("005925", 1234567890, None));
+    }
+
+    #[test]
+    fn reject_used_codes() {
+        let secret = assert_ok!(TotpSecret::decode(RFC_SECRET));
+        let step = assert_some!(secret.verify("005924", 1234567890, None));
+        assert_none!(secret.verify("005924", 1234567890, Some(step)));
+    }
+}
@@ -2,40 +2,41 @@
 
 use anyhow::Result;
 use cookie::Cookie;
-use mello::kvstorage::KVStorage;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::{
-    opaque::{LoginState, PasswordFile},
+    config::Secret,
+    invitation::InvitationCode,
+    legacy::LegacyHash,
+    metrics::StorageMetrics,
+    opaque::{LoginState, OpaqueSignature, PasswordFile},
+    record::{self, Stored, Upgrade, Versioned},
+    recovery::RecoveryCodes,
     session::SessionId,
+    storage::{Handshake, Storage, UserField},
     time::{DateTime, Duration},
+    totp::TotpSecret,
+    webauthn::{Challenge, Credential},
 };
 
-const SIGNUP_SESSION: &str = "signup-session";
-const SIGNIN_SESSION: &str = "signin-session";
-const SESSION: &str = "session";
-const PASSWORD: &str = "password";
-
 /// Function related to user's table.
 pub trait UserTable {
     /// Check if the user has been already registered.
     fn user_is_registered(&self, username: &str) -> Result<bool>;
 
-    /// Register a new user, removing the used invitation.
+    /// Register a new user, removing the legacy password hash if any.
     fn register_user_password(&self, username: &str, password_file: PasswordFile) -> Result<()>;
 }
 
-impl UserTable for KVStorage {
+impl UserTable for Storage {
     fn user_is_registered(&self, username: &str) -> Result<bool> {
-        self.read()?
-            .has(format!("{PASSWORD}:{username}"))
-            .map_err(Into::into)
+        self.has_user(username, UserField::Password)
     }
 
     fn register_user_password(&self, username: &str, password_file: PasswordFile) -> Result<()> {
-        self.write()
-            .set(format!("{PASSWORD}:{username}"), &password_file)
-            .map_err(Into::into)
+        self.set_user(username, UserField::Password, &Stored(password_file))?;
+        self.delete_user(username, UserField::Legacy)
     }
 }
 
@@ -43,9 +44,24 @@ impl UserTable for KVStorage {
 #[derive(Deserialize, Serialize)]
 pub struct SignupSession {
     pub username: String,
+    /// Invitation used to start the registration, redeemed when completed.
+    pub code: Option<InvitationCode>,
     created_at: DateTime,
 }
 
+impl Versioned for SignupSession {
+    const UPGRADES: &'static [Upgrade] = &[upgrade_signup_session_v0];
+}
+
+/// Version 1: sessions saved before the invitations were redeemed get no
+/// invitation code.
+fn upgrade_signup_session_v0(mut value: Value) -> Result<Value> {
+    record::object(&mut value)?
+        .entry("code")
+        .or_insert(Value::Null);
+    Ok(value)
+}
+
 impl SignupSession {
     const LIFETIME: Duration = Duration::minutes(1);
 
@@ -53,10 +69,19 @@ impl SignupSession {
     pub fn new(username: String) -> Self {
         Self {
             username,
+            code: None,
             created_at: DateTime::now(),
         }
     }
 
+    /// Create a new signup session started with the invitation.
+    pub fn with_invitation(username: String, code: InvitationCode) -> Self {
+        Self {
+            code: Some(code),
+            ..Self::new(username)
+        }
+    }
+
     /// Check if the signup session is expired.
     fn is_expired(&self) -> bool {
         DateTime::now().duration_since(self.created_at) > Self::LIFETIME
@@ -64,22 +89,73 @@ impl SignupSession {
 }
 
 /// Push the signup session in the storage.
-pub fn push_signup_session(storage: &KVStorage, session: SignupSession) -> Result<SessionId> {
+pub fn push_signup_session(storage: &Storage, session: SignupSession) -> Result<SessionId> {
     let session_id = SessionId::random();
-    let key = format!("{SIGNUP_SESSION}:{}", session_id.display());
-    storage.write().set(key, &session)?;
+    storage.push_handshake(Handshake::Signup, &session_id.digest(), &Stored(session))?;
     Ok(session_id)
 }
 
 /// Pull the signup session from the storage.
 pub fn pull_signup_session(
-    storage: &KVStorage,
+    storage: &Storage,
+    session_id: SessionId,
+) -> Result<Option<SignupSession>> {
+    let session = storage
+        .pull_handshake::<Stored<SignupSession>>(Handshake::Signup, &session_id.digest())?
+        .map(|Stored(session)| session)
+        .filter(|session| !session.is_expired());
+    Ok(session)
+}
+
+/// Push the re-registration session in the storage.
+///
+/// The re-registration is used to migrate an already authenticated user to
+/// the current server setup.
+pub fn push_reregister_session(storage: &Storage, session: SignupSession) -> Result<SessionId> {
+    let session_id = SessionId::random();
+    storage.push_handshake(
+        Handshake::Reregister,
+        &session_id.digest(),
+        &Stored(session),
+    )?;
+    Ok(session_id)
+}
+
+/// Pull the re-registration session from the storage.
+pub fn pull_reregister_session(
+    storage: &Storage,
     session_id: SessionId,
 ) -> Result<Option<SignupSession>> {
-    let key = format!("{SIGNUP_SESSION}:{}", session_id.display());
     let session = storage
-        .write()
-        .extract::<_, SignupSession>(key)?
+        .pull_handshake::<Stored<SignupSession>>(Handshake::Reregister, &session_id.digest())?
+        .map(|Stored(session)| session)
+        .filter(|session| !session.is_expired());
+    Ok(session)
+}
+
+/// Push the started re-registration in the storage, waiting for the
+/// password file.
+///
+/// It is kept apart from the re-registration session, so that the token
+/// given by the login can not skip the registration start.
+pub fn push_reregister_upload(storage: &Storage, session: SignupSession) -> Result<SessionId> {
+    let session_id = SessionId::random();
+    storage.push_handshake(
+        Handshake::ReregisterUpload,
+        &session_id.digest(),
+        &Stored(session),
+    )?;
+    Ok(session_id)
+}
+
+/// Pull the started re-registration from the storage.
+pub fn pull_reregister_upload(
+    storage: &Storage,
+    session_id: SessionId,
+) -> Result<Option<SignupSession>> {
+    let session = storage
+        .pull_handshake::<Stored<SignupSession>>(Handshake::ReregisterUpload, &session_id.digest())?
+        .map(|Stored(session)| session)
         .filter(|session| !session.is_expired());
     Ok(session)
 }
@@ -89,17 +165,33 @@ pub fn pull_signup_session(
 pub struct SigninSession {
     pub username: String,
     pub state: LoginState,
+    /// The user should be re-registered with the current server setup.
+    pub reregister: bool,
     created_at: DateTime,
 }
 
+impl Versioned for SigninSession {
+    const UPGRADES: &'static [Upgrade] = &[upgrade_signin_session_v0];
+}
+
+/// Version 1: sessions saved before the server setup rotation do not
+/// require the re-registration.
+fn upgrade_signin_session_v0(mut value: Value) -> Result<Value> {
+    record::object(&mut value)?
+        .entry("reregister")
+        .or_insert(Value::Bool(false));
+    Ok(value)
+}
+
 impl SigninSession {
     const LIFETIME: Duration = Duration::minutes(1);
 
     /// Create a new signin session with the given data.
-    pub fn new(username: String, state: LoginState) -> Self {
+    pub fn new(username: String, state: LoginState, reregister: bool) -> Self {
         Self {
             username,
             state,
+            reregister,
             created_at: DateTime::now(),
         }
     }
@@ -111,47 +203,131 @@ impl SigninSession {
 }
 
 /// Push the signin session in the storage.
-pub fn push_signin_session(storage: &KVStorage, session: SigninSession) -> Result<SessionId> {
+pub fn push_signin_session(storage: &Storage, session: SigninSession) -> Result<SessionId> {
     let session_id = SessionId::random();
-    let key = format!("{SIGNIN_SESSION}:{}", session_id.display());
-    storage.write().set(key, &session)?;
+    storage.push_handshake(Handshake::Signin, &session_id.digest(), &Stored(session))?;
     Ok(session_id)
 }
+
 /// Pull the signin session from the storage.
 pub fn pull_signin_session(
-    storage: &KVStorage,
+    storage: &Storage,
     session_id: SessionId,
 ) -> Result<Option<SigninSession>> {
-    let key = format!("{SIGNIN_SESSION}:{}", session_id.display());
     let session = storage
-        .write()
-        .extract::<_, SigninSession>(key)?
+        .pull_handshake::<Stored<SigninSession>>(Handshake::Signin, &session_id.digest())?
+        .map(|Stored(session)| session)
         .filter(|session| !session.is_expired());
     Ok(session)
 }
 
-/// Register a new user, removing the used invitation.
-pub fn get_password_file(storage: &KVStorage, username: &str) -> Result<Option<PasswordFile>> {
-    let key = format!("{PASSWORD}:{}", username);
-    let password_file = storage.read()?.get(key)?;
+/// Pending second factor session, the password of the user has been already
+/// verified.
+#[derive(Deserialize, Serialize)]
+pub struct MfaSession {
+    pub username: String,
+    /// Challenge of the pending WebAuthn assertion.
+    #[serde(default)]
+    pub challenge: Option<Challenge>,
+    created_at: DateTime,
+}
+
+impl MfaSession {
+    const LIFETIME: Duration = Duration::minutes(5);
+
+    /// Create a new second factor session for the user.
+    pub fn new(username: String) -> Self {
+        Self {
+            username,
+            challenge: None,
+            created_at: DateTime::now(),
+        }
+    }
+
+    /// Check if the second factor session is expired.
+    fn is_expired(&self) -> bool {
+        DateTime::now().duration_since(self.created_at) > Self::LIFETIME
+    }
+}
+
+/// Push the second factor session in the storage.
+pub fn push_mfa_session(storage: &Storage, session: MfaSession) -> Result<SessionId> {
+    let session_id = SessionId::random();
+    storage.push_handshake(Handshake::Mfa, &session_id.digest(), &session)?;
+    Ok(session_id)
+}
+
+/// Pull the second factor session from the storage.
+pub fn pull_mfa_session(storage: &Storage, session_id: SessionId) -> Result<Option<MfaSession>> {
+    let session = storage
+        .pull_handshake::<MfaSession>(Handshake::Mfa, &session_id.digest())?
+        .filter(|session| !session.is_expired());
+    Ok(session)
+}
+
+/// Retrieve the password file of the user.
+pub fn get_password_file(storage: &Storage, username: &str) -> Result<Option<PasswordFile>> {
+    let password_file = storage
+        .get_user::<Stored<PasswordFile>>(username, UserField::Password)?
+        .map(|Stored(password_file)| password_file);
     Ok(password_file)
 }
 
+/// Record the signature in the password file saved without setup identifier,
+/// returns `false` if the password file has already one.
+pub fn bind_password_file(
+    storage: &Storage,
+    username: &str,
+    signature: &OpaqueSignature,
+) -> Result<bool> {
+    let Some(mut password_file) = get_password_file(storage, username)? else {
+        return Ok(false);
+    };
+    if !password_file.bind(signature) {
+        return Ok(false);
+    }
+    storage.set_user(username, UserField::Password, &Stored(password_file))?;
+    Ok(true)
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Session {
     pub username: String,
     created_at: DateTime,
 }
 
+impl Versioned for Session {
+    const UPGRADES: &'static [Upgrade] = &[upgrade_session_v0];
+}
+
+/// Version 1: the format is unchanged, only the version is added.
+fn upgrade_session_v0(value: Value) -> Result<Value> {
+    Ok(value)
+}
+
 impl Session {
     pub const LIFETIME: Duration = Duration::days(7);
     pub const COOKIE: &'static str = "SESSIONID";
 
+    /// Sensitive operations require a session started within this time.
+    const REAUTHENTICATION: Duration = Duration::minutes(5);
+
     /// Check if the session is expired.
     fn is_expired(&self) -> bool {
         DateTime::now().duration_since(self.created_at) > Self::LIFETIME
     }
 
+    /// Returns the end of the session.
+    fn expiration(&self) -> DateTime {
+        self.created_at + Self::LIFETIME
+    }
+
+    /// Check if the session has been started recently, the user has just
+    /// authenticated.
+    pub fn is_recent(&self) -> bool {
+        DateTime::now().duration_since(self.created_at) <= Self::REAUTHENTICATION
+    }
+
     /// Create the session cookie.
     fn create_cookie(session_id: SessionId) -> Cookie<'static> {
         let value = session_id.display().to_string();
@@ -174,33 +350,477 @@ impl Session {
 }
 
 /// Start a new session and return the cookie that should be set by the client.
-pub fn start_new_session(storage: &KVStorage, username: String) -> Result<Cookie<'static>> {
+pub fn start_new_session(storage: &Storage, username: String) -> Result<Cookie<'static>> {
     let session_id = SessionId::random();
     let session = Session {
-        username,
+        username: username.clone(),
         created_at: DateTime::now(),
     };
 
-    let key = format!("{SESSION}:{}", session_id.display());
-    storage.write().set(key, &session)?;
+    let id = session_id.digest();
+    let expiration = session.expiration();
+    storage.insert_session(&id, &username, &Stored(session))?;
+    session_started(storage, &id, expiration);
 
     Ok(Session::create_cookie(session_id))
 }
 
 /// End the session and return the cookie that should be set by the client.
-pub fn finish_session(storage: &KVStorage, session_id: SessionId) -> Result<Cookie<'static>> {
-    let key = format!("{SESSION}:{}", session_id.display());
-    storage.write().del(key)?;
+pub fn finish_session(storage: &Storage, session_id: SessionId) -> Result<Cookie<'static>> {
+    let id = session_id.digest();
+    storage.delete_session(&id)?;
+    session_ended(storage, &id);
     Ok(Session::remove_cookie())
 }
 
+/// End all the sessions of the user, returns the number of ended sessions.
+pub fn finish_user_sessions(storage: &Storage, username: &str) -> Result<usize> {
+    let sessions = storage.user_sessions(username)?;
+    for id in &sessions {
+        storage.delete_session(id)?;
+        session_ended(storage, id);
+    }
+    Ok(sessions.len())
+}
+
+/// Count the session among the active ones, if the metrics are recorded.
+fn session_started(storage: &Storage, id: &str, expiration: DateTime) {
+    if let Some(metrics) = storage.metrics() {
+        metrics.session_started(id, expiration);
+    }
+}
+
+/// Stop counting the session among the active ones.
+fn session_ended(storage: &Storage, id: &str) {
+    if let Some(metrics) = storage.metrics() {
+        metrics.session_ended(id);
+    }
+}
+
 /// Retrieve the session.
-pub fn get_session(storage: &KVStorage, session_id: SessionId) -> Result<Option<Session>> {
-    let key = format!("{SESSION}:{}", session_id.display());
-    let session = storage
-        .read()?
-        .get::<_, Session>(key)?
-        .filter(|session| !session.is_expired());
+///
+/// Sessions saved before the introduction of the digest are keyed by the
+/// session id itself, they cannot be listed so they are moved under the
+/// digest when used and removed once expired.
+pub fn get_session(storage: &Storage, session_id: SessionId) -> Result<Option<Session>> {
+    let id = session_id.digest();
+    let session = match storage.get_session::<Stored<Session>>(&id)? {
+        Some(Stored(session)) => Some(session),
+        None => pull_unhashed_session(storage, &session_id)?,
+    };
 
-    Ok(session)
+    Ok(session.filter(|session| !session.is_expired()))
+}
+
+/// Remove the session keyed by the session id, saving it again under the
+/// digest if not expired.
+fn pull_unhashed_session(storage: &Storage, session_id: &SessionId) -> Result<Option<Session>> {
+    let raw_id = session_id.display().to_string();
+    let Some(session) = storage.get_session::<Stored<Session>>(&raw_id)? else {
+        return Ok(None);
+    };
+    storage.delete_session(&raw_id)?;
+    if !session.0.is_expired() {
+        let id = session_id.digest();
+        storage.insert_session(&id, &session.0.username, &session)?;
+        session_started(storage, &id, session.0.expiration());
+    }
+    Ok(Some(session.0))
+}
+
+/// Count the sessions not yet expired of all the users among the active
+/// ones, returns their number.
+///
+/// The storage is scanned once at startup, the sessions are then counted as
+/// they are started and ended.
+pub fn track_sessions(storage: &Storage, metrics: &StorageMetrics) -> Result<usize> {
+    let mut count = 0;
+    for username in storage.usernames()? {
+        for id in storage.user_sessions(&username)? {
+            let Some(Stored(session)) = storage.get_session::<Stored<Session>>(&id)? else {
+                continue;
+            };
+            if !session.is_expired() {
+                metrics.session_started(&id, session.expiration());
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Rewrite the password file and the sessions of the user in the current
+/// format, returns `false` if the user is not registered.
+///
+/// The records saved before the encryption are encrypted. The pending
+/// handshakes expire in minutes, they are upgraded when read.
+pub fn migrate_records(storage: &Storage, username: &str) -> Result<bool> {
+    storage.seal_user(username)?;
+    let Some(password_file) = get_password_file(storage, username)? else {
+        return Ok(false);
+    };
+    storage.set_user(username, UserField::Password, &Stored(password_file))?;
+
+    for id in storage.user_sessions(username)? {
+        if let Some(session) = storage.get_session::<Stored<Session>>(&id)? {
+            storage.insert_session(&id, username, &session)?;
+        }
+    }
+    Ok(true)
+}
+
+/// TOTP authenticator of the user.
+#[derive(Deserialize, Serialize)]
+pub struct Totp {
+    /// Shared secret, base32 encoded.
+    pub secret: Secret,
+    /// The user has verified the first code, the second factor is required
+    /// at sign in.
+    pub confirmed: bool,
+    /// Time step of the last accepted code.
+    #[serde(default)]
+    pub last_step: Option<u64>,
+}
+
+/// Retrieve the legacy password hash of the user.
+pub fn get_legacy_hash(storage: &Storage, username: &str) -> Result<Option<LegacyHash>> {
+    storage.get_user(username, UserField::Legacy)
+}
+
+/// Returns the decoy of the legacy password hashes, with the most common
+/// parameters of the saved ones.
+pub fn legacy_decoy(storage: &Storage) -> Result<LegacyHash> {
+    let mut hashes = Vec::new();
+    for username in storage.usernames()? {
+        if let Some(hash) = get_legacy_hash(storage, &username)? {
+            hashes.push(hash);
+        }
+    }
+    Ok(LegacyHash::decoy(&hashes))
+}
+
+/// Save the legacy password hash of the user.
+pub fn set_legacy_hash(storage: &Storage, username: &str, hash: &LegacyHash) -> Result<()> {
+    storage.set_user(username, UserField::Legacy, hash)
+}
+
+/// Retrieve the TOTP authenticator of the user.
+pub fn get_totp(storage: &Storage, username: &str) -> Result<Option<Totp>> {
+    storage.get_user(username, UserField::Totp)
+}
+
+/// Save the TOTP authenticator of the user.
+pub fn set_totp(storage: &Storage, username: &str, totp: &Totp) -> Result<()> {
+    storage.set_user(username, UserField::Totp, totp)
+}
+
+/// Verify the code of the confirmed TOTP authenticator of the user and record
+/// its time step, so a code is accepted only once even by concurrent requests.
+/// Returns `false` if the user has no confirmed authenticator or the code is
+/// invalid.
+pub fn verify_totp(storage: &Storage, username: &str, code: &str, now: i64) -> Result<bool> {
+    let verified = storage.update_user(username, UserField::Totp, |totp: &mut Totp| {
+        if !totp.confirmed {
+            return Ok(None);
+        }
+        let secret = TotpSecret::decode(&totp.secret)?;
+        let Some(step) = secret.verify(code, now, totp.last_step) else {
+            return Ok(None);
+        };
+        totp.last_step = Some(step);
+        Ok(Some(()))
+    })?;
+    Ok(verified.is_some())
+}
+
+/// Retrieve the email address of the user.
+pub fn get_email(storage: &Storage, username: &str) -> Result<Option<String>> {
+    storage.get_user(username, UserField::Email)
+}
+
+/// Save the email address of the user.
+pub fn set_email(storage: &Storage, username: &str, email: &str) -> Result<()> {
+    storage.set_user(username, UserField::Email, &email)
+}
+
+/// New email address of the user, saved once confirmed with the link sent to
+/// it.
+#[derive(Deserialize, Serialize)]
+pub struct EmailConfirmation {
+    pub username: String,
+    pub email: String,
+    created_at: DateTime,
+}
+
+impl EmailConfirmation {
+    const LIFETIME: Duration = Duration::hours(1);
+
+    /// Create a new confirmation of the email address of the user.
+    pub fn new(username: String, email: String) -> Self {
+        Self {
+            username,
+            email,
+            created_at: DateTime::now(),
+        }
+    }
+
+    /// Check if the confirmation is expired.
+    fn is_expired(&self) -> bool {
+        DateTime::now().duration_since(self.created_at) > Self::LIFETIME
+    }
+}
+
+/// Push the email confirmation in the storage.
+pub fn push_email_confirmation(
+    storage: &Storage,
+    confirmation: EmailConfirmation,
+) -> Result<SessionId> {
+    let token = SessionId::random();
+    storage.push_handshake(Handshake::EmailConfirmation, &token.digest(), &confirmation)?;
+    Ok(token)
+}
+
+/// Pull the email confirmation from the storage.
+pub fn pull_email_confirmation(
+    storage: &Storage,
+    token: SessionId,
+) -> Result<Option<EmailConfirmation>> {
+    let confirmation = storage
+        .pull_handshake::<EmailConfirmation>(Handshake::EmailConfirmation, &token.digest())?
+        .filter(|confirmation| !confirmation.is_expired());
+    Ok(confirmation)
+}
+
+/// Device used to sign in, identified by a long-lived cookie.
+pub struct Device;
+
+impl Device {
+    pub const COOKIE: &'static str = "DEVICEID";
+    const LIFETIME: Duration = Duration::days(365);
+
+    /// Number of devices remembered for each user.
+    const MAX_DEVICES: usize = 20;
+
+    /// Create the device cookie.
+    fn create_cookie(device_id: &SessionId) -> Cookie<'static> {
+        let value = device_id.display().to_string();
+        Cookie::build((Self::COOKIE, value))
+            .secure(true)
+            .http_only(true)
+            .same_site(cookie::SameSite::Strict)
+            .path("/")
+            .max_age(Self::LIFETIME.into())
+            .build()
+    }
+}
+
+/// Device never used before by the user.
+pub struct NewDevice {
+    /// Cookie identifying the device.
+    pub cookie: Cookie<'static>,
+    /// The user has no other known device.
+    pub is_first: bool,
+}
+
+/// Remember the device of the user, returns `None` if already known.
+pub fn register_device(
+    storage: &Storage,
+    username: &str,
+    device_id: Option<SessionId>,
+) -> Result<Option<NewDevice>> {
+    let mut devices = storage
+        .get_user::<Vec<String>>(username, UserField::Devices)?
+        .unwrap_or_default();
+    if let Some(device_id) = &device_id {
+        if devices.contains(&device_id.digest()) {
+            return Ok(None);
+        }
+    }
+
+    let device_id = device_id.unwrap_or_else(SessionId::random);
+    let is_first = devices.is_empty();
+    devices.push(device_id.digest());
+    if devices.len() > Device::MAX_DEVICES {
+        devices.drain(..devices.len() - Device::MAX_DEVICES);
+    }
+    storage.set_user(username, UserField::Devices, &devices)?;
+
+    Ok(Some(NewDevice {
+        cookie: Device::create_cookie(&device_id),
+        is_first,
+    }))
+}
+
+/// Retrieve the recovery codes of the user.
+pub fn get_recovery_codes(storage: &Storage, username: &str) -> Result<Option<RecoveryCodes>> {
+    storage.get_user(username, UserField::Recovery)
+}
+
+/// Save the recovery codes of the user.
+pub fn set_recovery_codes(storage: &Storage, username: &str, codes: &RecoveryCodes) -> Result<()> {
+    storage.set_user(username, UserField::Recovery, codes)
+}
+
+/// Redeem one of the recovery codes of the user, so a code is accepted only
+/// once even by concurrent requests. Returns the number of unused codes, `None`
+/// if the user has no recovery codes or the code is invalid.
+pub fn redeem_recovery_code(
+    storage: &Storage,
+    username: &str,
+    code: &str,
+) -> Result<Option<usize>> {
+    storage.update_user(
+        username,
+        UserField::Recovery,
+        |codes: &mut RecoveryCodes| Ok(codes.redeem(code).then(|| codes.remaining())),
+    )
+}
+
+/// Retrieve the WebAuthn credentials of the user.
+pub fn get_webauthn_credentials(storage: &Storage, username: &str) -> Result<Vec<Credential>> {
+    let credentials = storage.get_user(username, UserField::Webauthn)?;
+    Ok(credentials.unwrap_or_default())
+}
+
+/// Save the WebAuthn credentials of the user.
+pub fn set_webauthn_credentials(
+    storage: &Storage,
+    username: &str,
+    credentials: &[Credential],
+) -> Result<()> {
+    storage.set_user(username, UserField::Webauthn, &credentials)
+}
+
+/// Pending WebAuthn registration.
+#[derive(Deserialize, Serialize)]
+struct WebauthnRegistration {
+    challenge: Challenge,
+    created_at: DateTime,
+}
+
+impl WebauthnRegistration {
+    const LIFETIME: Duration = Duration::minutes(5);
+
+    /// Check if the registration is expired.
+    fn is_expired(&self) -> bool {
+        DateTime::now().duration_since(self.created_at) > Self::LIFETIME
+    }
+}
+
+/// Push the challenge of the WebAuthn registration, replacing the pending
+/// one of the user.
+pub fn push_webauthn_registration(
+    storage: &Storage,
+    username: &str,
+    challenge: Challenge,
+) -> Result<()> {
+    let registration = WebauthnRegistration {
+        challenge,
+        created_at: DateTime::now(),
+    };
+    storage.push_handshake(Handshake::WebauthnRegistration, username, &registration)
+}
+
+/// Pull the challenge of the WebAuthn registration.
+pub fn pull_webauthn_registration(storage: &Storage, username: &str) -> Result<Option<Challenge>> {
+    let challenge = storage
+        .pull_handshake::<WebauthnRegistration>(Handshake::WebauthnRegistration, username)?
+        .filter(|registration| !registration.is_expired())
+        .map(|registration| registration.challenge);
+    Ok(challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+    use serde_json::json;
+
+    use crate::{rng, storage};
+
+    fn created_at() -> Value {
+        assert_ok!(serde_json::to_value(DateTime::now()))
+    }
+
+    #[test]
+    fn move_session_keyed_by_session_id() {
+        let storage = &storage::tests::storage();
+        let session_id = SessionId::random();
+        let raw_id = session_id.display().to_string();
+        let session = Session {
+            username: "user".to_string(),
+            created_at: DateTime::now(),
+        };
+        assert_ok!(storage.insert_session(&raw_id, "user", &Stored(session)));
+
+        let session = assert_some!(assert_ok!(get_session(storage, session_id)));
+        assert_eq!(session.username, "user");
+        assert_none!(assert_ok!(storage.get_session::<Value>(&raw_id)));
+        let session_id: SessionId = assert_ok!(raw_id.parse());
+        assert_some!(assert_ok!(get_session(storage, session_id)));
+    }
+
+    #[test]
+    fn upgrade_unversioned_session() {
+        let value = json!({ "username": "user", "created_at": created_at() });
+        let Stored(session) = assert_ok!(serde_json::from_value::<Stored<Session>>(value));
+        assert_eq!(session.username, "user");
+        assert!(!session.is_expired());
+
+        let value = assert_ok!(serde_json::to_value(Stored(session)));
+        assert_eq!(value["version"], 1);
+    }
+
+    #[test]
+    fn upgrade_signup_session_without_code() {
+        let value = json!({ "username": "user", "created_at": created_at() });
+        let Stored(session) = assert_ok!(serde_json::from_value::<Stored<SignupSession>>(value));
+        assert_eq!(session.username, "user");
+        assert!(session.code.is_none());
+    }
+
+    #[test]
+    fn upgrade_signin_session_without_reregister() {
+        let value = json!({ "username": "user", "state": "c3RhdGU", "created_at": created_at() });
+        let session = assert_ok!(record::upgrade::<SigninSession>(value));
+        assert_eq!(session["reregister"], false);
+        assert_eq!(session["state"], "c3RhdGU");
+    }
+
+    #[test]
+    fn keep_signin_session_to_reregister() {
+        let value = json!({
+            "version": 1,
+            "username": "user",
+            "state": "c3RhdGU",
+            "reregister": true,
+            "created_at": created_at(),
+        });
+        let session = assert_ok!(record::upgrade::<SigninSession>(value));
+        assert_eq!(session["reregister"], true);
+        assert!(session.get("version").is_none());
+    }
+
+    #[test]
+    fn redeem_recovery_code_once() {
+        let storage = &storage::tests::storage();
+        let (codes, recovery) = rng::with_crypto_rng(RecoveryCodes::generate);
+        assert_ok!(set_recovery_codes(storage, "user", &recovery));
+
+        let redeemed = std::thread::scope(|scope| {
+            let threads = (0..2)
+                .map(|_| {
+                    scope.spawn(|| assert_ok!(redeem_recovery_code(storage, "user", &codes[0])))
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .filter_map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(redeemed, [recovery.remaining() - 1]);
+        assert_none!(assert_ok!(redeem_recovery_code(storage, "user", &codes[0])));
+        assert_some!(assert_ok!(redeem_recovery_code(storage, "user", &codes[1])));
+    }
 }
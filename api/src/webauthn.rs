@@ -0,0 +1,693 @@
+//! WebAuthn second factor
+//!
+//! Minimal relying party of the registration and authentication ceremonies
+//! (WebAuthn Level 2). The attestation is not requested: the credentials are
+//! registered by users already signed in, so they are trusted as they are.
+//! Only ES256 and EdDSA credentials are supported.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ciborium::Value;
+use ed25519_dalek::Verifier;
+use rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Length in bytes of the challenges.
+const CHALLENGE_BYTES: usize = 32;
+
+/// Time given to the user to complete the ceremony, in milliseconds.
+const TIMEOUT: u32 = 60_000;
+
+/// COSE algorithm identifier of ECDSA with P-256 and SHA-256.
+const COSE_ALG_ES256: i64 = -7;
+
+/// COSE algorithm identifier of EdDSA.
+const COSE_ALG_EDDSA: i64 = -8;
+
+/// Flag of the authenticator data, the user is present.
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// Flag of the authenticator data, attested credential data included.
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// Relying party configuration.
+#[derive(Deserialize)]
+pub struct ConfigWebauthn {
+    /// Relying party identifier, the domain of the application.
+    pub id: String,
+    /// Origin of the application, like `https://example.com`.
+    pub origin: String,
+}
+
+/// Random challenge of a ceremony, base64url encoded.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Challenge(String);
+
+impl Challenge {
+    /// Generate a new random challenge.
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> Self {
+        let mut bytes = [0_u8; CHALLENGE_BYTES];
+        rng.fill_bytes(&mut bytes);
+        Self(Base64UrlUnpadded::encode_string(&bytes))
+    }
+}
+
+/// Registered credential of a user.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Credential {
+    /// Credential identifier, base64url encoded.
+    pub id: String,
+    /// COSE algorithm of the public key.
+    alg: i64,
+    /// Public key, SEC1 encoded for ES256 and raw for EdDSA.
+    key: String,
+    /// Signature counter of the authenticator.
+    counter: u32,
+}
+
+impl Credential {
+    /// Verify the signature of the assertion.
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let key = Base64UrlUnpadded::decode_vec(&self.key)
+            .map_err(|_| anyhow!("invalid public key of credential {}", self.id))?;
+        match self.alg {
+            COSE_ALG_ES256 => {
+                let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&key)?;
+                let signature = p256::ecdsa::Signature::from_der(signature)?;
+                key.verify(message, &signature)?;
+            }
+            COSE_ALG_EDDSA => {
+                let key = ed25519_dalek::VerifyingKey::from_bytes(
+                    key.as_slice()
+                        .try_into()
+                        .context("invalid Ed25519 public key")?,
+                )?;
+                let signature = ed25519_dalek::Signature::from_slice(signature)?;
+                key.verify_strict(message, &signature)?;
+            }
+            alg => bail!("unsupported algorithm {alg}"),
+        }
+        Ok(())
+    }
+}
+
+/// Relying party of the ceremonies.
+pub struct RelyingParty {
+    id: String,
+    name: String,
+    origin: String,
+}
+
+impl RelyingParty {
+    /// Create the relying party from the configuration, the name is shown by
+    /// the browser.
+    pub fn new(config: &ConfigWebauthn, name: &str) -> Self {
+        Self {
+            id: config.id.clone(),
+            name: name.to_string(),
+            origin: config.origin.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Options of the registration ceremony, passed to
+    /// `navigator.credentials.create`.
+    pub fn creation_options(
+        &self,
+        challenge: &Challenge,
+        username: &str,
+        credentials: &[Credential],
+    ) -> CreationOptions {
+        let user_id = Sha256::digest(username.as_bytes());
+        CreationOptions {
+            challenge: challenge.clone(),
+            rp: RpEntity {
+                id: self.id.clone(),
+                name: self.name.clone(),
+            },
+            user: UserEntity {
+                id: Base64UrlUnpadded::encode_string(&user_id),
+                name: username.to_string(),
+                display_name: username.to_string(),
+            },
+            pub_key_cred_params: [COSE_ALG_ES256, COSE_ALG_EDDSA]
+                .into_iter()
+                .map(|alg| CredentialParameters {
+                    kind: "public-key",
+                    alg,
+                })
+                .collect(),
+            timeout: TIMEOUT,
+            attestation: "none",
+            exclude_credentials: descriptors(credentials),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "discouraged",
+                user_verification: "preferred",
+            },
+        }
+    }
+
+    /// Verify the response of the registration ceremony, returns the new
+    /// credential.
+    pub fn register(
+        &self,
+        challenge: &Challenge,
+        response: &RegistrationResponse,
+    ) -> Result<Credential> {
+        let client_data_json = decode(&response.response.client_data_json)?;
+        self.verify_client_data(&client_data_json, "webauthn.create", challenge)?;
+
+        let attestation_object = decode(&response.response.attestation_object)?;
+        let attestation_object: Value = ciborium::de::from_reader(attestation_object.as_slice())
+            .context("invalid attestation object")?;
+        let auth_data = map_get(&attestation_object, Value::Text("authData".to_string()))
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| anyhow!("missing authenticator data"))?;
+        let auth_data = self.parse_auth_data(auth_data)?;
+        ensure!(
+            auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0,
+            "missing attested credential data"
+        );
+
+        // aaguid (16 bytes), credential id length (2 bytes), credential id
+        // and credential public key
+        let data = auth_data.rest;
+        ensure!(data.len() >= 18, "invalid attested credential data");
+        let id_len = u16::from_be_bytes([data[16], data[17]]) as usize;
+        ensure!(
+            data.len() >= 18 + id_len,
+            "invalid attested credential data"
+        );
+        let id = Base64UrlUnpadded::encode_string(&data[18..18 + id_len]);
+        ensure!(id == response.id, "credential identifier mismatch");
+
+        let mut cose_key = &data[18 + id_len..];
+        let cose_key: Value =
+            ciborium::de::from_reader(&mut cose_key).context("invalid credential public key")?;
+        let (alg, key) = parse_cose_key(&cose_key)?;
+
+        Ok(Credential {
+            id,
+            alg,
+            key: Base64UrlUnpadded::encode_string(&key),
+            counter: auth_data.counter,
+        })
+    }
+
+    /// Options of the authentication ceremony, passed to
+    /// `navigator.credentials.get`.
+    pub fn request_options(
+        &self,
+        challenge: &Challenge,
+        credentials: &[Credential],
+    ) -> RequestOptions {
+        RequestOptions {
+            challenge: challenge.clone(),
+            rp_id: self.id.clone(),
+            allow_credentials: descriptors(credentials),
+            timeout: TIMEOUT,
+            user_verification: "preferred",
+        }
+    }
+
+    /// Verify the response of the authentication ceremony, the signature
+    /// counter of the used credential is updated.
+    pub fn authenticate(
+        &self,
+        challenge: &Challenge,
+        credentials: &mut [Credential],
+        response: &AssertionResponse,
+    ) -> Result<()> {
+        let credential = credentials
+            .iter_mut()
+            .find(|credential| credential.id == response.id)
+            .ok_or_else(|| anyhow!("unknown credential {}", response.id))?;
+
+        let client_data_json = decode(&response.response.client_data_json)?;
+        self.verify_client_data(&client_data_json, "webauthn.get", challenge)?;
+
+        let auth_data_bytes = decode(&response.response.authenticator_data)?;
+        let auth_data = self.parse_auth_data(&auth_data_bytes)?;
+
+        let mut message = auth_data_bytes.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data_json));
+        let signature = decode(&response.response.signature)?;
+        credential.verify(&message, &signature)?;
+
+        // authenticators without a counter always return zero
+        if auth_data.counter != 0 || credential.counter != 0 {
+            ensure!(
+                auth_data.counter > credential.counter,
+                "signature counter of credential {} did not increase, the authenticator could be cloned",
+                credential.id
+            );
+        }
+        credential.counter = auth_data.counter;
+        Ok(())
+    }
+
+    /// Verify the client data collected by the browser.
+    fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        kind: &str,
+        challenge: &Challenge,
+    ) -> Result<()> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).context("invalid client data")?;
+        ensure!(
+            client_data.kind == kind,
+            "unexpected ceremony {}",
+            client_data.kind
+        );
+        ensure!(client_data.challenge == *challenge, "challenge mismatch");
+        ensure!(
+            client_data.origin == self.origin,
+            "unexpected origin {}",
+            client_data.origin
+        );
+        Ok(())
+    }
+
+    /// Parse the authenticator data, checking the relying party and the user
+    /// presence.
+    fn parse_auth_data<'a>(&self, bytes: &'a [u8]) -> Result<AuthenticatorData<'a>> {
+        ensure!(bytes.len() >= 37, "invalid authenticator data");
+        let rp_id_hash = Sha256::digest(self.id.as_bytes());
+        ensure!(bytes[..32] == rp_id_hash[..], "relying party mismatch");
+        let flags = bytes[32];
+        ensure!(flags & FLAG_USER_PRESENT != 0, "user is not present");
+        let counter = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+        Ok(AuthenticatorData {
+            flags,
+            counter,
+            rest: &bytes[37..],
+        })
+    }
+}
+
+/// Parsed authenticator data.
+struct AuthenticatorData<'a> {
+    flags: u8,
+    counter: u32,
+    /// Attested credential data and extensions.
+    rest: &'a [u8],
+}
+
+/// Client data collected by the browser.
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: Challenge,
+    origin: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    challenge: Challenge,
+    rp: RpEntity,
+    user: UserEntity,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: u32,
+    attestation: &'static str,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Serialize)]
+struct RpEntity {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserEntity {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Serialize)]
+struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: Challenge,
+    rp_id: String,
+    allow_credentials: Vec<CredentialDescriptor>,
+    timeout: u32,
+    user_verification: &'static str,
+}
+
+/// Response of the registration ceremony, binary fields are base64url
+/// encoded.
+#[derive(Deserialize)]
+pub struct RegistrationResponse {
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+}
+
+/// Response of the authentication ceremony, binary fields are base64url
+/// encoded.
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    id: String,
+    response: AuthenticatorAssertionResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticatorAssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+}
+
+/// Descriptors of the registered credentials.
+fn descriptors(credentials: &[Credential]) -> Vec<CredentialDescriptor> {
+    credentials
+        .iter()
+        .map(|credential| CredentialDescriptor {
+            kind: "public-key",
+            id: credential.id.clone(),
+        })
+        .collect()
+}
+
+/// Decode a base64url field of the responses.
+fn decode(value: &str) -> Result<Vec<u8>> {
+    Base64UrlUnpadded::decode_vec(value.trim_end_matches('='))
+        .map_err(|_| anyhow!("invalid base64url value"))
+}
+
+/// Returns the value of the key in a CBOR map.
+fn map_get(map: &Value, key: Value) -> Option<&Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value)
+}
+
+/// Returns the integer value of the key in a CBOR map.
+fn map_get_integer(map: &Value, key: i64) -> Option<i128> {
+    map_get(map, Value::Integer(key.into()))?
+        .as_integer()
+        .map(i128::from)
+}
+
+/// Returns the bytes value of the key in a CBOR map.
+fn map_get_bytes(map: &Value, key: i64) -> Option<&[u8]> {
+    map_get(map, Value::Integer(key.into()))?
+        .as_bytes()
+        .map(Vec::as_slice)
+}
+
+/// Parse the COSE key, returns the algorithm and the encoded public key.
+fn parse_cose_key(cose_key: &Value) -> Result<(i64, Vec<u8>)> {
+    // key type (1), algorithm (3), curve (-1) and coordinates (-2, -3)
+    let kty = map_get_integer(cose_key, 1);
+    let alg = map_get_integer(cose_key, 3);
+    let crv = map_get_integer(cose_key, -1);
+    match (kty, alg, crv) {
+        (Some(2), Some(alg), Some(1)) if alg == i128::from(COSE_ALG_ES256) => {
+            let x = map_get_bytes(cose_key, -2).context("missing x coordinate")?;
+            let y = map_get_bytes(cose_key, -3).context("missing y coordinate")?;
+            ensure!(x.len() == 32 && y.len() == 32, "invalid P-256 public key");
+            let mut key = vec![0x04];
+            key.extend_from_slice(x);
+            key.extend_from_slice(y);
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&key)?;
+            Ok((COSE_ALG_ES256, key))
+        }
+        (Some(1), Some(alg), Some(6)) if alg == i128::from(COSE_ALG_EDDSA) => {
+            let x = map_get_bytes(cose_key, -2).context("missing public key")?;
+            ed25519_dalek::VerifyingKey::from_bytes(
+                x.try_into().context("invalid Ed25519 public key")?,
+            )?;
+            Ok((COSE_ALG_EDDSA, x.to_vec()))
+        }
+        _ => bail!("unsupported credential public key"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+    use ed25519_dalek::Signer;
+    use rand_core::RngCore;
+
+    use crate::rng;
+
+    const RP_ID: &str = "example.com";
+    const ORIGIN: &str = "https://example.com";
+
+    /// Private key of the software authenticator.
+    enum SoftKey {
+        Es256(p256::ecdsa::SigningKey),
+        EdDsa(ed25519_dalek::SigningKey),
+    }
+
+    /// Software authenticator, it behaves like a browser and an authenticator
+    /// together.
+    struct SoftAuthenticator {
+        key: SoftKey,
+        id: Vec<u8>,
+        origin: String,
+        counter: u32,
+    }
+
+    impl SoftAuthenticator {
+        fn new(alg: i64) -> Self {
+            let key = rng::with_crypto_rng(|rng| match alg {
+                COSE_ALG_ES256 => SoftKey::Es256(p256::ecdsa::SigningKey::random(rng)),
+                _ => {
+                    let mut secret = [0_u8; 32];
+                    rng.fill_bytes(&mut secret);
+                    SoftKey::EdDsa(ed25519_dalek::SigningKey::from_bytes(&secret))
+                }
+            });
+            let id = rng::with_crypto_rng(|rng| {
+                let mut id = vec![0_u8; 16];
+                rng.fill_bytes(&mut id);
+                id
+            });
+            Self {
+                key,
+                id,
+                origin: ORIGIN.to_string(),
+                counter: 0,
+            }
+        }
+
+        fn cose_key(&self) -> Value {
+            let int = |value: i64| Value::Integer(value.into());
+            match &self.key {
+                SoftKey::Es256(key) => {
+                    let point = key.verifying_key().to_encoded_point(false);
+                    Value::Map(vec![
+                        (int(1), int(2)),
+                        (int(3), int(COSE_ALG_ES256)),
+                        (int(-1), int(1)),
+                        (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                        (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+                    ])
+                }
+                SoftKey::EdDsa(key) => Value::Map(vec![
+                    (int(1), int(1)),
+                    (int(3), int(COSE_ALG_EDDSA)),
+                    (int(-1), int(6)),
+                    (
+                        int(-2),
+                        Value::Bytes(key.verifying_key().to_bytes().to_vec()),
+                    ),
+                ]),
+            }
+        }
+
+        fn client_data(&self, kind: &str, challenge: &Challenge) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({
+                "type": kind,
+                "challenge": challenge.0,
+                "origin": self.origin,
+            }))
+            .unwrap()
+        }
+
+        fn auth_data(&mut self, rp_id: &str, flags: u8) -> Vec<u8> {
+            self.counter += 1;
+            let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&self.counter.to_be_bytes());
+            auth_data
+        }
+
+        fn create(&mut self, options: &CreationOptions) -> RegistrationResponse {
+            let client_data_json = self.client_data("webauthn.create", &options.challenge);
+            let mut auth_data = self.auth_data(
+                &options.rp.id,
+                FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA,
+            );
+            auth_data.extend_from_slice(&[0_u8; 16]);
+            auth_data.extend_from_slice(&(self.id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.id);
+            ciborium::ser::into_writer(&self.cose_key(), &mut auth_data).unwrap();
+
+            let attestation_object = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(vec![])),
+                (Value::Text("authData".into()), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object_bytes = Vec::new();
+            ciborium::ser::into_writer(&attestation_object, &mut attestation_object_bytes).unwrap();
+
+            RegistrationResponse {
+                id: Base64UrlUnpadded::encode_string(&self.id),
+                response: AttestationResponse {
+                    client_data_json: Base64UrlUnpadded::encode_string(&client_data_json),
+                    attestation_object: Base64UrlUnpadded::encode_string(&attestation_object_bytes),
+                },
+            }
+        }
+
+        fn get(&mut self, options: &RequestOptions) -> AssertionResponse {
+            let client_data_json = self.client_data("webauthn.get", &options.challenge);
+            let auth_data = self.auth_data(&options.rp_id, FLAG_USER_PRESENT);
+
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(&client_data_json));
+            let signature = match &self.key {
+                SoftKey::Es256(key) => {
+                    let signature: p256::ecdsa::Signature = key.sign(&message);
+                    signature.to_der().as_bytes().to_vec()
+                }
+                SoftKey::EdDsa(key) => key.sign(&message).to_bytes().to_vec(),
+            };
+
+            AssertionResponse {
+                id: Base64UrlUnpadded::encode_string(&self.id),
+                response: AuthenticatorAssertionResponse {
+                    client_data_json: Base64UrlUnpadded::encode_string(&client_data_json),
+                    authenticator_data: Base64UrlUnpadded::encode_string(&auth_data),
+                    signature: Base64UrlUnpadded::encode_string(&signature),
+                },
+            }
+        }
+    }
+
+    fn relying_party() -> RelyingParty {
+        let config = ConfigWebauthn {
+            id: RP_ID.to_string(),
+            origin: ORIGIN.to_string(),
+        };
+        RelyingParty::new(&config, "fresh-auth")
+    }
+
+    fn register(rp: &RelyingParty, authenticator: &mut SoftAuthenticator) -> Result<Credential> {
+        let challenge = rng::with_crypto_rng(Challenge::generate);
+        let options = rp.creation_options(&challenge, "user", &[]);
+        let response = authenticator.create(&options);
+        rp.register(&challenge, &response)
+    }
+
+    fn authenticate(
+        rp: &RelyingParty,
+        authenticator: &mut SoftAuthenticator,
+        credentials: &mut [Credential],
+    ) -> Result<()> {
+        let challenge = rng::with_crypto_rng(Challenge::generate);
+        let options = rp.request_options(&challenge, credentials);
+        let response = authenticator.get(&options);
+        rp.authenticate(&challenge, credentials, &response)
+    }
+
+    #[test]
+    fn register_and_authenticate_with_es256() {
+        let rp = relying_party();
+        let mut authenticator = SoftAuthenticator::new(COSE_ALG_ES256);
+        let credential = assert_ok!(register(&rp, &mut authenticator));
+        let mut credentials = vec![credential];
+        assert_ok!(authenticate(&rp, &mut authenticator, &mut credentials));
+        assert_ok!(authenticate(&rp, &mut authenticator, &mut credentials));
+        assert_eq!(credentials[0].counter, 3);
+    }
+
+    #[test]
+    fn register_and_authenticate_with_eddsa() {
+        let rp = relying_party();
+        let mut authenticator = SoftAuthenticator::new(COSE_ALG_EDDSA);
+        let credential = assert_ok!(register(&rp, &mut authenticator));
+        let mut credentials = vec![credential];
+        assert_ok!(authenticate(&rp, &mut authenticator, &mut credentials));
+    }
+
+    #[test]
+    fn reject_wrong_origin() {
+        let rp = relying_party();
+        let mut authenticator = SoftAuthenticator::new(COSE_ALG_ES256);
+        let credential = assert_ok!(register(&rp, &mut authenticator));
+        let mut credentials = vec![credential];
+
+        authenticator.origin = "https://example.org".to_string();
+        assert!(authenticate(&rp, &mut authenticator, &mut credentials).is_err());
+    }
+
+    #[test]
+    fn reject_wrong_challenge() {
+        let rp = relying_party();
+        let mut authenticator = SoftAuthenticator::new(COSE_ALG_ES256);
+        let mut credentials = vec![assert_ok!(register(&rp, &mut authenticator))];
+
+        let challenge = rng::with_crypto_rng(Challenge::generate);
+        let options = rp.request_options(&challenge, &credentials);
+        let response = authenticator.get(&options);
+        let other_challenge = rng::with_crypto_rng(Challenge::generate);
+        assert!(rp
+            .authenticate(&other_challenge, &mut credentials, &response)
+            .is_err());
+    }
+
+    #[test]
+    fn reject_cloned_authenticator() {
+        let rp = relying_party();
+        let mut authenticator = SoftAuthenticator::new(COSE_ALG_EDDSA);
+        let mut credentials = vec![assert_ok!(register(&rp, &mut authenticator))];
+        assert_ok!(authenticate(&rp, &mut authenticator, &mut credentials));
+
+        authenticator.counter = 0;
+        assert!(authenticate(&rp, &mut authenticator, &mut credentials).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use std::sync::Mutex;
+
+use base64ct::{Base64Url, Encoding};
+use reqwest::{header, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    opaque::{KsfParams, Login, Registration},
+    Error,
+};
+
+/// Name of the session cookie set by the server.
+const SESSION_COOKIE: &str = "SESSIONID";
+
+/// Name of the device cookie set by the server.
+const DEVICE_COOKIE: &str = "DEVICEID";
+
+/// Client of the application.
+pub struct Client {
+    http: reqwest::Client,
+    url: Url,
+    /// Device cookie received with the last sign in, it is sent back so that
+    /// the following sign ins are not notified as coming from a new device.
+    device: Mutex<Option<String>>,
+    /// Pinned static public keys of the server.
+    pinned: Vec<Vec<u8>>,
+}
+
+/// Result of a successful password verification.
+pub enum Signin {
+    /// The session is started.
+    Session(SessionCookie),
+    /// The user has a second factor, the token has to be exchanged with a
+    /// valid code to start the session.
+    SecondFactor {
+        token: MfaToken,
+        /// Second factors available to the user.
+        methods: Vec<String>,
+    },
+}
+
+/// Token of a sign in waiting for the second factor, it can be used only once.
+pub struct MfaToken(String);
+
+/// Session cookie set by the server.
+pub struct SessionCookie {
+    value: String,
+}
+
+impl SessionCookie {
+    /// Returns the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the value of the `Cookie` header for authenticated requests.
+    pub fn header(&self) -> String {
+        format!("{SESSION_COOKIE}={}", self.value)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "step", rename_all = "lowercase")]
+enum SignupReq<'a> {
+    Start { code: &'a str, message: String },
+    Finish { session: String, message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionMessage {
+    session: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ParamsRes {
+    suite: String,
+    #[serde(flatten)]
+    ksf: KsfParams,
+    identity: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SigninStartReq<'a> {
+    username: &'a str,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SigninStartRes {
+    session: String,
+    message: String,
+    ksf: KsfParams,
+    identity: Option<String>,
+    context: Option<String>,
+    suite: String,
+    #[serde(default)]
+    legacy: bool,
+}
+
+#[derive(Serialize)]
+struct LegacySigninReq<'a> {
+    username: &'a str,
+    password: &'a str,
+    message: String,
+}
+
+#[derive(Default, Deserialize)]
+struct SigninFinishRes {
+    reregister: Option<String>,
+    mfa: Option<String>,
+    #[serde(default)]
+    methods: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MfaReq<'a> {
+    token: &'a str,
+    code: &'a str,
+}
+
+impl Client {
+    /// Create the client of the application served at the given URL.
+    pub fn new(url: Url) -> Self {
+        Self::with_http_client(reqwest::Client::new(), url)
+    }
+
+    /// Create the client using the given HTTP client, for example to set
+    /// timeouts or custom root certificates.
+    pub fn with_http_client(http: reqwest::Client, url: Url) -> Self {
+        Self {
+            http,
+            url,
+            device: Mutex::new(None),
+            pinned: Vec::new(),
+        }
+    }
+
+    /// Pin the static public key of the server (base64url encoded), the sign
+    /// in fails if the server reveals a different one. More keys can be
+    /// pinned while the signature of the server is rotated.
+    pub fn pin(mut self, key: &str) -> Result<Self, Error> {
+        self.pinned.push(Base64Url::decode_vec(key)?);
+        Ok(self)
+    }
+
+    /// Register the password of an invited user, the invitation code is the
+    /// one of the sign up link.
+    pub async fn signup(&self, code: &str, password: &str) -> Result<(), Error> {
+        let params = self.registration_params().await?;
+        let (registration, message) = Registration::start(password)?;
+        let req = SignupReq::Start { code, message };
+        let res: SessionMessage = self
+            .send(self.post("/signup", &req), Error::InvalidInvitation)
+            .await?
+            .json()
+            .await?;
+
+        let message = registration.finish(&res.message, &params.ksf, params.identity.as_deref())?;
+        let req = SignupReq::Finish {
+            session: res.session,
+            message,
+        };
+        self.send(self.post("/signup", &req), Error::SessionExpired)
+            .await?;
+        Ok(())
+    }
+
+    /// Sign in, the session is started unless the user has a second factor.
+    ///
+    /// If the user was registered with a retired server setup or with
+    /// outdated key stretching parameters the password is registered again,
+    /// as done by the web client. When the server accepts the legacy password
+    /// hashes and the password is rejected, the legacy sign in is tried: the
+    /// imported users fail like the unknown ones and are registered with
+    /// OPAQUE.
+    pub async fn signin(&self, username: &str, password: &str) -> Result<Signin, Error> {
+        let (login, message) = Login::start(password)?;
+        let req = SigninStartReq { username, message };
+        let res: SigninStartRes = self
+            .send(
+                self.post("/api/signin/start", &req),
+                Error::InvalidCredentials,
+            )
+            .await?
+            .json()
+            .await?;
+        check_suite(&res.suite)?;
+
+        let message = match login.finish(
+            &res.message,
+            &res.ksf,
+            res.identity.as_deref(),
+            res.context.as_deref(),
+            &self.pinned,
+        ) {
+            Err(Error::InvalidCredentials) if res.legacy => {
+                let response = self.legacy_signin(username, password).await?;
+                let (session, res) = self.signin_response(response).await?;
+                return signin_result(session, res);
+            }
+            result => result?,
+        };
+        let req = SessionMessage {
+            session: res.session,
+            message,
+        };
+        let response = self
+            .send(
+                self.post("/api/signin/finish", &req),
+                Error::InvalidCredentials,
+            )
+            .await?;
+        let (session, res) = self.signin_response(response).await?;
+
+        match res.reregister {
+            Some(session) => {
+                let response = self.reregister(session, password).await?;
+                let (session, res) = self.signin_response(response).await?;
+                signin_result(session, res)
+            }
+            None => signin_result(session, res),
+        }
+    }
+
+    /// Exchange the second factor token with a TOTP code.
+    pub async fn mfa_totp(&self, token: MfaToken, code: &str) -> Result<SessionCookie, Error> {
+        self.mfa("/api/mfa/totp", token, code).await
+    }
+
+    /// Exchange the second factor token with a recovery code.
+    pub async fn mfa_recovery(&self, token: MfaToken, code: &str) -> Result<SessionCookie, Error> {
+        self.mfa("/api/mfa/recovery", token, code).await
+    }
+
+    async fn mfa(&self, path: &str, token: MfaToken, code: &str) -> Result<SessionCookie, Error> {
+        let req = MfaReq {
+            token: &token.0,
+            code,
+        };
+        let response = self.send(self.post(path, &req), Error::InvalidCode).await?;
+        let (session, _) = self.signin_response(response).await?;
+        session.ok_or(Error::MissingCookie)
+    }
+
+    /// Register again the password, returns the response of the last step.
+    async fn reregister(&self, session: String, password: &str) -> Result<Response, Error> {
+        let params = self.registration_params().await?;
+        let (registration, message) = Registration::start(password)?;
+        let req = SessionMessage { session, message };
+        let res: SessionMessage = self
+            .send(
+                self.post("/api/reregister/start", &req),
+                Error::SessionExpired,
+            )
+            .await?
+            .json()
+            .await?;
+
+        let message = registration.finish(&res.message, &params.ksf, params.identity.as_deref())?;
+        let req = SessionMessage {
+            session: res.session,
+            message,
+        };
+        self.send(
+            self.post("/api/reregister/finish", &req),
+            Error::SessionExpired,
+        )
+        .await
+    }
+
+    /// Send the password to be verified against the legacy hash and register
+    /// it, returns the response of the last step.
+    async fn legacy_signin(&self, username: &str, password: &str) -> Result<Response, Error> {
+        let params = self.registration_params().await?;
+        let (registration, message) = Registration::start(password)?;
+        let req = LegacySigninReq {
+            username,
+            password,
+            message,
+        };
+        let res: SessionMessage = self
+            .send(
+                self.post("/api/signin/legacy", &req),
+                Error::InvalidCredentials,
+            )
+            .await?
+            .json()
+            .await?;
+
+        let message = registration.finish(&res.message, &params.ksf, params.identity.as_deref())?;
+        let req = SessionMessage {
+            session: res.session,
+            message,
+        };
+        self.send(
+            self.post("/api/reregister/finish", &req),
+            Error::SessionExpired,
+        )
+        .await
+    }
+
+    /// Retrieve the protocol parameters for new registrations.
+    async fn registration_params(&self) -> Result<ParamsRes, Error> {
+        let request = self.http.get(self.endpoint("/api/opaque/params"));
+        let res: ParamsRes = self
+            .send(request, Error::Status(StatusCode::UNAUTHORIZED))
+            .await?
+            .json()
+            .await?;
+        check_suite(&res.suite)?;
+        Ok(res)
+    }
+
+    /// Read the cookies and the body of a response that can start the session.
+    async fn signin_response(
+        &self,
+        response: Response,
+    ) -> Result<(Option<SessionCookie>, SigninFinishRes), Error> {
+        let mut session = None;
+        for value in response.headers().get_all(header::SET_COOKIE) {
+            let Some((name, value)) = value.to_str().ok().and_then(parse_cookie) else {
+                continue;
+            };
+            match name {
+                SESSION_COOKIE => {
+                    session = Some(SessionCookie {
+                        value: value.to_string(),
+                    })
+                }
+                DEVICE_COOKIE => *self.device.lock().unwrap() = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        // the server replies with `null` when the session is started
+        let res: Option<SigninFinishRes> = response.json().await?;
+        Ok((session, res.unwrap_or_default()))
+    }
+
+    fn endpoint(&self, path: &str) -> Url {
+        let mut url = self.url.clone();
+        url.set_path(path);
+        url
+    }
+
+    fn post(&self, path: &str, body: &impl Serialize) -> RequestBuilder {
+        self.http.post(self.endpoint(path)).json(body)
+    }
+
+    /// Send the request with the device cookie, the status `401 Unauthorized`
+    /// is mapped to the given error and `400 Bad Request` to the reason of the
+    /// rejection.
+    async fn send(&self, request: RequestBuilder, unauthorized: Error) -> Result<Response, Error> {
+        let device = self.device.lock().unwrap().clone();
+        let request = match device {
+            Some(device) => request.header(header::COOKIE, format!("{DEVICE_COOKIE}={device}")),
+            None => request,
+        };
+
+        let response = request.send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            StatusCode::UNAUTHORIZED => Err(unauthorized),
+            StatusCode::BAD_REQUEST => Err(Error::Rejected(response.text().await?)),
+            status => Err(Error::Status(status)),
+        }
+    }
+}
+
+fn signin_result(session: Option<SessionCookie>, res: SigninFinishRes) -> Result<Signin, Error> {
+    if let Some(token) = res.mfa {
+        return Ok(Signin::SecondFactor {
+            token: MfaToken(token),
+            methods: res.methods,
+        });
+    }
+    session.map(Signin::Session).ok_or(Error::MissingCookie)
+}
+
+/// Check that the server runs the same cipher suite of the client.
+fn check_suite(suite: &str) -> Result<(), Error> {
+    if suite != fresh_auth_suite::SUITE {
+        return Err(Error::UnsupportedSuite(suite.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns the name and the value of a `Set-Cookie` header.
+fn parse_cookie(header: &str) -> Option<(&str, &str)> {
+    let (name, value) = header.split(';').next()?.split_once('=')?;
+    Some((name.trim(), value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn parse_set_cookie_header() {
+        let header = "SESSIONID=abc=; HttpOnly; SameSite=Strict; Secure; Path=/; Max-Age=86400";
+        assert_eq!(assert_some!(parse_cookie(header)), ("SESSIONID", "abc="));
+        assert_none!(parse_cookie("SESSIONID"));
+    }
+
+    #[test]
+    fn check_cipher_suite() {
+        assert_ok!(check_suite(fresh_auth_suite::SUITE));
+        let err = assert_err!(check_suite("unknown"));
+        assert!(matches!(err, Error::UnsupportedSuite(suite) if suite == "unknown"));
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use fresh_auth_suite::envelope::EnvelopeError;
+use reqwest::StatusCode;
+
+/// Errors returned by the client.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent or the response could not be read.
+    Http(reqwest::Error),
+    /// The server replied with an unexpected status.
+    Status(StatusCode),
+    /// The server rejected a message of the client, with the reason.
+    Rejected(String),
+    /// The username or the password are wrong.
+    InvalidCredentials,
+    /// The invitation code is wrong or expired.
+    InvalidInvitation,
+    /// The second factor code is wrong, the sign in has to be restarted.
+    InvalidCode,
+    /// The session between two steps is expired, the process has to be
+    /// restarted.
+    SessionExpired,
+    /// The server runs a different cipher suite.
+    UnsupportedSuite(String),
+    /// The password is rejected by the normalization.
+    InvalidPassword(&'static str),
+    /// The key stretching parameters of the server are rejected.
+    InvalidKsfParams(argon2::Error),
+    /// The pinned key is not base64url encoded.
+    Encoding(base64ct::Error),
+    /// A message of the server cannot be opened, the server runs a different
+    /// version of the protocol.
+    Envelope(EnvelopeError),
+    /// A step of the OPAQUE protocol failed.
+    Protocol(opaque_ke::errors::ProtocolError),
+    /// The server did not set the session cookie.
+    MissingCookie,
+    /// The static public key of the server is not one of the pinned keys.
+    UnpinnedServerKey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(err) => write!(f, "request failed, {err}"),
+            Error::Status(status) => write!(f, "unexpected response status {status}"),
+            Error::Rejected(reason) => write!(f, "message rejected by the server, {reason}"),
+            Error::InvalidCredentials => f.write_str("invalid username or password"),
+            Error::InvalidInvitation => f.write_str("invalid invitation code"),
+            Error::InvalidCode => f.write_str("invalid second factor code"),
+            Error::SessionExpired => f.write_str("session is expired"),
+            Error::UnsupportedSuite(suite) => {
+                write!(f, "cipher suite {suite} of the server is not supported")
+            }
+            Error::InvalidPassword(reason) => write!(f, "invalid password, {reason}"),
+            Error::InvalidKsfParams(err) => write!(f, "invalid key stretching parameters, {err}"),
+            Error::Encoding(err) => write!(f, "invalid key encoding, {err}"),
+            Error::Envelope(err) => write!(f, "invalid message, {err}"),
+            Error::Protocol(err) => write!(f, "protocol error, {err}"),
+            Error::MissingCookie => f.write_str("missing session cookie"),
+            Error::UnpinnedServerKey => f.write_str("server public key is not pinned"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Encoding(err) => Some(err),
+            Error::Envelope(err) => Some(err),
+            Error::Protocol(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<base64ct::Error> for Error {
+    fn from(err: base64ct::Error) -> Self {
+        Error::Encoding(err)
+    }
+}
+
+impl From<EnvelopeError> for Error {
+    fn from(err: EnvelopeError) -> Self {
+        Error::Envelope(err)
+    }
+}
+
+impl From<opaque_ke::errors::ProtocolError> for Error {
+    fn from(err: opaque_ke::errors::ProtocolError) -> Self {
+        Error::Protocol(err)
+    }
+}
@@ -0,0 +1,33 @@
+//! Native client of Fresh Auth
+//!
+//! It runs the same OPAQUE protocol of the web client over HTTP, so that
+//! services and test suites can sign up and sign in:
+//!
+//! ```no_run
+//! use fresh_auth_client::{Client, Signin, Url};
+//!
+//! # async fn example() -> Result<(), fresh_auth_client::Error> {
+//! let url = Url::parse("https://auth.example.com").unwrap();
+//! let client = Client::new(url);
+//! match client.signin("xyz", "correct horse battery staple").await? {
+//!     Signin::Session(session) => {
+//!         // authenticate the following requests with `session.header()`
+//!     }
+//!     Signin::SecondFactor { token, .. } => {
+//!         let session = client.mfa_totp(token, "123456").await?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The cipher suite is selected with the same features of the server. Unlike
+//! the web client, the strength of the password is not checked on sign up.
+
+mod client;
+mod error;
+mod opaque;
+
+pub use client::{Client, MfaToken, SessionCookie, Signin};
+pub use error::Error;
+pub use reqwest::Url;
@@ -0,0 +1,281 @@
+use fresh_auth_suite::{
+    envelope::{self, MessageType},
+    CipherSuite,
+};
+use opaque_ke::errors::ProtocolError;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use crate::Error;
+
+/// Parameters of the key stretching function (Argon2id), provided by the server.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct KsfParams {
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KsfParams {
+    /// Build the key stretching function.
+    fn ksf(&self) -> Result<argon2::Argon2<'static>, Error> {
+        let params = argon2::Params::new(self.memory, self.iterations, self.parallelism, None)
+            .map_err(Error::InvalidKsfParams)?;
+        Ok(argon2::Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params,
+        ))
+    }
+}
+
+/// Client registration, the messages are base64url encoded as expected by
+/// the server.
+pub struct Registration {
+    state: opaque_ke::ClientRegistration<CipherSuite>,
+    password: Zeroizing<String>,
+}
+
+impl Registration {
+    /// Start the registration, returns the message to be sent to the server.
+    pub fn start(password: &str) -> Result<(Self, String), Error> {
+        let password = normalize(password)?;
+        let registration_start =
+            opaque_ke::ClientRegistration::<CipherSuite>::start(&mut OsRng, password.as_bytes())?;
+
+        let message = registration_start.message.serialize();
+        let message = envelope::seal(MessageType::RegistrationRequest, &message);
+
+        let registration = Self {
+            state: registration_start.state,
+            password,
+        };
+        Ok((registration, message))
+    }
+
+    /// Finish the registration with the response of the server, returns the
+    /// upload message.
+    pub fn finish(
+        self,
+        message: &str,
+        ksf_params: &KsfParams,
+        identity: Option<&str>,
+    ) -> Result<String, Error> {
+        let registration_response = envelope::open(MessageType::RegistrationResponse, message)?;
+        let registration_response =
+            opaque_ke::RegistrationResponse::deserialize(&registration_response)?;
+        let ksf = ksf_params.ksf()?;
+        let params =
+            opaque_ke::ClientRegistrationFinishParameters::new(identifiers(identity), Some(&ksf));
+
+        let registration_finish = self.state.finish(
+            &mut OsRng,
+            self.password.as_bytes(),
+            registration_response,
+            params,
+        )?;
+
+        let message = registration_finish.message.serialize();
+        Ok(envelope::seal(MessageType::RegistrationUpload, &message))
+    }
+}
+
+/// Client login, the messages are base64url encoded as expected by the server.
+pub struct Login {
+    state: opaque_ke::ClientLogin<CipherSuite>,
+    password: Zeroizing<String>,
+}
+
+impl Login {
+    /// Start the login, returns the message to be sent to the server.
+    pub fn start(password: &str) -> Result<(Self, String), Error> {
+        let password = normalize(password)?;
+        let login_start =
+            opaque_ke::ClientLogin::<CipherSuite>::start(&mut OsRng, password.as_bytes())?;
+
+        let message = login_start.message.serialize();
+        let message = envelope::seal(MessageType::CredentialRequest, &message);
+
+        let login = Self {
+            state: login_start.state,
+            password,
+        };
+        Ok((login, message))
+    }
+
+    /// Finish the login with the response of the server, returns the
+    /// finalization message.
+    ///
+    /// A wrong password is detected by the client, before the finalization.
+    /// The static public key of the server must be one of the pinned keys,
+    /// any key is accepted when none is pinned.
+    pub fn finish(
+        self,
+        message: &str,
+        ksf_params: &KsfParams,
+        identity: Option<&str>,
+        context: Option<&str>,
+        pinned: &[Vec<u8>],
+    ) -> Result<String, Error> {
+        let credential_response = envelope::open(MessageType::CredentialResponse, message)?;
+        let credential_response = opaque_ke::CredentialResponse::deserialize(&credential_response)?;
+        let ksf = ksf_params.ksf()?;
+        let params = opaque_ke::ClientLoginFinishParameters::new(
+            context.map(str::as_bytes),
+            identifiers(identity),
+            Some(&ksf),
+        );
+
+        let login_finish = self
+            .state
+            .finish(self.password.as_bytes(), credential_response, params)
+            .map_err(|err| match err {
+                ProtocolError::InvalidLoginError => Error::InvalidCredentials,
+                err => Error::Protocol(err),
+            })?;
+
+        let server_key = login_finish.server_s_pk.serialize();
+        if !pinned.is_empty() && !pinned.iter().any(|key| key[..] == server_key[..]) {
+            return Err(Error::UnpinnedServerKey);
+        }
+
+        let message = login_finish.message.serialize();
+        Ok(envelope::seal(
+            MessageType::CredentialFinalization,
+            &message,
+        ))
+    }
+}
+
+/// Identifiers bound to the key exchange, the server public key is used when
+/// the server has no identity.
+fn identifiers(identity: Option<&str>) -> opaque_ke::Identifiers<'_> {
+    opaque_ke::Identifiers {
+        client: None,
+        server: identity.map(str::as_bytes),
+    }
+}
+
+fn normalize(password: &str) -> Result<Zeroizing<String>, Error> {
+    fresh_auth_suite::password::normalize(password)
+        .map(Zeroizing::new)
+        .map_err(Error::InvalidPassword)
+}
+
+#[cfg(test)]
+mod tests {
+    use opaque_ke::{ServerLogin, ServerLoginStartParameters, ServerRegistration, ServerSetup};
+
+    use super::*;
+
+    use claym::*;
+
+    const KSF_PARAMS: KsfParams = KsfParams {
+        memory: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    const IDENTITY: Option<&str> = Some("auth.example.com");
+    const CONTEXT: Option<&str> = Some("fresh-auth test");
+
+    /// Register the password on the server side, as done by the api.
+    fn register(
+        server_setup: &ServerSetup<CipherSuite>,
+        password: &str,
+    ) -> ServerRegistration<CipherSuite> {
+        let (registration, message) = assert_ok!(Registration::start(password));
+        let message = assert_ok!(envelope::open(MessageType::RegistrationRequest, &message));
+        let message = assert_ok!(opaque_ke::RegistrationRequest::deserialize(&message));
+        let response = assert_ok!(ServerRegistration::start(server_setup, message, b"xyz"));
+        let response = envelope::seal(
+            MessageType::RegistrationResponse,
+            &response.message.serialize(),
+        );
+
+        let message = assert_ok!(registration.finish(&response, &KSF_PARAMS, IDENTITY));
+        let message = assert_ok!(envelope::open(MessageType::RegistrationUpload, &message));
+        let message = assert_ok!(opaque_ke::RegistrationUpload::deserialize(&message));
+        ServerRegistration::finish(message)
+    }
+
+    /// Start the login on the server side, returns the encoded response.
+    fn login_start(
+        server_setup: &ServerSetup<CipherSuite>,
+        password_file: ServerRegistration<CipherSuite>,
+        message: &str,
+        context: Option<&str>,
+    ) -> (ServerLogin<CipherSuite>, String) {
+        let message = assert_ok!(envelope::open(MessageType::CredentialRequest, message));
+        let message = assert_ok!(opaque_ke::CredentialRequest::deserialize(&message));
+        let response = assert_ok!(ServerLogin::start(
+            &mut OsRng,
+            server_setup,
+            Some(password_file),
+            message,
+            b"xyz",
+            ServerLoginStartParameters {
+                context: context.map(str::as_bytes),
+                identifiers: identifiers(IDENTITY),
+            },
+        ));
+        let encoded = envelope::seal(
+            MessageType::CredentialResponse,
+            &response.message.serialize(),
+        );
+        (response.state, encoded)
+    }
+
+    #[test]
+    fn register_and_login() {
+        let server_setup = ServerSetup::<CipherSuite>::new(&mut OsRng);
+        let password_file = register(&server_setup, "correct horse battery staple");
+
+        let (login, message) = assert_ok!(Login::start("correct horse battery staple"));
+        let (state, response) = login_start(&server_setup, password_file, &message, CONTEXT);
+        let pinned = [server_setup.keypair().public().serialize().to_vec()];
+        let message = assert_ok!(login.finish(&response, &KSF_PARAMS, IDENTITY, CONTEXT, &pinned));
+        let message = assert_ok!(envelope::open(
+            MessageType::CredentialFinalization,
+            &message
+        ));
+        let message = assert_ok!(opaque_ke::CredentialFinalization::deserialize(&message));
+        assert_ok!(state.finish(message));
+    }
+
+    #[test]
+    fn reject_wrong_password() {
+        let server_setup = ServerSetup::<CipherSuite>::new(&mut OsRng);
+        let password_file = register(&server_setup, "correct horse battery staple");
+
+        let (login, message) = assert_ok!(Login::start("wrong horse battery staple"));
+        let (_, response) = login_start(&server_setup, password_file, &message, CONTEXT);
+        let err = assert_err!(login.finish(&response, &KSF_PARAMS, IDENTITY, CONTEXT, &[]));
+        assert!(matches!(err, Error::InvalidCredentials));
+    }
+
+    #[test]
+    fn reject_different_context() {
+        let server_setup = ServerSetup::<CipherSuite>::new(&mut OsRng);
+        let password_file = register(&server_setup, "correct horse battery staple");
+
+        let (login, message) = assert_ok!(Login::start("correct horse battery staple"));
+        let (_, response) = login_start(&server_setup, password_file, &message, CONTEXT);
+        // the client cannot verify the server transcript
+        assert_err!(login.finish(&response, &KSF_PARAMS, IDENTITY, Some("other"), &[]));
+    }
+
+    #[test]
+    fn reject_unpinned_server_key() {
+        let server_setup = ServerSetup::<CipherSuite>::new(&mut OsRng);
+        let password_file = register(&server_setup, "correct horse battery staple");
+        let other_setup = ServerSetup::<CipherSuite>::new(&mut OsRng);
+        let pinned = [other_setup.keypair().public().serialize().to_vec()];
+
+        let (login, message) = assert_ok!(Login::start("correct horse battery staple"));
+        let (_, response) = login_start(&server_setup, password_file, &message, CONTEXT);
+        let err = assert_err!(login.finish(&response, &KSF_PARAMS, IDENTITY, CONTEXT, &pinned));
+        assert!(matches!(err, Error::UnpinnedServerKey));
+    }
+}
@@ -0,0 +1,17 @@
+interface Props {
+  codes: string[];
+}
+
+export default function RecoveryCodes({ codes }: Props) {
+  return (
+    <div class="flex flex-col gap-2">
+      <p>
+        Save these recovery codes, each one can be used once in place of the
+        second factor. They will not be shown again.
+      </p>
+      <ul class="grid grid-cols-2 gap-1 font-mono">
+        {codes.map((code) => <li key={code}>{code}</li>)}
+      </ul>
+    </div>
+  );
+}
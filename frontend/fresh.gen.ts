@@ -5,13 +5,21 @@
 import * as $_404 from "./routes/_404.tsx";
 import * as $_app from "./routes/_app.tsx";
 import * as $_middleware from "./routes/_middleware.ts";
+import * as $email from "./routes/email.tsx";
 import * as $index from "./routes/index.tsx";
+import * as $reset from "./routes/reset.tsx";
 import * as $signin from "./routes/signin.tsx";
 import * as $signup from "./routes/signup.tsx";
+import * as $EmailConfirm from "./islands/EmailConfirm.tsx";
+import * as $EmailForm from "./islands/EmailForm.tsx";
 import * as $ErrorBox from "./islands/ErrorBox.tsx";
+import * as $RecoveryRegenerate from "./islands/RecoveryRegenerate.tsx";
+import * as $ResetForm from "./islands/ResetForm.tsx";
 import * as $SignInForm from "./islands/SignInForm.tsx";
 import * as $SignUpForm from "./islands/SignUpForm.tsx";
 import * as $Signout from "./islands/Signout.tsx";
+import * as $TotpEnroll from "./islands/TotpEnroll.tsx";
+import * as $WebauthnRegister from "./islands/WebauthnRegister.tsx";
 import * as $form_Password from "./islands/form/Password.tsx";
 import * as $form_Text from "./islands/form/Text.tsx";
 import { type Manifest } from "$fresh/server.ts";
@@ -21,15 +29,23 @@ const manifest = {
     "./routes/_404.tsx": $_404,
     "./routes/_app.tsx": $_app,
     "./routes/_middleware.ts": $_middleware,
+    "./routes/email.tsx": $email,
     "./routes/index.tsx": $index,
+    "./routes/reset.tsx": $reset,
     "./routes/signin.tsx": $signin,
     "./routes/signup.tsx": $signup,
   },
   islands: {
+    "./islands/EmailConfirm.tsx": $EmailConfirm,
+    "./islands/EmailForm.tsx": $EmailForm,
     "./islands/ErrorBox.tsx": $ErrorBox,
+    "./islands/RecoveryRegenerate.tsx": $RecoveryRegenerate,
+    "./islands/ResetForm.tsx": $ResetForm,
     "./islands/SignInForm.tsx": $SignInForm,
     "./islands/SignUpForm.tsx": $SignUpForm,
     "./islands/Signout.tsx": $Signout,
+    "./islands/TotpEnroll.tsx": $TotpEnroll,
+    "./islands/WebauthnRegister.tsx": $WebauthnRegister,
     "./islands/form/Password.tsx": $form_Password,
     "./islands/form/Text.tsx": $form_Text,
   },
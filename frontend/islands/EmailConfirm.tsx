@@ -0,0 +1,53 @@
+import { JSX } from "preact";
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import { emailConfirm } from "#utils/email.ts";
+
+interface Props {
+  /** Token of the confirmation link */
+  token: string;
+}
+
+export default function EmailConfirm({ token }: Props) {
+  const confirmed = useSignal(false);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onSubmit = async (event: JSX.TargetedSubmitEvent<HTMLFormElement>) => {
+    event.preventDefault();
+
+    try {
+      await emailConfirm({ token });
+      errorMessage.value = undefined;
+      confirmed.value = true;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  return (
+    <form
+      class="flex flex-col gap-6 w-96 m-auto p-5 bg-gray-50 border border-gray-300 rounded shadow"
+      onSubmit={onSubmit}
+    >
+      <div class="flex flex-row">
+        <h1 class="mx-auto text-2xl font-bold text-gray-900">
+          Confirm email address
+        </h1>
+      </div>
+      {confirmed.value
+        ? <p>Email address saved.</p>
+        : (
+          <div class="flex flex-row">
+            <div class="mx-auto">
+              <Button type="submit" disabled={token === ""}>Confirm</Button>
+            </div>
+          </div>
+        )}
+      <ErrorBox message={errorMessage} />
+    </form>
+  );
+}
@@ -0,0 +1,49 @@
+import { JSX } from "preact";
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import Label from "#components/form/Label.tsx";
+import Text from "#islands/form/Text.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import { emailSet } from "#utils/email.ts";
+
+export default function EmailForm() {
+  const email = useSignal("");
+  const sent = useSignal(false);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onSubmit = async (event: JSX.TargetedSubmitEvent<HTMLFormElement>) => {
+    event.preventDefault();
+
+    try {
+      const form = new FormData(event.currentTarget);
+      await emailSet({ email: form.get("email") as string });
+      errorMessage.value = undefined;
+      sent.value = true;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  return (
+    <form class="flex flex-col gap-4 w-96" onSubmit={onSubmit}>
+      <div class="flex flex-col gap-1">
+        <Label for="email">Email address</Label>
+        <Text
+          id="email"
+          name="email"
+          value={email}
+          inputMode="email"
+          autoComplete="email"
+        />
+      </div>
+      <Button type="submit" disabled={email.value === ""}>Save</Button>
+      {sent.value && (
+        <p>A confirmation link has been sent to the new email address.</p>
+      )}
+      <ErrorBox message={errorMessage} />
+    </form>
+  );
+}
@@ -0,0 +1,30 @@
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import RecoveryCodes from "#components/RecoveryCodes.tsx";
+import { recoveryRegenerate } from "#utils/mfa.ts";
+
+export default function RecoveryRegenerate() {
+  const recovery = useSignal<string[] | undefined>(undefined);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onRegenerate = async () => {
+    try {
+      recovery.value = await recoveryRegenerate();
+      errorMessage.value = undefined;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  return (
+    <div class="flex flex-col gap-2">
+      <Button onClick={onRegenerate}>Regenerate recovery codes</Button>
+      {recovery.value && <RecoveryCodes codes={recovery.value} />}
+      <ErrorBox message={errorMessage} />
+    </div>
+  );
+}
@@ -0,0 +1,76 @@
+import { JSX } from "preact";
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import Label from "#components/form/Label.tsx";
+import Text from "#islands/form/Text.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import { passwordReset } from "#utils/email.ts";
+
+interface Props {
+  /** The password of the user appears in the breached password list */
+  breached?: boolean;
+}
+
+export default function ResetForm({ breached }: Props) {
+  const username = useSignal("");
+  const sent = useSignal(false);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onSubmit = async (event: JSX.TargetedSubmitEvent<HTMLFormElement>) => {
+    event.preventDefault();
+
+    try {
+      const form = new FormData(event.currentTarget);
+      await passwordReset({ username: form.get("username") as string });
+      errorMessage.value = undefined;
+      sent.value = true;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  return (
+    <form
+      class="flex flex-col gap-6 w-96 m-auto p-5 bg-gray-50 border border-gray-300 rounded shadow"
+      onSubmit={onSubmit}
+    >
+      <div class="flex flex-row">
+        <h1 class="mx-auto text-2xl font-bold text-gray-900">
+          Reset password
+        </h1>
+      </div>
+      {sent.value
+        ? (
+          <p>
+            If the account has an email address, a link to choose a new
+            password has been sent to it.
+          </p>
+        )
+        : (
+          <>
+            {breached && (
+              <p>
+                Your password appears in a list of breached passwords, choose
+                a new one with a reset link.
+              </p>
+            )}
+            <div class="flex flex-col gap-1">
+              <Label for="username">Username</Label>
+              <Text id="username" name="username" value={username} />
+            </div>
+            <div class="flex flex-row">
+              <div class="mx-auto">
+                <Button type="submit" disabled={username.value === ""}>
+                  Send reset link
+                </Button>
+              </div>
+            </div>
+          </>
+        )}
+      <ErrorBox message={errorMessage} />
+    </form>
+  );
+}
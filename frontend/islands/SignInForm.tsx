@@ -1,18 +1,32 @@
 import { JSX } from "preact";
-import { computed, signal } from "@preact/signals";
+import { computed, signal, useSignal } from "@preact/signals";
 
 import Label from "#components/form/Label.tsx";
 import Password from "#islands/form/Password.tsx";
 import Text from "#islands/form/Text.tsx";
 import Button from "#components/form/Button.tsx";
 import ErrorBox from "#islands/ErrorBox.tsx";
+import { signinRecovery, signinTotp } from "#utils/mfa.ts";
 import { signin } from "#utils/opaque.ts";
+import { signinWebauthn } from "#utils/webauthn.ts";
 
-export default function SignInForm() {
+interface Props {
+  /** Pinned public keys of the server */
+  serverKeys?: string[];
+}
+
+export default function SignInForm({ serverKeys }: Props) {
   const username = signal("");
   const password = signal("");
+  const code = useSignal("");
+  const mfa = useSignal<string | undefined>(undefined);
+  const methods = useSignal<string[]>([]);
+  const recovery = useSignal(false);
+  const breached = useSignal(false);
   const disabled = computed(() =>
-    password.value === "" || username.value === ""
+    mfa.value === undefined
+      ? password.value === "" || username.value === ""
+      : code.value === ""
   );
 
   const errorMessage = signal<string | undefined>(undefined);
@@ -21,12 +35,50 @@ export default function SignInForm() {
 
     try {
       const form = new FormData(event.currentTarget);
-      await signin({
-        username: form.get("username") as string,
-        password: form.get("password") as string,
-      });
+      if (mfa.value === undefined) {
+        const result = await signin({
+          username: form.get("username") as string,
+          password: form.get("password") as string,
+          serverKeys,
+        });
+        breached.value = result.breached ?? false;
+        if (result.mfa) {
+          errorMessage.value = undefined;
+          mfa.value = result.mfa;
+          methods.value = result.methods ?? ["totp"];
+          recovery.value = false;
+          return;
+        }
+      } else {
+        const token = mfa.value;
+        mfa.value = undefined;
+        const code = form.get("code") as string;
+        if (recovery.value) {
+          await signinRecovery({ token, code });
+        } else {
+          await signinTotp({ token, code });
+        }
+      }
       errorMessage.value = undefined;
-      window.location.href = "/";
+      window.location.href = breached.value ? "/reset?breached" : "/";
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  const onSecurityKey = async () => {
+    if (mfa.value === undefined) {
+      return;
+    }
+
+    try {
+      const token = mfa.value;
+      mfa.value = undefined;
+      await signinWebauthn(token);
+      errorMessage.value = undefined;
+      window.location.href = breached.value ? "/reset?breached" : "/";
     } catch (err) {
       errorMessage.value = (err instanceof Error)
         ? err.message
@@ -42,28 +94,76 @@ export default function SignInForm() {
       <div class="flex flex-row">
         <h1 class="mx-auto text-2xl font-bold text-gray-900">Sign in</h1>
       </div>
-      <div class="flex flex-col gap-1">
-        <Label for="username">Username or email address</Label>
-        <Text
-          id="username"
-          name="username"
-          value={username}
-        />
-      </div>
-      <div class="flex flex-col gap-1">
-        <Label for="password">Password</Label>
-        <Password id="password" name="password" value={password} />
-      </div>
-      <div class="flex flex-row">
-        <div class="mx-auto">
+      {mfa.value === undefined
+        ? (
+          <>
+            <div class="flex flex-col gap-1">
+              <Label for="username">Username or email address</Label>
+              <Text
+                id="username"
+                name="username"
+                value={username}
+              />
+            </div>
+            <div class="flex flex-col gap-1">
+              <Label for="password">Password</Label>
+              <Password id="password" name="password" value={password} />
+            </div>
+          </>
+        )
+        : recovery.value
+        ? (
+          <div class="flex flex-col gap-1">
+            <Label for="code">Recovery code</Label>
+            <Text id="code" name="code" value={code} autoComplete="off" />
+          </div>
+        )
+        : methods.value.includes("totp") && (
+          <div class="flex flex-col gap-1">
+            <Label for="code">Authentication code</Label>
+            <Text
+              id="code"
+              name="code"
+              value={code}
+              inputMode="numeric"
+              autoComplete="one-time-code"
+            />
+          </div>
+        )}
+      <div class="flex flex-row gap-2 justify-center">
+        {(mfa.value === undefined || recovery.value ||
+          methods.value.includes("totp")) && (
           <Button
             type="submit"
             disabled={disabled}
           >
             Sign in
           </Button>
-        </div>
+        )}
+        {mfa.value !== undefined && !recovery.value &&
+          methods.value.includes("webauthn") && (
+          <Button type="button" onClick={onSecurityKey}>
+            Use security key
+          </Button>
+        )}
       </div>
+      {mfa.value !== undefined && !recovery.value && (
+        <button
+          type="button"
+          class="mx-auto text-sm underline text-gray-700"
+          onClick={() => {
+            code.value = "";
+            recovery.value = true;
+          }}
+        >
+          Use a recovery code
+        </button>
+      )}
+      {mfa.value === undefined && (
+        <a class="mx-auto text-sm underline text-gray-700" href="/reset">
+          Forgot your password?
+        </a>
+      )}
       <ErrorBox message={errorMessage} />
     </form>
   );
@@ -0,0 +1,87 @@
+import { JSX } from "preact";
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import Label from "#components/form/Label.tsx";
+import Text from "#islands/form/Text.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import RecoveryCodes from "#components/RecoveryCodes.tsx";
+import { totpConfirm, totpEnroll } from "#utils/mfa.ts";
+import { TotpEnrollRes } from "#utils/api.ts";
+
+export default function TotpEnroll() {
+  const enrolment = useSignal<TotpEnrollRes | undefined>(undefined);
+  const code = useSignal("");
+  const enabled = useSignal(false);
+  const recovery = useSignal<string[] | undefined>(undefined);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onEnroll = async () => {
+    try {
+      enrolment.value = await totpEnroll();
+      errorMessage.value = undefined;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  const onSubmit = async (event: JSX.TargetedSubmitEvent<HTMLFormElement>) => {
+    event.preventDefault();
+
+    try {
+      const form = new FormData(event.currentTarget);
+      const res = await totpConfirm({ code: form.get("code") as string });
+      recovery.value = res.recovery;
+      errorMessage.value = undefined;
+      enrolment.value = undefined;
+      enabled.value = true;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  if (enabled.value) {
+    return (
+      <div class="flex flex-col gap-2">
+        <p>Two-factor authentication is enabled.</p>
+        {recovery.value && <RecoveryCodes codes={recovery.value} />}
+      </div>
+    );
+  }
+
+  if (enrolment.value === undefined) {
+    return (
+      <div class="flex flex-col gap-2">
+        <Button onClick={onEnroll}>Enable two-factor authentication</Button>
+        <ErrorBox message={errorMessage} />
+      </div>
+    );
+  }
+
+  return (
+    <form class="flex flex-col gap-4 w-96" onSubmit={onSubmit}>
+      <p>
+        Add the account to your{" "}
+        <a class="underline" href={enrolment.value.uri}>authenticator app</a>
+        {" "}or enter the secret manually:
+      </p>
+      <code class="break-all">{enrolment.value.secret}</code>
+      <div class="flex flex-col gap-1">
+        <Label for="code">Authentication code</Label>
+        <Text
+          id="code"
+          name="code"
+          value={code}
+          inputMode="numeric"
+          autoComplete="one-time-code"
+        />
+      </div>
+      <Button type="submit" disabled={code.value === ""}>Confirm</Button>
+      <ErrorBox message={errorMessage} />
+    </form>
+  );
+}
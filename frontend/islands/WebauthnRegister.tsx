@@ -0,0 +1,34 @@
+import { useSignal } from "@preact/signals";
+
+import Button from "#components/form/Button.tsx";
+import ErrorBox from "#islands/ErrorBox.tsx";
+import RecoveryCodes from "#components/RecoveryCodes.tsx";
+import { webauthnRegister } from "#utils/webauthn.ts";
+
+export default function WebauthnRegister() {
+  const registered = useSignal(false);
+  const recovery = useSignal<string[] | undefined>(undefined);
+
+  const errorMessage = useSignal<string | undefined>(undefined);
+  const onRegister = async () => {
+    try {
+      const res = await webauthnRegister();
+      recovery.value = res.recovery ?? recovery.value;
+      errorMessage.value = undefined;
+      registered.value = true;
+    } catch (err) {
+      errorMessage.value = (err instanceof Error)
+        ? err.message
+        : err.toString();
+    }
+  };
+
+  return (
+    <div class="flex flex-col gap-2">
+      {registered.value && <p>Security key registered.</p>}
+      {recovery.value && <RecoveryCodes codes={recovery.value} />}
+      <Button onClick={onRegister}>Register a security key</Button>
+      <ErrorBox message={errorMessage} />
+    </div>
+  );
+}
@@ -15,8 +15,8 @@ export async function handler(
   req: Request,
   ctx: FreshContext<State>,
 ) {
-  // signin and signup page are always available
-  const excludedPath = ["/signin", "/signup"];
+  // signin, signup, reset and email confirmation page are always available
+  const excludedPath = ["/signin", "/signup", "/reset", "/email"];
   const isSigninRoute = <T>(ctx: FreshContext<T>) =>
     ctx.url.pathname === "/signin";
   const isExcludedRoute = <T>(ctx: FreshContext<T>) =>
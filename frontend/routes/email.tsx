@@ -0,0 +1,16 @@
+import { Head } from "$fresh/runtime.ts";
+import { PageProps } from "$fresh/server.ts";
+import EmailConfirm from "#islands/EmailConfirm.tsx";
+
+export default function Email({ url }: PageProps) {
+  return (
+    <>
+      <Head>
+        <title>Fresh Auth | Confirm email address</title>
+      </Head>
+      <div class="flex h-screen">
+        <EmailConfirm token={url.searchParams.get("token") ?? ""} />
+      </div>
+    </>
+  );
+}
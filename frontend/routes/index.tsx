@@ -1,9 +1,17 @@
+import EmailForm from "#islands/EmailForm.tsx";
+import RecoveryRegenerate from "#islands/RecoveryRegenerate.tsx";
 import Signout from "#islands/Signout.tsx";
+import TotpEnroll from "#islands/TotpEnroll.tsx";
+import WebauthnRegister from "#islands/WebauthnRegister.tsx";
 
 export default function Index() {
   return (
-    <div class="p-4">
+    <div class="flex flex-col gap-4 p-4">
       <Signout />
+      <EmailForm />
+      <TotpEnroll />
+      <WebauthnRegister />
+      <RecoveryRegenerate />
     </div>
   );
 }
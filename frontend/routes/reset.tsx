@@ -0,0 +1,16 @@
+import { Head } from "$fresh/runtime.ts";
+import { PageProps } from "$fresh/server.ts";
+import ResetForm from "#islands/ResetForm.tsx";
+
+export default function Reset({ url }: PageProps) {
+  return (
+    <>
+      <Head>
+        <title>Fresh Auth | Reset password</title>
+      </Head>
+      <div class="flex h-screen">
+        <ResetForm breached={url.searchParams.has("breached")} />
+      </div>
+    </>
+  );
+}
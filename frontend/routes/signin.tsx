@@ -1,6 +1,12 @@
 import { Head } from "$fresh/runtime.ts";
 import SignInForm from "#islands/SignInForm.tsx";
 
+/** Static public keys of the api server pinned by the client, comma separated */
+const serverKeys = Deno.env.get("OPAQUE_PINNED_KEYS")
+  ?.split(",")
+  .map((key) => key.trim())
+  .filter((key) => key !== "");
+
 export default function Signin() {
   return (
     <>
@@ -8,7 +14,7 @@ export default function Signin() {
         <title>Fresh Auth | Signin</title>
       </Head>
       <div class="flex h-screen">
-        <SignInForm />
+        <SignInForm serverKeys={serverKeys} />
       </div>
     </>
   );
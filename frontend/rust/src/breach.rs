@@ -0,0 +1,35 @@
+use fresh_auth_suite::breach;
+use wasm_bindgen::prelude::*;
+
+/// Bloom filter of breached passwords, built by the api server.
+#[wasm_bindgen]
+pub struct BreachFilter {
+    filter: breach::BreachFilter,
+}
+
+#[wasm_bindgen]
+impl BreachFilter {
+    /// Load the filter from its binary format.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<BreachFilter, JsError> {
+        let filter = breach::BreachFilter::from_bytes(bytes)
+            .map_err(|_| JsError::new("Invalid breached password filter"))?;
+        Ok(BreachFilter { filter })
+    }
+
+    /// Check if the password appears in the filter.
+    #[wasm_bindgen(js_name = "isBreached")]
+    pub fn is_breached(&self, password: &str) -> bool {
+        self.filter.contains(password)
+    }
+
+    /// Check the password, it fails if the password is a breached one.
+    pub fn check(&self, password: &str) -> Result<(), JsError> {
+        if self.is_breached(password) {
+            return Err(JsError::new(
+                "Password appears in a list of breached passwords",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 use wasm_bindgen::prelude::*;
 
+mod breach;
 mod opaque;
 mod password;
+mod strength;
 
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
@@ -1,22 +1,65 @@
 use std::cell::RefCell;
 
 use base64ct::{Base64Url, Encoding};
+use fresh_auth_suite::{
+    envelope::{self, MessageType},
+    CipherSuite,
+};
+use opaque_ke::errors::ProtocolError;
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use wasm_bindgen::prelude::*;
 
+use crate::password;
+
+/// Error of a login with a wrong password, the client may try the legacy sign
+/// in.
+const INVALID_CREDENTIALS: &str = "Invalid username or password";
+
 thread_local! {
     static RNG: RefCell<ChaChaRng> = RefCell::new(ChaChaRng::from_entropy());
 }
 
-/// Cipher suite definition
-pub struct CipherSuite;
+/// Identifier of the cipher suite, it must match the one advertised by the
+/// server.
+#[wasm_bindgen(js_name = "cipherSuite")]
+pub fn cipher_suite() -> String {
+    fresh_auth_suite::SUITE.to_string()
+}
+
+/// Parameters of the key stretching function (Argon2id), provided by the server.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct KsfParams {
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+#[wasm_bindgen]
+impl KsfParams {
+    /// Create the parameters, memory size is in KiB.
+    #[wasm_bindgen(constructor)]
+    pub fn new(memory: u32, iterations: u32, parallelism: u32) -> KsfParams {
+        KsfParams {
+            memory,
+            iterations,
+            parallelism,
+        }
+    }
+}
 
-impl opaque_ke::CipherSuite for CipherSuite {
-    type OprfCs = opaque_ke::Ristretto255;
-    type KeGroup = opaque_ke::Ristretto255;
-    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
-    type Ksf = argon2::Argon2<'static>;
+impl KsfParams {
+    /// Build the key stretching function.
+    fn ksf(&self) -> Result<argon2::Argon2<'static>, JsError> {
+        let params = argon2::Params::new(self.memory, self.iterations, self.parallelism, None)
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(argon2::Argon2::new(
+            argon2::Algorithm::Argon2id,
+            argon2::Version::V0x13,
+            params,
+        ))
+    }
 }
 
 #[wasm_bindgen(getter_with_clone)]
@@ -36,6 +79,7 @@ pub struct OpaqueRegistrationFinish {
 impl OpaqueRegistration {
     /// Start registration step.
     pub fn start(password: &str) -> Result<OpaqueRegistration, JsError> {
+        let password = password::normalize(password).map_err(JsError::new)?;
         let registration_start = RNG
             .with_borrow_mut(|rng| {
                 opaque_ke::ClientRegistration::<CipherSuite>::start(rng, password.as_bytes())
@@ -43,7 +87,7 @@ impl OpaqueRegistration {
             .map_err(JsError::from)?;
 
         let message = registration_start.message.serialize();
-        let message = Base64Url::encode_string(&message);
+        let message = envelope::seal(MessageType::RegistrationRequest, &message);
 
         Ok(OpaqueRegistration {
             state: registration_start.state,
@@ -51,17 +95,25 @@ impl OpaqueRegistration {
         })
     }
 
-    /// Finish the registration.
+    /// Finish the registration, the server identity is the one provided by
+    /// the server.
     pub fn finish(
         self,
         password: &str,
         message: &str,
+        ksf_params: &KsfParams,
+        identity: Option<String>,
     ) -> Result<OpaqueRegistrationFinish, JsError> {
-        let registration_response = Base64Url::decode_vec(message).map_err(JsError::from)?;
+        let password = password::normalize(password).map_err(JsError::new)?;
+        let registration_response = envelope::open(MessageType::RegistrationResponse, message)?;
         let registration_response =
             opaque_ke::RegistrationResponse::deserialize(&registration_response)
                 .map_err(JsError::from)?;
-        let params = opaque_ke::ClientRegistrationFinishParameters::default();
+        let ksf = ksf_params.ksf()?;
+        let params = opaque_ke::ClientRegistrationFinishParameters::new(
+            identifiers(identity.as_deref()),
+            Some(&ksf),
+        );
 
         let registration_finish = RNG
             .with_borrow_mut(|rng| {
@@ -71,7 +123,7 @@ impl OpaqueRegistration {
             .map_err(JsError::from)?;
 
         let message = registration_finish.message.serialize();
-        let message = Base64Url::encode_string(&message);
+        let message = envelope::seal(MessageType::RegistrationUpload, &message);
 
         Ok(OpaqueRegistrationFinish { message })
     }
@@ -80,6 +132,7 @@ impl OpaqueRegistration {
 #[wasm_bindgen(getter_with_clone)]
 pub struct OpaqueLogin {
     state: opaque_ke::ClientLogin<CipherSuite>,
+    pinned: Vec<Vec<u8>>,
     /// Base64 encoded message should be sent to the server.
     pub message: String,
 }
@@ -94,6 +147,7 @@ pub struct OpaqueLoginFinish {
 impl OpaqueLogin {
     /// Start login step.
     pub fn start(password: &str) -> Result<OpaqueLogin, JsError> {
+        let password = password::normalize(password).map_err(JsError::new)?;
         let login_start = RNG
             .with_borrow_mut(|rng| {
                 opaque_ke::ClientLogin::<CipherSuite>::start(rng, password.as_bytes())
@@ -101,29 +155,73 @@ impl OpaqueLogin {
             .map_err(JsError::from)?;
 
         let message = login_start.message.serialize();
-        let message = Base64Url::encode_string(&message);
+        let message = envelope::seal(MessageType::CredentialRequest, &message);
 
         Ok(OpaqueLogin {
             state: login_start.state,
+            pinned: Vec::new(),
             message,
         })
     }
 
-    /// Finish the login.
-    pub fn finish(self, password: &str, message: &str) -> Result<OpaqueLoginFinish, JsError> {
-        let credential_response = Base64Url::decode_vec(message).map_err(JsError::from)?;
+    /// Pin the static public key of the server (base64url encoded), the login
+    /// fails if the server reveals a different one. More keys can be pinned
+    /// while the signature of the server is rotated.
+    pub fn pin(&mut self, key: &str) -> Result<(), JsError> {
+        let key = Base64Url::decode_vec(key).map_err(JsError::from)?;
+        self.pinned.push(key);
+        Ok(())
+    }
+
+    /// Finish the login, the server identity and the context are the ones
+    /// provided by the server.
+    ///
+    /// A wrong password or an unknown user fails with the error
+    /// [`INVALID_CREDENTIALS`].
+    pub fn finish(
+        self,
+        password: &str,
+        message: &str,
+        ksf_params: &KsfParams,
+        identity: Option<String>,
+        context: Option<String>,
+    ) -> Result<OpaqueLoginFinish, JsError> {
+        let password = password::normalize(password).map_err(JsError::new)?;
+        let credential_response = envelope::open(MessageType::CredentialResponse, message)?;
         let credential_response = opaque_ke::CredentialResponse::deserialize(&credential_response)
             .map_err(JsError::from)?;
-        let params = opaque_ke::ClientLoginFinishParameters::default();
-
-        let registration_finish = self
+        let ksf = ksf_params.ksf()?;
+        let params = opaque_ke::ClientLoginFinishParameters::new(
+            context.as_deref().map(str::as_bytes),
+            identifiers(identity.as_deref()),
+            Some(&ksf),
+        );
+
+        let login_finish = self
             .state
             .finish(password.as_bytes(), credential_response, params)
-            .map_err(JsError::from)?;
+            .map_err(|err| match err {
+                ProtocolError::InvalidLoginError => JsError::new(INVALID_CREDENTIALS),
+                err => JsError::from(err),
+            })?;
 
-        let message = registration_finish.message.serialize();
-        let message = Base64Url::encode_string(&message);
+        let server_key = login_finish.server_s_pk.serialize();
+        if !self.pinned.is_empty() && !self.pinned.iter().any(|key| key[..] == server_key[..]) {
+            return Err(JsError::new("Server public key is not pinned"));
+        }
+
+        let message = login_finish.message.serialize();
+        let message = envelope::seal(MessageType::CredentialFinalization, &message);
 
         Ok(OpaqueLoginFinish { message })
     }
 }
+
+/// Identifiers bound to the key exchange, the server public key is used when
+/// the server has no identity.
+fn identifiers(identity: Option<&str>) -> opaque_ke::Identifiers<'_> {
+    opaque_ke::Identifiers {
+        client: None,
+        server: identity.map(str::as_bytes),
+    }
+}
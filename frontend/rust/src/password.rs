@@ -1,38 +1,96 @@
-use itertools::Itertools;
 use wasm_bindgen::prelude::*;
 
-const MIN_PASSWORD_ENTROPY: f64 = 75.0;
+use crate::strength;
 
-/// Check credentials strength.
-#[wasm_bindgen(js_name = "checkCredentialsStrength")]
-pub fn check_credentials_strength(username: &str, password: &str) -> Result<(), JsError> {
-    if strsim::levenshtein(username, password) < 5 {
-        return Err(JsError::new("Username and password are too similar"));
-    }
+pub use fresh_auth_suite::password::normalize;
 
-    if password.chars().any(|c| !c.is_ascii()) {
-        return Err(JsError::new("Password contains non ASCII characters"));
-    }
+/// Password policy, provided by the server.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct PasswordPolicy {
+    score: u8,
+    length: u32,
+    distance: u32,
+}
 
-    let charset: String = password.chars().unique().collect();
-    let mut total_range = 0;
-    if charset.chars().any(|c| c.is_ascii_digit()) {
-        total_range += 10;
-    }
-    if charset.chars().any(|c| c.is_ascii_lowercase()) {
-        total_range += 26;
+#[wasm_bindgen]
+impl PasswordPolicy {
+    /// Create the policy from the minimum score, the minimum length and the
+    /// minimum edit distance between username and password.
+    #[wasm_bindgen(constructor)]
+    pub fn new(score: u8, length: u32, distance: u32) -> PasswordPolicy {
+        PasswordPolicy {
+            score,
+            length,
+            distance,
+        }
     }
-    if charset.chars().any(|c| c.is_ascii_uppercase()) {
-        total_range += 26;
+}
+
+/// Strength of the credentials, with the feedback to improve them.
+#[wasm_bindgen(getter_with_clone)]
+pub struct PasswordStrength {
+    /// Whether the password can be used.
+    pub accepted: bool,
+    /// Score from 0 (too guessable) to 4 (very unguessable).
+    pub score: u8,
+    /// Estimated time to crack the password with an offline attack.
+    #[wasm_bindgen(js_name = "crackTime")]
+    pub crack_time: String,
+    /// Estimated seconds to crack the password with an offline attack.
+    #[wasm_bindgen(js_name = "crackTimeSeconds")]
+    pub crack_time_seconds: f64,
+    /// Main weakness of the password.
+    pub warning: Option<String>,
+    /// Suggestions to improve the password.
+    pub suggestions: Vec<String>,
+}
+
+impl PasswordStrength {
+    fn rejected(warning: impl Into<String>) -> Self {
+        Self {
+            accepted: false,
+            score: 0,
+            crack_time: "less than a second".to_string(),
+            crack_time_seconds: 0.0,
+            warning: Some(warning.into()),
+            suggestions: vec![],
+        }
     }
-    if charset.chars().any(|c| !c.is_ascii_alphanumeric()) {
-        total_range += 32;
+}
+
+/// Check credentials strength.
+#[wasm_bindgen(js_name = "checkCredentialsStrength")]
+pub fn check_credentials_strength(
+    username: &str,
+    password: &str,
+    policy: &PasswordPolicy,
+) -> PasswordStrength {
+    let username: String = username.nfc().collect();
+    let password = match normalize(password) {
+        Ok(password) => password,
+        Err(err) => return PasswordStrength::rejected(err),
+    };
+
+    if password.chars().count() < policy.length as usize {
+        return PasswordStrength::rejected(format!(
+            "Password must be at least {} characters long",
+            policy.length
+        ));
     }
 
-    let entropy = charset.len() as f64 * (total_range as f64).log2();
-    if entropy < MIN_PASSWORD_ENTROPY {
-        return Err(JsError::new("Too weak password!"));
+    if strsim::levenshtein(&username, &password) < policy.distance as usize {
+        return PasswordStrength::rejected("Username and password are too similar");
     }
 
-    Ok(())
+    let estimate = strength::estimate(&password, &[&username]);
+    let (warning, suggestions) = estimate.feedback();
+    PasswordStrength {
+        accepted: estimate.score >= policy.score,
+        score: estimate.score,
+        crack_time: estimate.crack_time_display(),
+        crack_time_seconds: estimate.crack_time_seconds(),
+        warning: warning.map(str::to_string),
+        suggestions: suggestions.into_iter().map(str::to_string).collect(),
+    }
 }
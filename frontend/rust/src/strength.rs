@@ -0,0 +1,1142 @@
+//! Password strength estimation
+//!
+//! The estimator follows the approach of zxcvbn: the password is matched
+//! against a set of patterns (dictionary words, keyboard walks, repeats,
+//! sequences and dates), each match is given an estimate of the guesses
+//! needed to find it, and the password is scored by the sequence of
+//! non-overlapping matches that needs the fewest guesses. The characters not
+//! covered by any match are guessed by brute force.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+/// Common passwords, ordered by frequency.
+const PASSWORDS: &str = include_str!("dictionary/passwords.txt");
+
+/// Common english words, ordered by frequency.
+const WORDS: &str = include_str!("dictionary/words.txt");
+
+/// Only the first characters of the password are matched and searched for the
+/// best sequence, the remaining ones are guessed by brute force. The search
+/// is cubic in the number of characters, so it is bounded.
+const MAX_MATCHED_CHARS: usize = 100;
+
+/// Guesses of a single character guessed by brute force.
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+
+/// Guesses of a single non-ASCII character guessed by brute force, they are
+/// drawn from a larger alphabet.
+const NON_ASCII_BRUTEFORCE_CARDINALITY: f64 = 20.0;
+
+/// Minimum guesses of a match of a single character.
+const MIN_SUBMATCH_GUESSES_SINGLE_CHAR: f64 = 10.0;
+
+/// Minimum guesses of a match of more characters.
+const MIN_SUBMATCH_GUESSES_MULTI_CHAR: f64 = 50.0;
+
+/// Penalty of each additional match in the sequence.
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10000.0;
+
+/// Minimum distance in years of a date from the reference year.
+const MIN_YEAR_SPACE: i32 = 20;
+
+/// Separators of the dates.
+const DATE_SEPARATORS: &[char] = &['/', '-', '.', '_', ' '];
+
+/// Guesses per second of an offline attack against a slow hash function.
+const GUESSES_PER_SECOND: f64 = 1e4;
+
+/// Substitutions commonly used in passwords, as `(substitute, letters)`.
+const L33T_TABLE: &[(char, &[char])] = &[
+    ('4', &['a']),
+    ('@', &['a']),
+    ('8', &['b']),
+    ('(', &['c']),
+    ('{', &['c']),
+    ('[', &['c']),
+    ('<', &['c']),
+    ('3', &['e']),
+    ('6', &['g']),
+    ('9', &['g']),
+    ('1', &['i', 'l']),
+    ('!', &['i']),
+    ('|', &['i', 'l']),
+    ('0', &['o']),
+    ('$', &['s']),
+    ('5', &['s']),
+    ('+', &['t']),
+    ('7', &['t', 'l']),
+    ('%', &['x']),
+    ('2', &['z']),
+];
+
+/// Rows of the qwerty keyboard, unshifted and shifted. Each row is shifted
+/// half a key to the right of the previous one, so the key at column `c` is
+/// adjacent to the keys at columns `c` and `c + 1` of the previous row.
+const QWERTY: &[(&str, &str)] = &[
+    ("`1234567890-=", "~!@#$%^&*()_+"),
+    (" qwertyuiop[]\\", " QWERTYUIOP{}|"),
+    (" asdfghjkl;'", " ASDFGHJKL:\""),
+    (" zxcvbnm,./", " ZXCVBNM<>?"),
+];
+
+/// Source of a dictionary match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dictionary {
+    Passwords,
+    Words,
+    UserInputs,
+}
+
+/// Pattern recognized in the password.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Word of a dictionary, possibly reversed or with l33t substitutions.
+    Dictionary {
+        dictionary: Dictionary,
+        rank: usize,
+        reversed: bool,
+        l33t: bool,
+    },
+    /// Walk on adjacent keys of the keyboard.
+    Spatial { turns: usize },
+    /// Repetition of the same characters.
+    Repeat { base_len: usize },
+    /// Sequence of characters, like `abc` or `6543`.
+    Sequence,
+    /// Date or year.
+    Date,
+    /// Characters not matched by any pattern.
+    Bruteforce,
+}
+
+/// Part of the password matched by a pattern.
+#[derive(Clone, Debug)]
+pub struct Match {
+    /// Index of the first character.
+    pub i: usize,
+    /// Index of the last character.
+    pub j: usize,
+    /// Matched characters.
+    pub token: String,
+    /// Recognized pattern.
+    pub pattern: Pattern,
+    /// Estimated number of guesses.
+    pub guesses: f64,
+}
+
+impl Match {
+    fn new(chars: &[char], i: usize, j: usize, pattern: Pattern, guesses: f64) -> Self {
+        Self {
+            i,
+            j,
+            token: chars[i..=j].iter().collect(),
+            pattern,
+            guesses,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.j - self.i + 1
+    }
+}
+
+/// Result of the estimation.
+pub struct Estimate {
+    /// Estimated number of guesses.
+    pub guesses: f64,
+    /// Score from 0 (too guessable) to 4 (very unguessable).
+    pub score: u8,
+    /// Matches used by the estimation.
+    pub sequence: Vec<Match>,
+}
+
+impl Estimate {
+    /// Estimated time to crack the password with an offline attack.
+    pub fn crack_time_seconds(&self) -> f64 {
+        self.guesses / GUESSES_PER_SECOND
+    }
+
+    /// Estimated time to crack the password, in a human readable form.
+    pub fn crack_time_display(&self) -> String {
+        const MINUTE: f64 = 60.0;
+        const HOUR: f64 = MINUTE * 60.0;
+        const DAY: f64 = HOUR * 24.0;
+        const MONTH: f64 = DAY * 31.0;
+        const YEAR: f64 = MONTH * 12.0;
+        const CENTURY: f64 = YEAR * 100.0;
+
+        let seconds = self.crack_time_seconds();
+        let (value, unit) = if seconds < 1.0 {
+            return "less than a second".to_string();
+        } else if seconds < MINUTE {
+            (seconds, "second")
+        } else if seconds < HOUR {
+            (seconds / MINUTE, "minute")
+        } else if seconds < DAY {
+            (seconds / HOUR, "hour")
+        } else if seconds < MONTH {
+            (seconds / DAY, "day")
+        } else if seconds < YEAR {
+            (seconds / MONTH, "month")
+        } else if seconds < CENTURY {
+            (seconds / YEAR, "year")
+        } else {
+            return "centuries".to_string();
+        };
+
+        let value = value.round() as u64;
+        if value == 1 {
+            format!("1 {unit}")
+        } else {
+            format!("{value} {unit}s")
+        }
+    }
+
+    /// Warning and suggestions to improve the password.
+    pub fn feedback(&self) -> (Option<&'static str>, Vec<&'static str>) {
+        if self.sequence.is_empty() {
+            return (
+                None,
+                vec![
+                    "Use a few words, avoid common phrases",
+                    "No need for symbols, digits, or uppercase letters",
+                ],
+            );
+        }
+        if self.score > 2 {
+            return (None, vec![]);
+        }
+
+        let longest = self
+            .sequence
+            .iter()
+            .max_by_key(|m| m.len())
+            .expect("sequence is not empty");
+        let (warning, mut suggestions) = match_feedback(longest, self.sequence.len() == 1);
+        suggestions.insert(0, "Add another word or two, uncommon words are better");
+        (warning, suggestions)
+    }
+}
+
+/// Estimate the strength of the password, the user inputs (like the
+/// username) are matched as a dictionary.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Estimate {
+    let chars: Vec<char> = password.chars().collect();
+    let matched = &chars[..chars.len().min(MAX_MATCHED_CHARS)];
+
+    let user_inputs: HashMap<String, usize> = user_inputs
+        .iter()
+        .filter(|input| !input.is_empty())
+        .enumerate()
+        .map(|(rank, input)| (input.to_lowercase(), rank + 1))
+        .collect();
+    let mut matches = omnimatch(matched, &user_inputs);
+    for m in &mut matches {
+        m.guesses = m.guesses.max(min_submatch_guesses(m, chars.len()));
+    }
+    let mut estimate = most_guessable_sequence(matched, matches);
+
+    if chars.len() > matched.len() {
+        let tail = bruteforce_match(&chars, matched.len(), chars.len() - 1);
+        estimate.guesses = (estimate.guesses * tail.guesses).min(f64::MAX);
+        estimate.score = score(estimate.guesses);
+        estimate.sequence.push(tail);
+    }
+    estimate
+}
+
+/// Find all the matches in the password.
+fn omnimatch(chars: &[char], user_inputs: &HashMap<String, usize>) -> Vec<Match> {
+    let mut matches = Vec::new();
+    dictionary_matches(chars, user_inputs, &mut matches);
+    spatial_matches(chars, &mut matches);
+    repeat_matches(chars, user_inputs, &mut matches);
+    sequence_matches(chars, &mut matches);
+    date_matches(chars, &mut matches);
+    matches
+}
+
+/// Ranked dictionaries.
+fn dictionaries() -> &'static [(Dictionary, HashMap<&'static str, usize>)] {
+    static DICTIONARIES: OnceLock<Vec<(Dictionary, HashMap<&'static str, usize>)>> =
+        OnceLock::new();
+    DICTIONARIES.get_or_init(|| {
+        let ranked = |words: &'static str| {
+            words
+                .lines()
+                .filter(|word| !word.is_empty())
+                .enumerate()
+                .map(|(rank, word)| (word, rank + 1))
+                .collect()
+        };
+        vec![
+            (Dictionary::Passwords, ranked(PASSWORDS)),
+            (Dictionary::Words, ranked(WORDS)),
+        ]
+    })
+}
+
+/// Search the word in the dictionaries, returns the best rank.
+fn lookup(word: &str, user_inputs: &HashMap<String, usize>) -> Option<(Dictionary, usize)> {
+    let user_input = user_inputs
+        .get(word)
+        .map(|rank| (Dictionary::UserInputs, *rank));
+    dictionaries()
+        .iter()
+        .filter_map(|(dictionary, words)| words.get(word).map(|rank| (*dictionary, *rank)))
+        .chain(user_input)
+        .min_by_key(|(_, rank)| *rank)
+}
+
+fn dictionary_matches(
+    chars: &[char],
+    user_inputs: &HashMap<String, usize>,
+    matches: &mut Vec<Match>,
+) {
+    // characters without a single lowercase character are kept as they are,
+    // so the indices are aligned with the password
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(lower), None) => lower,
+                _ => *c,
+            }
+        })
+        .collect();
+    let reversed: Vec<char> = lower.iter().rev().copied().collect();
+    let n = chars.len();
+
+    for i in 0..n {
+        for j in i..n {
+            let word: String = lower[i..=j].iter().collect();
+            if let Some((dictionary, rank)) = lookup(&word, user_inputs) {
+                let guesses = rank as f64 * uppercase_variations(&chars[i..=j]);
+                let pattern = Pattern::Dictionary {
+                    dictionary,
+                    rank,
+                    reversed: false,
+                    l33t: false,
+                };
+                matches.push(Match::new(chars, i, j, pattern, guesses));
+            }
+
+            // reversed words, the indices are mapped back to the password
+            let word: String = reversed[i..=j].iter().collect();
+            if word.chars().count() > 2 {
+                if let Some((dictionary, rank)) = lookup(&word, user_inputs) {
+                    let (ri, rj) = (n - 1 - j, n - 1 - i);
+                    let guesses = rank as f64 * uppercase_variations(&chars[ri..=rj]) * 2.0;
+                    let pattern = Pattern::Dictionary {
+                        dictionary,
+                        rank,
+                        reversed: true,
+                        l33t: false,
+                    };
+                    matches.push(Match::new(chars, ri, rj, pattern, guesses));
+                }
+            }
+
+            // substituted letters
+            if lower[i..=j].iter().any(|c| l33t_letters(*c).is_some()) {
+                for word in unl33t(&lower[i..=j]) {
+                    let word: String = word.into_iter().collect();
+                    if let Some((dictionary, rank)) = lookup(&word, user_inputs) {
+                        let guesses = rank as f64
+                            * uppercase_variations(&chars[i..=j])
+                            * l33t_variations(&lower[i..=j], &word);
+                        let pattern = Pattern::Dictionary {
+                            dictionary,
+                            rank,
+                            reversed: false,
+                            l33t: true,
+                        };
+                        matches.push(Match::new(chars, i, j, pattern, guesses));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Letters that can be replaced by the character.
+fn l33t_letters(c: char) -> Option<&'static [char]> {
+    L33T_TABLE
+        .iter()
+        .find(|(substitute, _)| *substitute == c)
+        .map(|(_, letters)| *letters)
+}
+
+/// Candidate words obtained by undoing the substitutions, at most 16.
+fn unl33t(chars: &[char]) -> Vec<Vec<char>> {
+    const MAX_CANDIDATES: usize = 16;
+
+    let mut candidates = vec![Vec::with_capacity(chars.len())];
+    for c in chars {
+        let letters = l33t_letters(*c).unwrap_or(&[]);
+        candidates = candidates
+            .into_iter()
+            .flat_map(|candidate| {
+                letters
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(*c))
+                    .map(move |letter| {
+                        let mut candidate = candidate.clone();
+                        candidate.push(letter);
+                        candidate
+                    })
+            })
+            .take(MAX_CANDIDATES)
+            .collect();
+    }
+    candidates.retain(|candidate| candidate.as_slice() != chars);
+    candidates
+}
+
+/// Number of ways to substitute the letters of the word.
+fn l33t_variations(token: &[char], word: &str) -> f64 {
+    let mut variations = 1.0;
+    let substituted: Vec<(char, char)> = token
+        .iter()
+        .zip(word.chars())
+        .filter(|(c, letter)| *c != letter)
+        .map(|(c, letter)| (*c, letter))
+        .collect();
+    let mut seen = Vec::new();
+    for (substitute, letter) in substituted {
+        if seen.contains(&(substitute, letter)) {
+            continue;
+        }
+        seen.push((substitute, letter));
+        let s = token
+            .iter()
+            .zip(word.chars())
+            .filter(|(c, l)| **c == substitute && *l == letter)
+            .count();
+        let u = token.iter().filter(|c| **c == letter).count();
+        variations *= if u == 0 {
+            2.0
+        } else {
+            (1..=s.min(u)).map(|k| n_choose_k(s + u, k)).sum::<f64>()
+        };
+    }
+    variations
+}
+
+/// Number of ways to capitalize the word.
+fn uppercase_variations(chars: &[char]) -> f64 {
+    let upper = chars.iter().filter(|c| c.is_uppercase()).count();
+    let lower = chars.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+
+    // the most common capitalizations
+    let first_upper = chars[0].is_uppercase() && upper == 1;
+    let last_upper = chars[chars.len() - 1].is_uppercase() && upper == 1;
+    if first_upper || last_upper || lower == 0 {
+        return 2.0;
+    }
+
+    (1..=upper.min(lower))
+        .map(|k| n_choose_k(upper + lower, k))
+        .sum()
+}
+
+/// Position of the key on the keyboard, as `(row, column, shifted)`.
+fn key_position(c: char) -> Option<(usize, usize, bool)> {
+    QWERTY
+        .iter()
+        .enumerate()
+        .find_map(|(row, (keys, shifted))| {
+            keys.chars()
+                .position(|k| k == c && k != ' ')
+                .map(|column| (row, column, false))
+                .or_else(|| {
+                    shifted
+                        .chars()
+                        .position(|k| k == c && k != ' ')
+                        .map(|column| (row, column, true))
+                })
+        })
+}
+
+/// Direction of the movement between adjacent keys, if they are adjacent.
+fn key_direction(from: (usize, usize), to: (usize, usize)) -> Option<u8> {
+    let (r1, c1) = (from.0 as isize, from.1 as isize);
+    let (r2, c2) = (to.0 as isize, to.1 as isize);
+    match (r2 - r1, c2 - c1) {
+        (0, -1) => Some(0),
+        (0, 1) => Some(1),
+        (-1, 0) => Some(2),
+        (-1, 1) => Some(3),
+        (1, -1) => Some(4),
+        (1, 0) => Some(5),
+        _ => None,
+    }
+}
+
+fn spatial_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut i = 0;
+    while i + 2 < n {
+        let Some(start) = key_position(chars[i]) else {
+            i += 1;
+            continue;
+        };
+        let mut shifted = usize::from(start.2);
+        let mut turns = 0;
+        let mut last_direction = None;
+        let mut previous = start;
+        let mut j = i;
+        while j + 1 < n {
+            let Some(next) = key_position(chars[j + 1]) else {
+                break;
+            };
+            let Some(direction) = key_direction((previous.0, previous.1), (next.0, next.1)) else {
+                break;
+            };
+            if last_direction != Some(direction) {
+                turns += 1;
+                last_direction = Some(direction);
+            }
+            shifted += usize::from(next.2);
+            previous = next;
+            j += 1;
+        }
+
+        if j - i + 1 > 2 {
+            let guesses = spatial_guesses(j - i + 1, turns, shifted);
+            matches.push(Match::new(chars, i, j, Pattern::Spatial { turns }, guesses));
+            i = j + 1;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+fn spatial_guesses(len: usize, turns: usize, shifted: usize) -> f64 {
+    // number of keys and average number of adjacent keys
+    const STARTING_POSITIONS: f64 = 47.0;
+    const AVERAGE_DEGREE: f64 = 4.6;
+
+    let mut guesses = 0.0;
+    for i in 2..=len {
+        for j in 1..=turns.min(i - 1) {
+            guesses +=
+                n_choose_k(i - 1, j - 1) * STARTING_POSITIONS * AVERAGE_DEGREE.powi(j as i32);
+        }
+    }
+
+    let unshifted = len - shifted;
+    if shifted > 0 {
+        guesses *= if unshifted == 0 {
+            2.0
+        } else {
+            (1..=shifted.min(unshifted))
+                .map(|k| n_choose_k(shifted + unshifted, k))
+                .sum()
+        };
+    }
+    guesses
+}
+
+fn repeat_matches(chars: &[char], user_inputs: &HashMap<String, usize>, matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut i = 0;
+    while i < n {
+        // longest repetition starting at i
+        let best = (1..=(n - i) / 2)
+            .filter_map(|base_len| {
+                let base = &chars[i..i + base_len];
+                let count = chars[i..]
+                    .chunks_exact(base_len)
+                    .take_while(|chunk| *chunk == base)
+                    .count();
+                let is_repeat = count > 2 || (count == 2 && base_len > 1);
+                is_repeat.then_some((base_len, count))
+            })
+            .max_by_key(|(base_len, count)| (base_len * count, std::cmp::Reverse(*base_len)));
+
+        match best {
+            Some((base_len, count)) => {
+                let base: String = chars[i..i + base_len].iter().collect();
+                let base_guesses = if base_len == 1 {
+                    BRUTEFORCE_CARDINALITY
+                } else {
+                    let user_inputs: Vec<&str> = user_inputs.keys().map(String::as_str).collect();
+                    estimate(&base, &user_inputs).guesses
+                };
+                let j = i + base_len * count - 1;
+                let guesses = base_guesses * count as f64;
+                matches.push(Match::new(
+                    chars,
+                    i,
+                    j,
+                    Pattern::Repeat { base_len },
+                    guesses,
+                ));
+                i = j + 1;
+            }
+            None => i += 1,
+        }
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let n = chars.len();
+    let mut i = 0;
+    while i + 2 < n {
+        let delta = chars[i + 1] as i64 - chars[i] as i64;
+        let same_class = |a: char, b: char| {
+            (a.is_lowercase() && b.is_lowercase())
+                || (a.is_uppercase() && b.is_uppercase())
+                || (a.is_ascii_digit() && b.is_ascii_digit())
+        };
+        if delta == 0 || delta.abs() > 5 || !same_class(chars[i], chars[i + 1]) {
+            i += 1;
+            continue;
+        }
+
+        let mut j = i + 1;
+        while j + 1 < n
+            && chars[j + 1] as i64 - chars[j] as i64 == delta
+            && same_class(chars[j], chars[j + 1])
+        {
+            j += 1;
+        }
+
+        if j - i + 1 > 2 {
+            let first = chars[i];
+            let base: f64 = if matches!(first, 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9') {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let descending = if delta > 0 { 1.0 } else { 2.0 };
+            let guesses = base * descending * (j - i + 1) as f64;
+            matches.push(Match::new(chars, i, j, Pattern::Sequence, guesses));
+            i = j + 1;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+fn date_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let reference_year = reference_year();
+    let n = chars.len();
+    for i in 0..n {
+        for j in i + 3..n.min(i + 10) {
+            let token = &chars[i..=j];
+            let Some((year, separator)) = parse_date(token) else {
+                continue;
+            };
+            let mut guesses = (year - reference_year).abs().max(MIN_YEAR_SPACE) as f64;
+            if token.len() > 4 {
+                guesses *= 365.0;
+            }
+            if separator {
+                guesses *= 4.0;
+            }
+            matches.push(Match::new(chars, i, j, Pattern::Date, guesses));
+        }
+    }
+}
+
+/// Current year, used as reference for the dates.
+#[cfg(target_arch = "wasm32")]
+fn reference_year() -> i32 {
+    js_sys::Date::new_0().get_full_year() as i32
+}
+
+/// Current year, used as reference for the dates.
+#[cfg(not(target_arch = "wasm32"))]
+fn reference_year() -> i32 {
+    const SECONDS_PER_YEAR: u64 = 31_556_952;
+    let elapsed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    1970 + (elapsed.as_secs() / SECONDS_PER_YEAR) as i32
+}
+
+/// Parse the characters as a year or a date, returns the year and if the
+/// date uses separators.
+fn parse_date(token: &[char]) -> Option<(i32, bool)> {
+    if token.iter().all(char::is_ascii_digit) {
+        let year = match token.len() {
+            4 => parse_number(token).filter(|year| (1900..=2099).contains(year)),
+            6 => valid_date(&[&token[..2], &token[2..4], &token[4..]]),
+            8 => valid_date(&[&token[..4], &token[4..6], &token[6..]])
+                .or_else(|| valid_date(&[&token[..2], &token[2..4], &token[4..]])),
+            _ => None,
+        };
+        return year.map(|year| (year, false));
+    }
+
+    let separator = token.iter().find(|c| !c.is_ascii_digit())?;
+    if !DATE_SEPARATORS.contains(separator) {
+        return None;
+    }
+    let parts: Vec<&[char]> = token.split(|c| c == separator).collect();
+    valid_date(&parts).map(|year| (year, true))
+}
+
+/// Check the parts of a date, the year is either the first or the last part.
+fn valid_date(parts: &[&[char]]) -> Option<i32> {
+    let &[a, b, c] = parts else {
+        return None;
+    };
+    let a = (parse_number(a)?, a.len());
+    let b = (parse_number(b)?, b.len());
+    let c = (parse_number(c)?, c.len());
+
+    let year = |(value, len): (i32, usize)| match len {
+        4 => (1000..=2099).contains(&value).then_some(value),
+        2 if value > 50 => Some(1900 + value),
+        2 => Some(2000 + value),
+        _ => None,
+    };
+    let day_month = |a: (i32, usize), b: (i32, usize)| {
+        let is_day = |(value, len): (i32, usize)| len <= 2 && (1..=31).contains(&value);
+        let is_month = |(value, len): (i32, usize)| len <= 2 && (1..=12).contains(&value);
+        (is_day(a) && is_month(b)) || (is_month(a) && is_day(b))
+    };
+
+    if a.1 != 4 && day_month(a, b) {
+        year(c)
+    } else if day_month(b, c) {
+        year(a)
+    } else {
+        None
+    }
+}
+
+/// Parse the digits as a number.
+fn parse_number(chars: &[char]) -> Option<i32> {
+    if chars.is_empty() {
+        return None;
+    }
+    chars
+        .iter()
+        .try_fold(0, |acc, c| c.to_digit(10).map(|d| acc * 10 + d as i32))
+}
+
+/// Minimum guesses of a match, shorter than the whole password.
+fn min_submatch_guesses(m: &Match, password_len: usize) -> f64 {
+    if m.len() == password_len {
+        1.0
+    } else if m.len() == 1 {
+        MIN_SUBMATCH_GUESSES_SINGLE_CHAR
+    } else {
+        MIN_SUBMATCH_GUESSES_MULTI_CHAR
+    }
+}
+
+fn bruteforce_match(chars: &[char], i: usize, j: usize) -> Match {
+    let non_ascii = chars[i..=j].iter().filter(|c| !c.is_ascii()).count();
+    let guesses = bruteforce_guesses(j - i + 1, non_ascii);
+    Match::new(chars, i, j, Pattern::Bruteforce, guesses)
+}
+
+/// Guesses of the characters guessed by brute force, given how many of them
+/// are not ASCII.
+fn bruteforce_guesses(len: usize, non_ascii: usize) -> f64 {
+    let guesses = BRUTEFORCE_CARDINALITY.powi((len - non_ascii) as i32)
+        * NON_ASCII_BRUTEFORCE_CARDINALITY.powi(non_ascii as i32);
+    let min_guesses = if len == 1 {
+        MIN_SUBMATCH_GUESSES_SINGLE_CHAR + 1.0
+    } else {
+        MIN_SUBMATCH_GUESSES_MULTI_CHAR + 1.0
+    };
+    guesses.min(f64::MAX).max(min_guesses)
+}
+
+/// Best sequence of matches ending at a given position, for a given length.
+#[derive(Clone)]
+struct Candidate {
+    last: Match,
+    product: f64,
+    guesses: f64,
+    previous: Option<(usize, usize)>,
+}
+
+/// Search the sequence of non-overlapping matches covering the password which
+/// needs the fewest guesses, the gaps are covered by brute force.
+fn most_guessable_sequence(chars: &[char], matches: Vec<Match>) -> Estimate {
+    let n = chars.len();
+    if n == 0 {
+        return Estimate {
+            guesses: 1.0,
+            score: 0,
+            sequence: Vec::new(),
+        };
+    }
+
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n];
+    for m in matches {
+        by_end[m.j].push(m);
+    }
+
+    // non-ASCII characters before each position, so that the guesses of a
+    // brute force match are computed in constant time
+    let mut non_ascii = Vec::with_capacity(n + 1);
+    non_ascii.push(0);
+    for c in chars {
+        non_ascii.push(non_ascii[non_ascii.len() - 1] + usize::from(!c.is_ascii()));
+    }
+
+    // optimal[k][l] is the best sequence of l matches covering 0..=k, the
+    // match is built only if it improves the sequence
+    let mut optimal: Vec<HashMap<usize, Candidate>> = vec![HashMap::new(); n];
+    let update = |optimal: &mut Vec<HashMap<usize, Candidate>>,
+                  k: usize,
+                  m_guesses: f64,
+                  m: &dyn Fn() -> Match,
+                  len: usize,
+                  previous: Option<(usize, usize)>| {
+        let product = match previous {
+            Some((k, l)) => optimal[k][&l].product * m_guesses,
+            None => m_guesses,
+        };
+        let guesses =
+            factorial(len) * product + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(len as i32 - 1);
+        let is_better = optimal[k]
+            .iter()
+            .filter(|(l, _)| **l <= len)
+            .all(|(_, candidate)| candidate.guesses > guesses);
+        if is_better {
+            optimal[k].insert(
+                len,
+                Candidate {
+                    last: m(),
+                    product,
+                    guesses,
+                    previous,
+                },
+            );
+        }
+    };
+
+    for (k, ending) in by_end.into_iter().enumerate() {
+        for m in ending {
+            let make = || m.clone();
+            if m.i == 0 {
+                update(&mut optimal, k, m.guesses, &make, 1, None);
+            } else {
+                let previous: Vec<usize> = optimal[m.i - 1].keys().copied().collect();
+                for l in previous {
+                    update(&mut optimal, k, m.guesses, &make, l + 1, Some((m.i - 1, l)));
+                }
+            }
+        }
+
+        for i in 0..=k {
+            let guesses = bruteforce_guesses(k - i + 1, non_ascii[k + 1] - non_ascii[i]);
+            let make = || Match::new(chars, i, k, Pattern::Bruteforce, guesses);
+            if i == 0 {
+                update(&mut optimal, k, guesses, &make, 1, None);
+            } else {
+                // consecutive brute force matches are never better than one
+                let previous: Vec<usize> = optimal[i - 1]
+                    .iter()
+                    .filter(|(_, candidate)| !matches!(candidate.last.pattern, Pattern::Bruteforce))
+                    .map(|(l, _)| *l)
+                    .collect();
+                for l in previous {
+                    update(&mut optimal, k, guesses, &make, l + 1, Some((i - 1, l)));
+                }
+            }
+        }
+    }
+
+    // unwind the best sequence
+    let (best_len, best) = optimal[n - 1]
+        .iter()
+        .min_by(|(_, a), (_, b)| a.guesses.total_cmp(&b.guesses))
+        .map(|(l, candidate)| (*l, candidate.clone()))
+        .expect("the password is covered by brute force");
+    let guesses = best.guesses;
+    let mut sequence = Vec::with_capacity(best_len);
+    let mut current = Some(best);
+    while let Some(candidate) = current {
+        current = candidate.previous.map(|(k, l)| optimal[k][&l].clone());
+        sequence.push(candidate.last);
+    }
+    sequence.reverse();
+
+    Estimate {
+        guesses,
+        score: score(guesses),
+        sequence,
+    }
+}
+
+/// Score of the password from the estimated number of guesses.
+fn score(guesses: f64) -> u8 {
+    const DELTA: f64 = 5.0;
+    if guesses < 1e3 + DELTA {
+        0
+    } else if guesses < 1e6 + DELTA {
+        1
+    } else if guesses < 1e8 + DELTA {
+        2
+    } else if guesses < 1e10 + DELTA {
+        3
+    } else {
+        4
+    }
+}
+
+fn match_feedback(m: &Match, is_sole_match: bool) -> (Option<&'static str>, Vec<&'static str>) {
+    match &m.pattern {
+        Pattern::Dictionary {
+            dictionary,
+            rank,
+            reversed,
+            l33t,
+        } => {
+            let warning = match dictionary {
+                Dictionary::UserInputs => Some("Avoid using your username in the password"),
+                Dictionary::Passwords if is_sole_match && !l33t && !reversed => {
+                    if *rank <= 10 {
+                        Some("This is a top-10 common password")
+                    } else if *rank <= 100 {
+                        Some("This is a top-100 common password")
+                    } else {
+                        Some("This is a very common password")
+                    }
+                }
+                Dictionary::Passwords => Some("This is similar to a commonly used password"),
+                Dictionary::Words if is_sole_match => Some("A word by itself is easy to guess"),
+                Dictionary::Words => None,
+            };
+
+            let mut suggestions = Vec::new();
+            let chars: Vec<char> = m.token.chars().collect();
+            let is_all_upper = chars.iter().all(|c| !c.is_lowercase());
+            if chars[0].is_uppercase() && !is_all_upper {
+                suggestions.push("Capitalization doesn't help very much");
+            } else if is_all_upper && chars.iter().any(|c| c.is_uppercase()) {
+                suggestions.push("All-uppercase is almost as easy to guess as all-lowercase");
+            }
+            if *reversed && m.token.chars().count() >= 4 {
+                suggestions.push("Reversed words aren't much harder to guess");
+            }
+            if *l33t {
+                suggestions
+                    .push("Predictable substitutions like '@' instead of 'a' don't help very much");
+            }
+            (warning, suggestions)
+        }
+        Pattern::Spatial { turns } => {
+            let warning = if *turns == 1 {
+                "Straight rows of keys are easy to guess"
+            } else {
+                "Short keyboard patterns are easy to guess"
+            };
+            (
+                Some(warning),
+                vec!["Use a longer keyboard pattern with more turns"],
+            )
+        }
+        Pattern::Repeat { base_len } => {
+            let warning = if *base_len == 1 {
+                "Repeats like \"aaa\" are easy to guess"
+            } else {
+                "Repeats like \"abcabcabc\" are only slightly harder to guess than \"abc\""
+            };
+            (Some(warning), vec!["Avoid repeated words and characters"])
+        }
+        Pattern::Sequence => (
+            Some("Sequences like abc or 6543 are easy to guess"),
+            vec!["Avoid sequences"],
+        ),
+        Pattern::Date => (
+            Some("Dates are often easy to guess"),
+            vec!["Avoid dates and years that are associated with you"],
+        ),
+        Pattern::Bruteforce => (None, vec![]),
+    }
+}
+
+fn n_choose_k(n: usize, k: usize) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    (1..=k).fold(1.0, |acc, d| acc * (n + 1 - d) as f64 / d as f64)
+}
+
+fn factorial(n: usize) -> f64 {
+    (2..=n).fold(1.0, |acc, d| acc * d as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    fn chars(password: &str) -> Vec<char> {
+        password.chars().collect()
+    }
+
+    /// Returns the matches of the matcher covering the characters from `i` to `j`.
+    fn covering(matches: &[Match], i: usize, j: usize) -> Vec<&Match> {
+        matches.iter().filter(|m| m.i == i && m.j == j).collect()
+    }
+
+    #[test]
+    fn match_dictionary_words() {
+        let mut matches = Vec::new();
+        dictionary_matches(&chars("password"), &HashMap::new(), &mut matches);
+        let found = covering(&matches, 0, 7);
+        assert!(found.iter().any(|m| matches!(
+            m.pattern,
+            Pattern::Dictionary {
+                dictionary: Dictionary::Passwords,
+                rank: 2,
+                reversed: false,
+                l33t: false,
+            }
+        )));
+    }
+
+    #[test]
+    fn match_reversed_words() {
+        let mut matches = Vec::new();
+        dictionary_matches(&chars("drowssap"), &HashMap::new(), &mut matches);
+        let found = covering(&matches, 0, 7);
+        assert!(found
+            .iter()
+            .any(|m| matches!(m.pattern, Pattern::Dictionary { reversed: true, .. })));
+    }
+
+    #[test]
+    fn match_l33t_substitutions() {
+        let mut matches = Vec::new();
+        dictionary_matches(&chars("p@ssw0rd"), &HashMap::new(), &mut matches);
+        let found = covering(&matches, 0, 7);
+        assert!(found
+            .iter()
+            .any(|m| matches!(m.pattern, Pattern::Dictionary { l33t: true, .. })));
+    }
+
+    #[test]
+    fn match_user_inputs() {
+        let user_inputs = HashMap::from([("johnsmith".to_string(), 1)]);
+        let mut matches = Vec::new();
+        dictionary_matches(&chars("xjohnsmith"), &user_inputs, &mut matches);
+        let found = covering(&matches, 1, 9);
+        assert!(found.iter().any(|m| matches!(
+            m.pattern,
+            Pattern::Dictionary {
+                dictionary: Dictionary::UserInputs,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn match_keyboard_walks() {
+        let mut matches = Vec::new();
+        spatial_matches(&chars("qwerty"), &mut matches);
+        let found = covering(&matches, 0, 5);
+        assert!(found
+            .iter()
+            .any(|m| matches!(m.pattern, Pattern::Spatial { turns: 1 })));
+    }
+
+    #[test]
+    fn match_repeats() {
+        let mut matches = Vec::new();
+        repeat_matches(&chars("aaaaaa"), &HashMap::new(), &mut matches);
+        let found = covering(&matches, 0, 5);
+        assert!(found
+            .iter()
+            .any(|m| matches!(m.pattern, Pattern::Repeat { base_len: 1 })));
+
+        let mut matches = Vec::new();
+        repeat_matches(&chars("abcabcabc"), &HashMap::new(), &mut matches);
+        let found = covering(&matches, 0, 8);
+        assert!(found
+            .iter()
+            .any(|m| matches!(m.pattern, Pattern::Repeat { base_len: 3 })));
+    }
+
+    #[test]
+    fn match_sequences() {
+        for password in ["abcdef", "6543"] {
+            let mut matches = Vec::new();
+            sequence_matches(&chars(password), &mut matches);
+            let found = covering(&matches, 0, password.len() - 1);
+            assert!(found.iter().any(|m| matches!(m.pattern, Pattern::Sequence)));
+        }
+    }
+
+    #[test]
+    fn match_non_ascii_sequences() {
+        let mut matches = Vec::new();
+        sequence_matches(&chars("αβγδ"), &mut matches);
+        let found = covering(&matches, 0, 3);
+        assert!(found.iter().any(|m| matches!(m.pattern, Pattern::Sequence)));
+    }
+
+    #[test]
+    fn guess_non_ascii_characters_from_a_larger_alphabet() {
+        assert_eq!(bruteforce_guesses(3, 1), 2000.0);
+        assert_eq!(bruteforce_match(&chars("aé1"), 0, 2).guesses, 2000.0);
+        assert_eq!(bruteforce_guesses(400, 0), f64::MAX);
+    }
+
+    #[test]
+    fn match_dates() {
+        let mut matches = Vec::new();
+        date_matches(&chars("13/05/1991"), &mut matches);
+        let found = covering(&matches, 0, 9);
+        assert!(found.iter().any(|m| matches!(m.pattern, Pattern::Date)));
+    }
+
+    #[test]
+    fn current_year_is_the_reference() {
+        assert!((2024..2200).contains(&reference_year()));
+    }
+
+    #[test]
+    fn rate_common_password_low() {
+        let estimate = estimate("password", &[]);
+        assert_eq!(estimate.score, 0);
+        assert_eq!(estimate.sequence.len(), 1);
+    }
+
+    #[test]
+    fn rate_repeated_characters_low() {
+        let estimate = estimate("Aa1!Aa1!Aa1!Aa1!", &[]);
+        assert!(estimate.score <= 1);
+        assert_eq!(estimate.sequence.len(), 1);
+        assert!(matches!(
+            estimate.sequence[0].pattern,
+            Pattern::Repeat { base_len: 4 }
+        ));
+    }
+
+    #[test]
+    fn rate_passphrase_high() {
+        let estimate = estimate("correct horse battery staple", &[]);
+        assert_eq!(estimate.score, 4);
+    }
+
+    #[test]
+    fn guess_long_passwords_tail_by_bruteforce() {
+        let password = "correct horse battery staple ".repeat(10);
+        let estimate = estimate(&password, &[]);
+        let last = assert_some!(estimate.sequence.last());
+        assert!(matches!(last.pattern, Pattern::Bruteforce));
+        assert_eq!((last.i, last.j), (MAX_MATCHED_CHARS, password.len() - 1));
+        assert_eq!(estimate.score, 4);
+    }
+}
@@ -103,10 +103,38 @@ export interface SigninStartReq {
   message: string;
 }
 
+/** Key stretching function parameters */
+export interface KsfParamsRes {
+  memory: number;
+  iterations: number;
+  parallelism: number;
+}
+
+/** Protocol parameters for new registrations */
+export interface OpaqueParamsRes extends KsfParamsRes {
+  suite: string;
+  identity: string | null;
+  context: string | null;
+}
+
+/** Password policy enforced by the client */
+export interface PasswordPolicyRes {
+  score: number;
+  length: number;
+  distance: number;
+  breach: boolean;
+}
+
 /** Sign in start step response */
 export interface SigninStartRes {
   session: string;
   message: string;
+  ksf: KsfParamsRes;
+  identity: string | null;
+  context: string | null;
+  suite: string;
+  /** The legacy sign in is tried when the sign in fails */
+  legacy: boolean;
 }
 
 /** Sign in finish step request */
@@ -115,6 +143,161 @@ export interface SigninFinishReq {
   message: string;
 }
 
+/** Sign in finish step response */
+export interface SigninFinishRes {
+  reregister?: string;
+  mfa?: string;
+  methods?: string[];
+}
+
+/** Legacy sign in request, the password is verified by the server */
+export interface LegacySigninReq {
+  username: string;
+  password: string;
+  message: string;
+}
+
+/** Legacy sign in response, the registration is completed as re-registration */
+export interface LegacySigninRes {
+  session: string;
+  message: string;
+}
+
+/** Re-registration start step request */
+export interface ReregisterStartReq {
+  session: string;
+  message: string;
+}
+
+/** Re-registration start step response */
+export interface ReregisterStartRes {
+  session: string;
+  message: string;
+}
+
+/** Re-registration finish step request */
+export interface ReregisterFinishReq {
+  session: string;
+  message: string;
+}
+
+/** Re-registration finish step response */
+export interface ReregisterFinishRes {
+  mfa?: string;
+  methods?: string[];
+}
+
+/** Second factor verification request */
+export interface MfaTotpReq {
+  token: string;
+  code: string;
+}
+
+/** Credential descriptor, the identifier is base64url encoded */
+export interface WebauthnCredentialDescriptor {
+  type: "public-key";
+  id: string;
+}
+
+/** WebAuthn registration options, binary fields are base64url encoded */
+export interface WebauthnCreationOptions {
+  challenge: string;
+  rp: { id: string; name: string };
+  user: { id: string; name: string; displayName: string };
+  pubKeyCredParams: { type: "public-key"; alg: number }[];
+  timeout: number;
+  attestation: AttestationConveyancePreference;
+  excludeCredentials: WebauthnCredentialDescriptor[];
+  authenticatorSelection: AuthenticatorSelectionCriteria;
+}
+
+/** WebAuthn registration response, binary fields are base64url encoded */
+export interface WebauthnRegistrationReq {
+  id: string;
+  response: {
+    clientDataJSON: string;
+    attestationObject: string;
+  };
+}
+
+/** WebAuthn authentication options, binary fields are base64url encoded */
+export interface WebauthnRequestOptions {
+  challenge: string;
+  rpId: string;
+  allowCredentials: WebauthnCredentialDescriptor[];
+  timeout: number;
+  userVerification: UserVerificationRequirement;
+}
+
+/** WebAuthn authentication response, binary fields are base64url encoded */
+export interface WebauthnAssertion {
+  id: string;
+  response: {
+    clientDataJSON: string;
+    authenticatorData: string;
+    signature: string;
+  };
+}
+
+/** Second factor security key start request */
+export interface MfaWebauthnStartReq {
+  token: string;
+}
+
+/** Second factor security key start response */
+export interface MfaWebauthnStartRes {
+  token: string;
+  options: WebauthnRequestOptions;
+}
+
+/** Second factor security key finish request */
+export interface MfaWebauthnFinishReq {
+  token: string;
+  credential: WebauthnAssertion;
+}
+
+/** TOTP enrolment response */
+export interface TotpEnrollRes {
+  secret: string;
+  uri: string;
+}
+
+/** TOTP confirmation request */
+export interface TotpConfirmReq {
+  code: string;
+}
+
+/** Second factor enabled response, recovery codes come with the first one */
+export interface MfaEnabledRes {
+  recovery?: string[];
+}
+
+/** Second factor recovery code request */
+export interface MfaRecoveryReq {
+  token: string;
+  code: string;
+}
+
+/** Recovery codes regeneration response */
+export interface RecoveryRes {
+  recovery: string[];
+}
+
+/** Email address change request */
+export interface EmailReq {
+  email: string;
+}
+
+/** Email address confirmation request */
+export interface EmailConfirmReq {
+  token: string;
+}
+
+/** Password reset request */
+export interface ResetReq {
+  username: string;
+}
+
 /** Session information */
 export interface SessionRes {
   username: string;
@@ -0,0 +1,47 @@
+import { api, EmailConfirmReq, EmailReq, ResetReq } from "#utils/api.ts";
+
+/** Change the email address of the current user, a confirmation link is sent
+ * to the new address */
+export const emailSet = async (req: EmailReq) => {
+  const response = await api.post("/email", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 400) {
+    throw new Error("Invalid email address");
+  }
+  if (response.status === 403) {
+    throw new Error("Sign in again to change the email address");
+  }
+  if (response.status === 503) {
+    throw new Error("Emails are not available");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Save the email address confirmed with the link */
+export const emailConfirm = async (req: EmailConfirmReq) => {
+  const response = await api.post("/email/confirm", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 401) {
+    throw new Error("The link is invalid or expired");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Request the password reset link, sent to the email address of the user */
+export const passwordReset = async (req: ResetReq) => {
+  const response = await api.post("/reset", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 429) {
+    throw new Error("Too many requests, try again later");
+  }
+  throw new Error("Api server is not available");
+};
@@ -0,0 +1,80 @@
+import {
+  api,
+  MfaEnabledRes,
+  MfaRecoveryReq,
+  MfaTotpReq,
+  RecoveryRes,
+  TotpConfirmReq,
+  TotpEnrollRes,
+} from "#utils/api.ts";
+
+/** Complete the sign in with a TOTP code */
+export const signinTotp = async (req: MfaTotpReq) => {
+  const response = await api.post("/mfa/totp", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Invalid code, sign in again");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Complete the sign in with a recovery code */
+export const signinRecovery = async (req: MfaRecoveryReq) => {
+  const response = await api.post("/mfa/recovery", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Invalid recovery code, sign in again");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Replace the recovery codes of the current user */
+export const recoveryRegenerate = async () => {
+  const response = await api.post<RecoveryRes>("/recovery/regenerate", {});
+  if (response.ok) {
+    return response.data.recovery;
+  }
+
+  if (response.status === 403) {
+    throw new Error("Sign in again to regenerate the recovery codes");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Generate a new TOTP secret for the current user */
+export const totpEnroll = async () => {
+  const response = await api.post<TotpEnrollRes>("/totp/enroll", {});
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 403) {
+    throw new Error("Sign in again to enable two-factor authentication");
+  }
+  if (response.status === 409) {
+    throw new Error("Two-factor authentication is already enabled");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Enable the TOTP authenticator, verifying its first code */
+export const totpConfirm = async (req: TotpConfirmReq) => {
+  const response = await api.post<MfaEnabledRes>("/totp/confirm", req);
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Invalid code");
+  }
+  if (response.status === 403) {
+    throw new Error("Sign in again to enable two-factor authentication");
+  }
+  throw new Error("Api server is not available");
+};
@@ -1,16 +1,37 @@
 // @deno-types="../wasm/fresh_auth_frontend.d.ts"
 import {
+  BreachFilter,
   checkCredentialsStrength,
+  cipherSuite,
+  KsfParams,
   OpaqueLogin,
   OpaqueRegistration,
+  PasswordPolicy,
 } from "../wasm/fresh_auth_frontend.js";
 import {
   api,
+  KsfParamsRes,
+  LegacySigninReq,
+  LegacySigninRes,
+  OpaqueParamsRes,
+  PasswordPolicyRes,
+  ReregisterFinishReq,
+  ReregisterFinishRes,
+  ReregisterStartReq,
+  ReregisterStartRes,
   SigninFinishReq,
+  SigninFinishRes,
   SigninStartReq,
   SigninStartRes,
 } from "#utils/api.ts";
 
+/** Error shown when the server rejects the messages of the client */
+const incompatibleProtocol =
+  "Protocol of the server is not supported by the client, reload the page";
+
+/** Error of the login with a wrong password, raised by `OpaqueLogin` */
+const invalidCredentials = "Invalid username or password";
+
 /** Sign up arguments */
 export interface SignupArgs {
   code: string;
@@ -20,7 +41,10 @@ export interface SignupArgs {
 
 /** Send the requests for sign up process */
 export const signup = async ({ code, username, password }: SignupArgs) => {
-  checkCredentialsStrength(username, password);
+  const policy = await passwordPolicyGet();
+  checkPasswordStrength(username, password, policy);
+  await checkBreachedPassword(password, policy);
+  const { ksfParams, identity } = await registrationParamsGet();
   const opaqueRegistration = OpaqueRegistration.start(password);
   const { session, message: startMessage } = await signupStart({
     code,
@@ -29,10 +53,106 @@ export const signup = async ({ code, username, password }: SignupArgs) => {
   const { message: finishMessage } = opaqueRegistration.finish(
     password,
     startMessage,
+    ksfParams,
+    identity ?? undefined,
   );
   await signupFinish({ session, message: finishMessage });
 };
 
+/** Check the strength of the password, the feedback is used as error */
+const checkPasswordStrength = (
+  username: string,
+  password: string,
+  { score, length, distance }: PasswordPolicyRes,
+) => {
+  const policy = new PasswordPolicy(score, length, distance);
+  const strength = checkCredentialsStrength(username, password, policy);
+  policy.free();
+  const { accepted, warning, suggestions, crackTime } = strength;
+  strength.free();
+  if (!accepted) {
+    const feedback = [warning ?? "Too weak password!"]
+      .concat(suggestions)
+      .join(". ");
+    throw new Error(`${feedback} (could be cracked in ${crackTime})`);
+  }
+};
+
+let breachFilter: Promise<BreachFilter | undefined> | undefined;
+
+/** Retrieve the breached password filter, if available on the server */
+const breachFilterGet = async () => {
+  const response = await fetch("/api/breach/filter");
+  if (response.ok) {
+    const bytes = new Uint8Array(await response.arrayBuffer());
+    return new BreachFilter(bytes);
+  }
+  if (response.status === 404) {
+    return undefined;
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Check if the password appears in the breached password list */
+const isBreachedPassword = async (
+  password: string,
+  policy: PasswordPolicyRes,
+) => {
+  breachFilter ??= breachFilterGet().catch((err) => {
+    breachFilter = undefined;
+    throw err;
+  });
+  const filter = await breachFilter;
+  if (filter === undefined && policy.breach) {
+    throw new Error("Breached password filter is not available");
+  }
+  return filter?.isBreached(password) ?? false;
+};
+
+/** Check that the password does not appear in the breached password list */
+const checkBreachedPassword = async (
+  password: string,
+  policy: PasswordPolicyRes,
+) => {
+  if (await isBreachedPassword(password, policy)) {
+    throw new Error("Password appears in a list of breached passwords");
+  }
+};
+
+/** Retrieve the password policy */
+const passwordPolicyGet = async () => {
+  const response = await api.get<PasswordPolicyRes>("/password/policy");
+  if (response.ok) {
+    return response.data;
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Retrieve the protocol parameters for new registrations */
+const registrationParamsGet = async () => {
+  const response = await api.get<OpaqueParamsRes>("/opaque/params");
+  if (response.ok) {
+    checkCipherSuite(response.data.suite);
+    return {
+      ksfParams: toKsfParams(response.data),
+      identity: response.data.identity,
+    };
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Check that the server runs the same cipher suite of the client */
+const checkCipherSuite = (suite: string) => {
+  if (suite !== cipherSuite()) {
+    throw new Error(
+      `Cipher suite ${suite} of the server is not supported by the client`,
+    );
+  }
+};
+
+const toKsfParams = ({ memory, iterations, parallelism }: KsfParamsRes) =>
+  new KsfParams(memory, iterations, parallelism);
+
 /** Sign up start step request */
 interface SignupStartReq {
   code: string;
@@ -58,6 +178,9 @@ const signupStart = async (req: SignupStartReq) => {
   if (response.status === 401) {
     throw new Error("Invalid credentials");
   }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
   throw new Error("Api server is not available");
 };
 
@@ -81,6 +204,9 @@ const signupFinish = async (req: SignupFinishReq) => {
   if (response.status === 401) {
     throw new Error("Invalid credentials");
   }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
   throw new Error("Api server is not available");
 };
 
@@ -88,17 +214,61 @@ const signupFinish = async (req: SignupFinishReq) => {
 export interface SigninArgs {
   username: string;
   password: string;
+  /** Pinned public keys of the server, any key is accepted when missing */
+  serverKeys?: string[];
+}
+
+/** Sign in result, the second factor token is returned if required */
+export interface SigninResult {
+  mfa?: string;
+  methods?: string[];
+  /** The password appears in the breached password list, it should be changed */
+  breached?: boolean;
 }
 
 /** Send the requests for sign in process */
-export const signin = async ({ username, password }: SigninArgs) => {
+export const signin = async (
+  { username, password, serverKeys }: SigninArgs,
+): Promise<SigninResult> => {
   const opaqueLogin = OpaqueLogin.start(password);
-  const { session, message: startMessage } = await signinStart({
+  serverKeys?.forEach((key) => opaqueLogin.pin(key));
+  const {
+    session,
+    message: startMessage,
+    ksf,
+    identity,
+    context,
+    suite,
+    legacy,
+  } = await signinStart({
     username,
     message: opaqueLogin.message,
   });
-  const { message: finishMessage } = opaqueLogin.finish(password, startMessage);
-  await signinFinish({ session, message: finishMessage });
+  checkCipherSuite(suite);
+  let finishMessage: string;
+  try {
+    ({ message: finishMessage } = opaqueLogin.finish(
+      password,
+      startMessage,
+      toKsfParams(ksf),
+      identity ?? undefined,
+      context ?? undefined,
+    ));
+  } catch (err) {
+    // the users with a legacy password hash fail like the unknown ones
+    if (legacy && err instanceof Error && err.message === invalidCredentials) {
+      return await legacySignin(username, password);
+    }
+    throw err;
+  }
+  const { reregister, mfa, methods } = await signinFinish({
+    session,
+    message: finishMessage,
+  });
+  if (reregister) {
+    return await reregisterPassword(reregister, password);
+  }
+  return { mfa, methods };
 };
 
 const signinStart = async (req: SigninStartReq) => {
@@ -110,17 +280,145 @@ const signinStart = async (req: SigninStartReq) => {
   if (response.status === 401) {
     throw new Error("Invalid username or password");
   }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
   throw new Error("Api server is not available");
 };
 
 const signinFinish = async (req: SigninFinishReq) => {
-  const response = await api.post("/signin/finish", req);
+  const response = await api.post<SigninFinishRes | null>(
+    "/signin/finish",
+    req,
+  );
   if (response.ok) {
-    return;
+    return response.data ?? {};
   }
 
   if (response.status === 401) {
     throw new Error("Invalid username or password");
   }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
+  throw new Error("Api server is not available");
+};
+
+/**
+ * Register again the password, the server setup used by the user is retired.
+ *
+ * The user is not locked out if the password has been breached, the password
+ * is migrated and flagged to be changed.
+ */
+const reregisterPassword = async (
+  session: string,
+  password: string,
+): Promise<SigninResult> => {
+  const breached = await isBreachedPassword(
+    password,
+    await passwordPolicyGet(),
+  ).catch(() => false);
+  const { ksfParams, identity } = await registrationParamsGet();
+  const opaqueRegistration = OpaqueRegistration.start(password);
+  const { session: finishSession, message: startMessage } =
+    await reregisterStart({
+      session,
+      message: opaqueRegistration.message,
+    });
+  const { message: finishMessage } = opaqueRegistration.finish(
+    password,
+    startMessage,
+    ksfParams,
+    identity ?? undefined,
+  );
+  const result = await reregisterFinish({
+    session: finishSession,
+    message: finishMessage,
+  });
+  return { ...result, breached };
+};
+
+/**
+ * Sign in with the legacy password hash, registering the password.
+ *
+ * As for the re-registration, the user is not locked out if the password has
+ * been breached, the password is migrated and flagged to be changed.
+ */
+const legacySignin = async (
+  username: string,
+  password: string,
+): Promise<SigninResult> => {
+  const breached = await isBreachedPassword(
+    password,
+    await passwordPolicyGet(),
+  ).catch(() => false);
+  const { ksfParams, identity } = await registrationParamsGet();
+  const opaqueRegistration = OpaqueRegistration.start(password);
+  const { session, message: startMessage } = await legacySigninStart({
+    username,
+    password,
+    message: opaqueRegistration.message,
+  });
+  const { message: finishMessage } = opaqueRegistration.finish(
+    password,
+    startMessage,
+    ksfParams,
+    identity ?? undefined,
+  );
+  const result = await reregisterFinish({ session, message: finishMessage });
+  return { ...result, breached };
+};
+
+const legacySigninStart = async (req: LegacySigninReq) => {
+  const response = await api.post<LegacySigninRes>("/signin/legacy", req);
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 401) {
+    throw new Error(invalidCredentials);
+  }
+  if (response.status === 429) {
+    throw new Error("Too many requests, try again later");
+  }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
+  throw new Error("Api server is not available");
+};
+
+const reregisterStart = async (req: ReregisterStartReq) => {
+  const response = await api.post<ReregisterStartRes>(
+    "/reregister/start",
+    req,
+  );
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Sign in session is expired");
+  }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
+  throw new Error("Api server is not available");
+};
+
+const reregisterFinish = async (req: ReregisterFinishReq) => {
+  const response = await api.post<ReregisterFinishRes | null>(
+    "/reregister/finish",
+    req,
+  );
+  if (response.ok) {
+    return response.data ?? {};
+  }
+
+  if (response.status === 401) {
+    throw new Error("Sign in session is expired");
+  }
+  if (response.status === 400) {
+    throw new Error(incompatibleProtocol);
+  }
   throw new Error("Api server is not available");
 };
@@ -0,0 +1,150 @@
+import {
+  api,
+  MfaEnabledRes,
+  MfaWebauthnFinishReq,
+  MfaWebauthnStartRes,
+  WebauthnCreationOptions,
+  WebauthnCredentialDescriptor,
+  WebauthnRegistrationReq,
+} from "#utils/api.ts";
+
+/** Decode a base64url string */
+const decode = (value: string): ArrayBuffer => {
+  const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+  const binary = atob(base64 + "=".repeat((4 - base64.length % 4) % 4));
+  return Uint8Array.from(binary, (c) => c.charCodeAt(0)).buffer;
+};
+
+/** Encode a buffer as base64url string, without padding */
+const encode = (value: ArrayBuffer): string => {
+  const binary = String.fromCharCode(...new Uint8Array(value));
+  const base64 = btoa(binary).replace(/=+$/, "");
+  return base64.replace(/\+/g, "-").replace(/\//g, "_");
+};
+
+const toDescriptors = (
+  credentials: WebauthnCredentialDescriptor[],
+): PublicKeyCredentialDescriptor[] =>
+  credentials.map(({ type, id }) => ({ type, id: decode(id) }));
+
+/** Register a new security key for the current user */
+export const webauthnRegister = async () => {
+  const options = await registerStart();
+  const credential = await navigator.credentials.create({
+    publicKey: {
+      ...options,
+      challenge: decode(options.challenge),
+      user: { ...options.user, id: decode(options.user.id) },
+      excludeCredentials: toDescriptors(options.excludeCredentials),
+    },
+  }) as PublicKeyCredential | null;
+  if (credential === null) {
+    throw new Error("Security key registration was cancelled");
+  }
+
+  const response = credential.response as AuthenticatorAttestationResponse;
+  return await registerFinish({
+    id: credential.id,
+    response: {
+      clientDataJSON: encode(response.clientDataJSON),
+      attestationObject: encode(response.attestationObject),
+    },
+  });
+};
+
+const registerStart = async () => {
+  const response = await api.post<WebauthnCreationOptions>(
+    "/webauthn/register/start",
+    {},
+  );
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 403) {
+    throw new Error("Sign in again to add a security key");
+  }
+  if (response.status === 404) {
+    throw new Error("Security keys are not enabled");
+  }
+  throw new Error("Api server is not available");
+};
+
+const registerFinish = async (req: WebauthnRegistrationReq) => {
+  const response = await api.post<MfaEnabledRes>(
+    "/webauthn/register/finish",
+    req,
+  );
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Invalid security key");
+  }
+  if (response.status === 403) {
+    throw new Error("Sign in again to add a security key");
+  }
+  if (response.status === 404) {
+    throw new Error("Registration is expired, try again");
+  }
+  if (response.status === 409) {
+    throw new Error("Security key is already registered");
+  }
+  throw new Error("Api server is not available");
+};
+
+/** Complete the sign in with a security key */
+export const signinWebauthn = async (token: string) => {
+  const { token: finishToken, options } = await mfaStart(token);
+  const credential = await navigator.credentials.get({
+    publicKey: {
+      ...options,
+      challenge: decode(options.challenge),
+      allowCredentials: toDescriptors(options.allowCredentials),
+    },
+  }) as PublicKeyCredential | null;
+  if (credential === null) {
+    throw new Error("Security key authentication was cancelled");
+  }
+
+  const response = credential.response as AuthenticatorAssertionResponse;
+  await mfaFinish({
+    token: finishToken,
+    credential: {
+      id: credential.id,
+      response: {
+        clientDataJSON: encode(response.clientDataJSON),
+        authenticatorData: encode(response.authenticatorData),
+        signature: encode(response.signature),
+      },
+    },
+  });
+};
+
+const mfaStart = async (token: string) => {
+  const response = await api.post<MfaWebauthnStartRes>(
+    "/mfa/webauthn/start",
+    { token },
+  );
+  if (response.ok) {
+    return response.data;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Sign in session is expired");
+  }
+  throw new Error("Api server is not available");
+};
+
+const mfaFinish = async (req: MfaWebauthnFinishReq) => {
+  const response = await api.post("/mfa/webauthn/finish", req);
+  if (response.ok) {
+    return;
+  }
+
+  if (response.status === 401) {
+    throw new Error("Invalid security key, sign in again");
+  }
+  throw new Error("Api server is not available");
+};
@@ -0,0 +1,185 @@
+//! Breached password filter
+//!
+//! The filter is a bloom filter built by the server from a list of breached
+//! passwords, it is served to the client which checks the password before the
+//! registration (the server never sees the password).
+//!
+//! The binary format is composed by a header of 16 bytes, the magic `BPF1`,
+//! the number of hash functions (1 byte), 3 reserved bytes and the number of
+//! bits (u64 little endian), followed by the bits. The bit indices of a
+//! password are computed by double hashing from the SHA-256 digest of its
+//! normalized form, the same used for the registration.
+
+use std::{borrow::Cow, fmt};
+
+use sha2::{Digest, Sha256};
+
+use crate::password;
+
+/// Magic bytes of the filter format.
+const MAGIC: &[u8; 4] = b"BPF1";
+
+/// Length in bytes of the header.
+const HEADER_BYTES: usize = 16;
+
+/// Errors of a filter that cannot be built or loaded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BreachFilterError {
+    /// The false positive rate is not between 0 and 1.
+    FalsePositiveRate,
+    /// The bytes are not a breached password filter.
+    Malformed,
+}
+
+impl fmt::Display for BreachFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreachFilterError::FalsePositiveRate => {
+                f.write_str("false positive rate must be between 0 and 1")
+            }
+            BreachFilterError::Malformed => f.write_str("invalid breached password filter"),
+        }
+    }
+}
+
+impl std::error::Error for BreachFilterError {}
+
+/// Bloom filter of breached passwords.
+pub struct BreachFilter {
+    hashes: u8,
+    bits: Vec<u8>,
+}
+
+impl BreachFilter {
+    /// Create an empty filter sized for the given number of passwords and
+    /// false positive rate, the rate must be between 0 and 1 (excluded).
+    pub fn with_capacity(
+        capacity: usize,
+        false_positive_rate: f64,
+    ) -> Result<Self, BreachFilterError> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(BreachFilterError::FalsePositiveRate);
+        }
+
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let bytes = (bits / 8.0).ceil().max(1.0) as usize;
+        let hashes = ((bytes * 8) as f64 / capacity * ln2)
+            .round()
+            .clamp(1.0, 32.0) as u8;
+
+        Ok(Self {
+            hashes,
+            bits: vec![0; bytes],
+        })
+    }
+
+    /// Read the filter from the binary format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BreachFilterError> {
+        if bytes.len() < HEADER_BYTES || bytes[..4] != MAGIC[..] {
+            return Err(BreachFilterError::Malformed);
+        }
+        let hashes = bytes[4];
+        let mut len = [0_u8; 8];
+        len.copy_from_slice(&bytes[8..HEADER_BYTES]);
+        let len = u64::from_le_bytes(len);
+        let bits = bytes[HEADER_BYTES..].to_vec();
+        if hashes == 0 || len != (bits.len() * 8) as u64 {
+            return Err(BreachFilterError::Malformed);
+        }
+        Ok(Self { hashes, bits })
+    }
+
+    /// Returns the filter in the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_BYTES + self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.hashes);
+        bytes.extend_from_slice(&[0; 3]);
+        bytes.extend_from_slice(&self.len().to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Add the password to the filter.
+    pub fn insert(&mut self, password: &str) {
+        for index in self.indices(password) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Check if the password could be in the filter.
+    pub fn contains(&self, password: &str) -> bool {
+        self.indices(password)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Number of bits of the filter.
+    fn len(&self) -> u64 {
+        (self.bits.len() * 8) as u64
+    }
+
+    /// Bit indices of the password, the passwords refused by the
+    /// normalization are hashed as they are.
+    fn indices(&self, password: &str) -> impl Iterator<Item = usize> {
+        let password = password::normalize(password)
+            .map(Cow::Owned)
+            .unwrap_or(Cow::Borrowed(password));
+        let digest = Sha256::digest(password.as_bytes());
+        let mut h1 = [0_u8; 8];
+        h1.copy_from_slice(&digest[..8]);
+        let h1 = u64::from_le_bytes(h1);
+        let mut h2 = [0_u8; 8];
+        h2.copy_from_slice(&digest[8..16]);
+        let h2 = u64::from_le_bytes(h2) | 1;
+        let len = self.len();
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn breached_passwords_are_found() {
+        let mut filter = assert_ok!(BreachFilter::with_capacity(3, 0.001));
+        filter.insert("123456");
+        filter.insert("password");
+        filter.insert("qwerty");
+
+        let filter = assert_ok!(BreachFilter::from_bytes(&filter.to_bytes()));
+        assert!(filter.contains("123456"));
+        assert!(filter.contains("password"));
+        assert!(filter.contains("qwerty"));
+        assert!(!filter.contains("correct horse battery staple"));
+    }
+
+    #[test]
+    fn normalize_breached_passwords() {
+        let mut filter = assert_ok!(BreachFilter::with_capacity(2, 0.001));
+        filter.insert("cafe\u{0301}");
+        filter.insert("correct\u{3000}horse");
+
+        assert!(filter.contains("caf\u{00E9}"));
+        assert!(filter.contains("correct horse"));
+    }
+
+    #[test]
+    fn reject_invalid_false_positive_rate() {
+        for rate in [0.0, 1.0, -0.5, 2.0, f64::NAN] {
+            let err = assert_err!(BreachFilter::with_capacity(3, rate).map(|_| ()));
+            assert_eq!(err, BreachFilterError::FalsePositiveRate);
+        }
+    }
+
+    #[test]
+    fn reject_malformed_filter() {
+        let err = assert_err!(BreachFilter::from_bytes(b"BPF1").map(|_| ()));
+        assert_eq!(err, BreachFilterError::Malformed);
+    }
+}
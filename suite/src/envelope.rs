@@ -0,0 +1,180 @@
+//! Versioned envelope of the OPAQUE messages
+//!
+//! Each message is sent as `v<version>.<suite>.<type>.<payload>`, where the
+//! payload is the base64url encoded serialized message. The receiver checks
+//! the version, the suite and the type before the deserialization, so that
+//! incompatible peers fail with a clear error.
+
+use std::fmt;
+
+use base64ct::{Base64Url, Encoding};
+
+use crate::SUITE;
+
+/// Version of the protocol, it changes when the messages are no longer
+/// compatible.
+pub const VERSION: u32 = 1;
+
+/// Type of the message in the envelope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// First message of the registration, sent by the client.
+    RegistrationRequest,
+    /// Response to the registration request, sent by the server.
+    RegistrationResponse,
+    /// Last message of the registration, sent by the client.
+    RegistrationUpload,
+    /// First message of the login, sent by the client.
+    CredentialRequest,
+    /// Response to the credential request, sent by the server.
+    CredentialResponse,
+    /// Last message of the login, sent by the client.
+    CredentialFinalization,
+}
+
+impl MessageType {
+    const ALL: [MessageType; 6] = [
+        MessageType::RegistrationRequest,
+        MessageType::RegistrationResponse,
+        MessageType::RegistrationUpload,
+        MessageType::CredentialRequest,
+        MessageType::CredentialResponse,
+        MessageType::CredentialFinalization,
+    ];
+
+    /// Returns the tag of the message type.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageType::RegistrationRequest => "registration-request",
+            MessageType::RegistrationResponse => "registration-response",
+            MessageType::RegistrationUpload => "registration-upload",
+            MessageType::CredentialRequest => "credential-request",
+            MessageType::CredentialResponse => "credential-response",
+            MessageType::CredentialFinalization => "credential-finalization",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == tag)
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Errors of an envelope that cannot be opened.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// The message is not wrapped in a versioned envelope.
+    Malformed,
+    /// The version of the protocol is not supported.
+    Version(u32),
+    /// The cipher suite is not the selected one.
+    Suite(String),
+    /// The message is not of the expected type.
+    Type {
+        expected: MessageType,
+        found: String,
+    },
+    /// The payload is not base64url encoded.
+    Encoding,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Malformed => {
+                f.write_str("message is not a versioned envelope, the client must be updated")
+            }
+            EnvelopeError::Version(version) => {
+                write!(f, "unsupported protocol version {version}, expected {VERSION}")
+            }
+            EnvelopeError::Suite(suite) => {
+                write!(f, "unsupported cipher suite {suite}, expected {SUITE}")
+            }
+            EnvelopeError::Type { expected, found } => {
+                write!(f, "unexpected message type {found}, expected {expected}")
+            }
+            EnvelopeError::Encoding => f.write_str("message payload is not base64url encoded"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// Wrap the serialized message in the envelope.
+pub fn seal(kind: MessageType, message: &[u8]) -> String {
+    let payload = Base64Url::encode_string(message);
+    format!("v{VERSION}.{SUITE}.{kind}.{payload}")
+}
+
+/// Open the envelope, returns the serialized message if the version, the
+/// suite and the type are the expected ones.
+pub fn open(kind: MessageType, envelope: &str) -> Result<Vec<u8>, EnvelopeError> {
+    let mut parts = envelope.splitn(4, '.');
+    let (Some(version), Some(suite), Some(found), Some(payload)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(EnvelopeError::Malformed);
+    };
+
+    let version = version
+        .strip_prefix('v')
+        .and_then(|version| version.parse::<u32>().ok())
+        .ok_or(EnvelopeError::Malformed)?;
+    if version != VERSION {
+        return Err(EnvelopeError::Version(version));
+    }
+    if suite != SUITE {
+        return Err(EnvelopeError::Suite(suite.to_string()));
+    }
+    if MessageType::from_tag(found) != Some(kind) {
+        return Err(EnvelopeError::Type {
+            expected: kind,
+            found: found.to_string(),
+        });
+    }
+    Base64Url::decode_vec(payload).map_err(|_| EnvelopeError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn seal_and_open() {
+        let envelope = seal(MessageType::CredentialRequest, b"message");
+        assert!(envelope.starts_with(&format!("v1.{SUITE}.credential-request.")));
+        let message = assert_ok!(open(MessageType::CredentialRequest, &envelope));
+        assert_eq!(message, b"message");
+    }
+
+    #[test]
+    fn reject_mismatches() {
+        let envelope = seal(MessageType::CredentialRequest, b"message");
+        let err = assert_err!(open(MessageType::RegistrationRequest, &envelope));
+        assert_eq!(
+            err,
+            EnvelopeError::Type {
+                expected: MessageType::RegistrationRequest,
+                found: "credential-request".to_string(),
+            }
+        );
+
+        let envelope = envelope.replacen("v1.", "v2.", 1);
+        let err = assert_err!(open(MessageType::CredentialRequest, &envelope));
+        assert_eq!(err, EnvelopeError::Version(2));
+
+        let envelope = "v1.other-suite.credential-request.bWVzc2FnZQ==";
+        let err = assert_err!(open(MessageType::CredentialRequest, envelope));
+        assert_eq!(err, EnvelopeError::Suite("other-suite".to_string()));
+
+        let err = assert_err!(open(MessageType::CredentialRequest, "bWVzc2FnZQ=="));
+        assert_eq!(err, EnvelopeError::Malformed);
+    }
+}
@@ -0,0 +1,108 @@
+//! OPAQUE cipher suite
+//!
+//! The suite is shared by the server and the client, so both are guaranteed
+//! to run the same protocol. The group is selected at compile time with one
+//! of the features `ristretto255` (default), `p256` or `p384`; the key
+//! exchange is always TripleDH and the key stretching function Argon2id.
+//!
+//! When several features are enabled, as with `--all-features` or because
+//! of feature unification, the strongest group wins: `p384`, then `p256`,
+//! then `ristretto255`. The server and the client must be built with the
+//! same features, the client checks the advertised [`SUITE`].
+//!
+//! Changing the suite invalidates the server setup and every registered
+//! password file, as does changing the normalization of the passwords.
+
+pub mod breach;
+pub mod envelope;
+pub mod password;
+
+#[cfg(not(any(feature = "ristretto255", feature = "p256", feature = "p384")))]
+compile_error!("one of the features `ristretto255`, `p256` and `p384` must be enabled");
+
+#[cfg(all(feature = "ristretto255", not(any(feature = "p256", feature = "p384"))))]
+type Group = opaque_ke::Ristretto255;
+
+#[cfg(all(feature = "p256", not(feature = "p384")))]
+type Group = p256::NistP256;
+
+#[cfg(feature = "p384")]
+type Group = p384::NistP384;
+
+/// Identifier of the selected suite, advertised by the server and checked by
+/// the client.
+#[cfg(all(feature = "ristretto255", not(any(feature = "p256", feature = "p384"))))]
+pub const SUITE: &str = "ristretto255-tripledh-argon2id";
+
+/// Identifier of the selected suite, advertised by the server and checked by
+/// the client.
+#[cfg(all(feature = "p256", not(feature = "p384")))]
+pub const SUITE: &str = "p256-tripledh-argon2id";
+
+/// Identifier of the selected suite, advertised by the server and checked by
+/// the client.
+#[cfg(feature = "p384")]
+pub const SUITE: &str = "p384-tripledh-argon2id";
+
+/// Cipher suite definition
+pub struct CipherSuite;
+
+impl opaque_ke::CipherSuite for CipherSuite {
+    type OprfCs = Group;
+    type KeGroup = Group;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
+    type Ksf = argon2::Argon2<'static>;
+}
+
+#[cfg(test)]
+mod tests {
+    use opaque_ke::{
+        ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+        ClientRegistrationFinishParameters, ServerLogin, ServerLoginStartParameters,
+        ServerRegistration, ServerSetup,
+    };
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn register_and_login() {
+        let mut rng = OsRng;
+        let password = b"correct horse battery staple";
+        let username = b"xyz";
+        let server_setup = ServerSetup::<CipherSuite>::new(&mut rng);
+
+        let client_start = assert_ok!(ClientRegistration::<CipherSuite>::start(&mut rng, password));
+        let server_start = assert_ok!(ServerRegistration::start(
+            &server_setup,
+            client_start.message,
+            username
+        ));
+        let client_finish = assert_ok!(client_start.state.finish(
+            &mut rng,
+            password,
+            server_start.message,
+            ClientRegistrationFinishParameters::default(),
+        ));
+        let password_file = ServerRegistration::finish(client_finish.message);
+
+        let client_start = assert_ok!(ClientLogin::<CipherSuite>::start(&mut rng, password));
+        let server_start = assert_ok!(ServerLogin::start(
+            &mut rng,
+            &server_setup,
+            Some(password_file),
+            client_start.message,
+            username,
+            ServerLoginStartParameters::default(),
+        ));
+        let client_finish = assert_ok!(client_start.state.finish(
+            password,
+            server_start.message,
+            ClientLoginFinishParameters::default(),
+        ));
+        let server_finish = assert_ok!(server_start.state.finish(client_finish.message));
+        assert_eq!(client_finish.session_key, server_finish.session_key);
+    }
+}
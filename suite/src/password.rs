@@ -0,0 +1,72 @@
+//! Password normalization
+//!
+//! The client derives the OPAQUE messages from the normalized password, every
+//! client must apply the same normalization or the users could not sign in.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Spaces mapped to the ASCII space, the `Zs` category without U+0020.
+const NON_ASCII_SPACES: &[char] = &[
+    '\u{00A0}', '\u{1680}', '\u{2000}', '\u{2001}', '\u{2002}', '\u{2003}', '\u{2004}', '\u{2005}',
+    '\u{2006}', '\u{2007}', '\u{2008}', '\u{2009}', '\u{200A}', '\u{202F}', '\u{205F}', '\u{3000}',
+];
+
+/// Normalize the password using the PRECIS OpaqueString profile (RFC 8265).
+///
+/// The non-ASCII spaces are mapped to the ASCII space and the password is
+/// normalized to NFC, control characters other than the tab are rejected.
+/// ASCII passwords are left untouched, so the normalization does not affect
+/// the registrations made before it.
+pub fn normalize(password: &str) -> Result<String, &'static str> {
+    if password.is_empty() {
+        return Err("Password is empty");
+    }
+    if password.is_ascii() {
+        return Ok(password.to_string());
+    }
+
+    let password: String = password
+        .chars()
+        .map(|c| {
+            if NON_ASCII_SPACES.contains(&c) {
+                ' '
+            } else {
+                c
+            }
+        })
+        .nfc()
+        .collect();
+    if password.chars().any(|c| c.is_control() && c != '\t') {
+        return Err("Password contains control characters");
+    }
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claym::*;
+
+    #[test]
+    fn normalize_spaces_and_compositions() {
+        assert_eq!(assert_ok!(normalize("correct horse")), "correct horse");
+        assert_eq!(
+            assert_ok!(normalize("correct\u{3000}horse")),
+            "correct horse"
+        );
+        assert_eq!(assert_ok!(normalize("cafe\u{0301}")), "caf\u{00E9}");
+        assert_err!(normalize(""));
+        assert_err!(normalize("caf\u{00E9}\u{0085}"));
+    }
+
+    #[test]
+    fn keep_ascii_passwords_and_tabs() {
+        assert_eq!(assert_ok!(normalize("correct\thorse")), "correct\thorse");
+        assert_eq!(assert_ok!(normalize("correct\nhorse")), "correct\nhorse");
+        assert_eq!(
+            assert_ok!(normalize("caf\u{00E9}\thorse")),
+            "caf\u{00E9}\thorse"
+        );
+    }
+}